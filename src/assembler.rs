@@ -1,22 +1,191 @@
 pub mod assembler {
+    use crate::datum::datum::{exp_to_object, object_to_exp};
+    use crate::diagnostics::diagnostics::{check_controller, Diagnostic};
+    use crate::infrastructure::stack::Stack;
     use crate::machine::basic_machine::BasicMachine;
     use crate::memory::memory::Memory;
-    use crate::parserfordev::parser::{exp_to_str, str_to_exp};
-    use crate::primitives::primitives::{cadr, cddr, is_tagged_list};
+    use crate::parser::parser::inner_element_spans;
+    use crate::parserfordev::parser::{display_exp, exp_to_str, str_to_exp};
+    use crate::primitives::primitives::{caddr, cadr, cddr, is_tagged_list};
     use crate::representation::type_system::Object;
     use crate::scheme_list;
     use crate::tpfordev::type_system::{
         append, car, cdr, scheme_cons, scheme_for_each, set_cdr, Exp, Pair,
     };
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::fmt;
+
+    // SICP exercise 5.12, what a controller does as seen from its text. Everything
+    // is kept sorted such that it can be printed as it is.
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct ControllerInfo {
+        // instruction type such as assign to the distinct instructions of the type
+        pub instructions: BTreeMap<String, BTreeSet<String>>,
+        // registers that hold entry points, that is, the ones used by goto
+        pub entry_points: BTreeSet<String>,
+        // registers that are saved or restored
+        pub stack_registers: BTreeSet<String>,
+        // register to the sources it is assigned from, e.g. (op -) (reg n) (const 1)
+        pub sources: BTreeMap<String, BTreeSet<String>>,
+    }
+
+    impl ControllerInfo {
+        #[allow(dead_code)]
+        pub fn new(insts: &[Exp]) -> Self {
+            let mut info = ControllerInfo::default();
+            for inst in insts {
+                let kind = display_exp(&car(inst).unwrap());
+                info.instructions
+                    .entry(kind.clone())
+                    .or_default()
+                    .insert(display_exp(inst));
+                match kind.as_str() {
+                    "goto" => {
+                        let dest = goto_dest(inst);
+                        if is_register_exp(&dest) {
+                            info.entry_points
+                                .insert(display_exp(&register_exp_reg(&dest)));
+                        }
+                    }
+                    "save" | "restore" | "save-from-to" | "restore-to-from" => {
+                        info.stack_registers
+                            .insert(display_exp(&stack_inst_reg_name(inst)));
+                    }
+                    "assign" => {
+                        let mut source = vec![];
+                        let mut rest = assign_value_exp(inst);
+                        while rest.is_pair() {
+                            source.push(display_exp(&car(&rest).unwrap()));
+                            rest = cdr(&rest).unwrap();
+                        }
+                        info.sources
+                            .entry(display_exp(&assign_reg_name(inst)))
+                            .or_default()
+                            .insert(source.join(" "));
+                    }
+                    _ => {}
+                }
+            }
+            info
+        }
+
+        // the registers that occur anywhere in the controller
+        #[allow(dead_code)]
+        pub fn registers(&self) -> BTreeSet<String> {
+            let mut registers = self.entry_points.clone();
+            registers.extend(self.stack_registers.iter().cloned());
+            registers.extend(self.sources.keys().cloned());
+            registers
+        }
+    }
+
+    impl fmt::Display for ControllerInfo {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(f, "instructions:")?;
+            for (kind, insts) in self.instructions.iter() {
+                writeln!(f, "  {}", kind)?;
+                for inst in insts {
+                    writeln!(f, "    {}", inst)?;
+                }
+            }
+            let entry_points: Vec<&str> = self.entry_points.iter().map(|x| x.as_str()).collect();
+            writeln!(f, "entry points: {}", entry_points.join(" "))?;
+            let saved: Vec<&str> = self.stack_registers.iter().map(|x| x.as_str()).collect();
+            writeln!(f, "saved and restored: {}", saved.join(" "))?;
+            writeln!(f, "sources:")?;
+            for (register, sources) in self.sources.iter() {
+                let sources: Vec<&str> = sources.iter().map(|x| x.as_str()).collect();
+                writeln!(f, "  {}: {}", register, sources.join(", "))?;
+            }
+            Ok(())
+        }
+    }
+
+    // the controller is checked before anything is installed, a controller with
+    // errors leaves the machine as it was. The instructions are added after the
+    // ones the machine has already, which they may refer to by their labels, such
+    // as compiled code calling the evaluator, see SICP exercise 5.48.
     #[allow(dead_code)]
-    pub fn assemble(controller_text: String, machine: &mut BasicMachine, memory: &mut Memory) {
+    pub fn assemble(
+        controller_text: String,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<(), Vec<Diagnostic>> {
+        let text = str_to_exp(controller_text.clone());
+        let spans = inner_element_spans(&controller_text);
+        let diagnostics = check_controller(&text, &spans, machine);
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
         let insts = extract_labels_alternative(controller_text, machine);
         machine.install_raw_instructions(&insts);
+        // the spans of the labels are dropped
+        let mut rest = text;
+        let mut i = 0;
+        while rest.is_pair() {
+            if !car(&rest).unwrap().is_symbol() {
+                if let Some(span) = spans.get(i) {
+                    machine.spans.push(*span);
+                }
+            }
+            rest = cdr(&rest).unwrap();
+            i += 1;
+        }
+        machine.info = ControllerInfo::new(&machine.raw_instructions);
+        allocate_registers(machine);
         let set_instruction_execution_proc = |inst| {
             let proc = make_execution_procedure(inst, machine, memory);
             machine.instruction_sequence.push(Some(proc));
         };
         update_inst(&insts, set_instruction_execution_proc);
+        Ok(())
+    }
+
+    // SICP exercise 5.13, every register the controller refers to is allocated
+    // before the execution procedures are made, such that a register that doesn't
+    // exist can't show up while running
+    pub fn allocate_registers(machine: &mut BasicMachine) {
+        for name in referenced_registers(&machine.raw_instructions) {
+            if machine.get_register(&name).is_none() {
+                machine.allocate_register(&name);
+            }
+        }
+    }
+
+    // the names of the registers the instructions refer to, labels are skipped
+    pub fn referenced_registers(insts: &[Exp]) -> BTreeSet<String> {
+        let mut names = BTreeSet::new();
+        for inst in insts.iter() {
+            collect_registers(inst, inst, &mut names);
+        }
+        names
+    }
+
+    // the registers set by assign and restore, saved by save and read by any
+    // (reg x) inside the instruction, constants are left alone. The instruction
+    // has been checked already.
+    fn collect_registers(inst: &Exp, exp: &Exp, names: &mut BTreeSet<String>) {
+        if !exp.is_pair() || is_constant_exp(exp) {
+            return;
+        }
+        let head = display_exp(&car(exp).unwrap());
+        let names_register = match head.as_str() {
+            "assign" | "save" | "restore" | "save-from-to" | "restore-to-from" => {
+                std::ptr::eq(exp, inst)
+            }
+            "reg" => true,
+            _ => false,
+        };
+        if names_register {
+            if let Ok(x) = cadr(exp) {
+                names.insert(exp_to_str(x));
+            }
+        }
+        let mut rest = exp.clone();
+        while rest.is_pair() {
+            collect_registers(inst, &car(&rest).unwrap(), names);
+            rest = cdr(&rest).unwrap();
+        }
     }
 
     #[allow(dead_code)]
@@ -28,7 +197,7 @@ pub mod assembler {
     #[allow(dead_code)]
     pub fn extract_labels_alternative(text: String, machine: &mut BasicMachine) -> Exp {
         let text = str_to_exp(text);
-        let mut offset: usize = 0;
+        let mut offset: usize = machine.raw_instructions.len();
         extract_labels_iter_alternative(text, machine, &mut offset)
     }
 
@@ -124,7 +293,6 @@ pub mod assembler {
         machine: &mut BasicMachine,
         memory: &mut Memory,
     ) -> Box<dyn FnOnce(&mut BasicMachine, &mut Memory) -> Exp> {
-        // println!("inst to be assembled => {}", exp_to_str(inst.clone()));
         let symbol = car(&inst).unwrap();
         let assign = Exp::Symbol("assign".to_string());
         let test = Exp::Symbol("test".to_string());
@@ -133,6 +301,9 @@ pub mod assembler {
         let save = Exp::Symbol("save".to_string());
         let restore = Exp::Symbol("restore".to_string());
         let perform = Exp::Symbol("perform".to_string());
+        let save_from_to = Exp::Symbol("save-from-to".to_string());
+        let restore_to_from = Exp::Symbol("restore-to-from".to_string());
+        let halt = Exp::Symbol("halt".to_string());
         match symbol {
             x if x == assign => make_assign(inst, machine, memory),
             x if x == test => make_test(inst, machine, memory),
@@ -141,6 +312,9 @@ pub mod assembler {
             x if x == save => make_save(inst, machine, memory),
             x if x == restore => make_restore(inst, machine, memory),
             x if x == perform => make_perform(inst, machine, memory),
+            x if x == save_from_to => make_save_from_to(inst, machine, memory),
+            x if x == restore_to_from => make_restore_to_from(inst, machine, memory),
+            x if x == halt => make_halt(inst, machine, memory),
             _ => {
                 panic!("Unknown instruction type => {}: ASSEMBLE", exp_to_str(inst));
             }
@@ -160,7 +334,7 @@ pub mod assembler {
         let lambda = |machine: &mut BasicMachine, _memory: &mut Memory| {
             let data = reg_name;
             let contents = machine.get_register_contents(&data).unwrap();
-            machine.stack.push(contents);
+            machine.stack.push_from(&data, contents);
             machine.advance_pc();
             Exp::Quote("ok".to_string())
         };
@@ -181,7 +355,7 @@ pub mod assembler {
         let reg_name = exp_to_str(stack_inst_reg_name(&inst));
         let lambda = |machine: &mut BasicMachine, _memory: &mut Memory| {
             let data = reg_name;
-            let contents = machine.stack.pop().unwrap();
+            let contents = machine.stack.pop_into(&data);
             machine.set_register_contents(&data, contents);
             machine.advance_pc();
             Exp::Quote("ok".to_string())
@@ -189,6 +363,73 @@ pub mod assembler {
         Box::new(lambda)
     }
 
+    // (save-from-to n numbers), the stack is made when the first instruction that
+    // names it is assembled and has the capacity and discipline of the stack of
+    // the machine
+    #[allow(dead_code)]
+    pub fn make_save_from_to(
+        inst: Exp,
+        machine: &mut BasicMachine,
+        _memory: &mut Memory,
+    ) -> Box<dyn FnOnce(&mut BasicMachine, &mut Memory) -> Exp> {
+        let reg_name = exp_to_str(stack_inst_reg_name(&inst));
+        let stack_name = named_stack(&inst, machine);
+        let lambda = move |machine: &mut BasicMachine, _memory: &mut Memory| {
+            let contents = machine.get_register_contents(&reg_name).unwrap();
+            let stack = machine.stacks.get_mut(&stack_name).unwrap();
+            stack.push_from(&reg_name, contents);
+            machine.advance_pc();
+            Exp::Quote("ok".to_string())
+        };
+        Box::new(lambda)
+    }
+
+    // (restore-to-from n numbers)
+    #[allow(dead_code)]
+    pub fn make_restore_to_from(
+        inst: Exp,
+        machine: &mut BasicMachine,
+        _memory: &mut Memory,
+    ) -> Box<dyn FnOnce(&mut BasicMachine, &mut Memory) -> Exp> {
+        let reg_name = exp_to_str(stack_inst_reg_name(&inst));
+        let stack_name = named_stack(&inst, machine);
+        let lambda = move |machine: &mut BasicMachine, _memory: &mut Memory| {
+            let stack = machine.stacks.get_mut(&stack_name).unwrap();
+            let contents = stack.pop_into(&reg_name);
+            machine.set_register_contents(&reg_name, contents);
+            machine.advance_pc();
+            Exp::Quote("ok".to_string())
+        };
+        Box::new(lambda)
+    }
+
+    fn named_stack(inst: &Exp, machine: &mut BasicMachine) -> String {
+        let name = exp_to_str(caddr(inst).unwrap());
+        let capacity = machine.stack.capacity();
+        let discipline = machine.stack.discipline();
+        machine
+            .stacks
+            .entry(name.clone())
+            .or_insert_with(|| Stack::with_discipline(capacity, discipline));
+        name
+    }
+
+    // (halt), pc is moved past the last instruction such that execute is done,
+    // the end is looked up when it is run since instructions may be added later
+    #[allow(dead_code)]
+    pub fn make_halt(
+        _inst: Exp,
+        _machine: &mut BasicMachine,
+        _memory: &mut Memory,
+    ) -> Box<dyn FnOnce(&mut BasicMachine, &mut Memory) -> Exp> {
+        let lambda = |machine: &mut BasicMachine, _memory: &mut Memory| {
+            let end = machine.raw_instructions.len();
+            machine.set_register_contents(&"pc".to_string(), Object::Index(end));
+            Exp::Quote("ok".to_string())
+        };
+        Box::new(lambda)
+    }
+
     #[allow(dead_code)]
     pub fn make_perform(
         inst: Exp,
@@ -197,7 +438,6 @@ pub mod assembler {
     ) -> Box<dyn FnOnce(&mut BasicMachine, &mut Memory) -> Exp> {
         let action = perform_action(&inst);
         let op_name = operation_exp_op(&action);
-        // println!("op_name=>{}", exp_to_str(op_name.clone()));
         let set_varialbe_value = Exp::Symbol("set-variable-value!".to_string());
         let define_variable = Exp::Symbol("define-variable!".to_string());
         let flag;
@@ -206,18 +446,16 @@ pub mod assembler {
             x if x == define_variable => flag = true,
             _ => flag = false,
         }
-        // println!("flag=>{}", flag);
         if is_operation_exp(&action) {
             let action_proc = make_operation_exp(action, machine, memory);
             let lambda = move |machine: &mut BasicMachine, memory: &mut Memory| {
                 let data = flag;
                 let r = consume_box_closure(action_proc, machine, memory);
                 if data {
-                    machine.set_register_contents_as_in_memory(
-                        &"env".to_string(),
-                        exp_to_str(r),
-                        memory,
-                    );
+                    match exp_to_object(&r, machine, memory) {
+                        Ok(x) => machine.set_register_contents(&"env".to_string(), x),
+                        Err(e) => panic!("Error: {}", e),
+                    }
                 }
                 machine.advance_pc();
                 Exp::Quote("ok".to_string())
@@ -241,7 +479,6 @@ pub mod assembler {
         _memory: &mut Memory,
     ) -> Box<dyn FnOnce(&mut BasicMachine, &mut Memory) -> Exp> {
         let dest = goto_dest(&inst);
-        // println!("{}", exp_to_str(inst.clone()));
         match dest {
             x if is_label_exp(&x) => {
                 let index = lookup_label(machine, &exp_to_str(label_exp_label(&x))).unwrap();
@@ -320,7 +557,10 @@ pub mod assembler {
             let lambda = |machine: &mut BasicMachine, memory: &mut Memory| {
                 let data = condition_proc;
                 let value = consume_box_closure(data, machine, memory);
-                machine.set_register_contents(&"flag".to_string(), value.exp_to_object());
+                match exp_to_object(&value, machine, memory) {
+                    Ok(x) => machine.set_register_contents(&"flag".to_string(), x),
+                    Err(e) => panic!("Error: {}", e),
+                }
                 machine.advance_pc();
                 Exp::Quote("ok".to_string())
             };
@@ -359,13 +599,10 @@ pub mod assembler {
             let name = exp_to_str(reg_name);
             let data = lambda;
             let value = consume_box_closure(data.unwrap(), machine, memory);
-            match value {
-                Exp::List(ref _x) => {
-                    machine.set_register_contents_as_in_memory(&name, exp_to_str(value), memory);
-                }
-                _ => {
-                    machine.set_register_contents(&name, value.exp_to_object());
-                }
+            // a list is written into the heap, see datum::exp_to_object
+            match exp_to_object(&value, machine, memory) {
+                Ok(x) => machine.set_register_contents(&name, x),
+                Err(e) => panic!("Error: {}", e),
             }
             machine.advance_pc();
             Exp::Quote("ok".to_string())
@@ -413,16 +650,7 @@ pub mod assembler {
                 let lambda = |machine: &mut BasicMachine, memory: &mut Memory| {
                     let data = name;
                     let content = machine.get_register_contents(&data).unwrap();
-                    let mem = &(*memory);
-                    match content {
-                        Object::Index(_x) => {
-                            let result = machine.get_register_contents_as_in_memory(&data, mem);
-                            return str_to_exp(result);
-                        }
-                        _ => {
-                            return content.object_to_exp();
-                        }
-                    }
+                    object_to_exp(&content, memory)
                 };
                 Box::new(lambda)
             }
@@ -512,7 +740,12 @@ pub mod assembler {
             Exp::List(Pair::Nil)
         } else {
             let item = car(&operands).unwrap();
-            let r = make_primitive_exp(item, machine, memory);
+            // an operand may be an operation itself, ((op +) (reg a) (const 1))
+            let r = if is_operation_exp(&item) {
+                make_operation_exp(item, machine, memory)
+            } else {
+                make_primitive_exp(item, machine, memory)
+            };
             let exp = consume_box_closure(r, machine, memory);
             scheme_cons(
                 exp,
@@ -574,11 +807,14 @@ mod test {
     };
 
     use super::assembler::{
-        assign_reg_name, assign_value_exp, extract_labels_alternative, lookup_label, make_assign,
-        make_branch, make_operation_exp, make_perform, make_primitive_exp, make_restore, make_save,
-        make_test,
+        assemble, assign_reg_name, assign_value_exp, extract_labels_alternative, lookup_label,
+        make_assign, make_branch, make_operation_exp, make_perform, make_primitive_exp,
+        make_restore, make_save, make_test,
     };
+    use crate::infrastructure::stack::{Stack, StackDiscipline};
+    use crate::runner::runner::panic_message;
     use crate::tpfordev::type_system::{append, scheme_cons};
+    use std::panic::{self, AssertUnwindSafe};
     #[test]
     fn lookup_label_works() {
         let mut machine = BasicMachine::new();
@@ -782,4 +1018,134 @@ mod test {
         ));
         assert_eq!(str_to_exp(content), checkout);
     }
+
+    #[test]
+    fn controller_info_works() {
+        let mut memory = Memory::new(20);
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        machine.initialize_op();
+        let text = MachineCase::test_case().controller_text.to_string();
+        assemble(text, &mut machine, &mut memory).unwrap();
+        let info = &machine.info;
+        let kinds: Vec<&String> = info.instructions.keys().collect();
+        assert_eq!(
+            kinds,
+            vec!["assign", "branch", "goto", "restore", "save", "test"]
+        );
+        assert_eq!(info.instructions["goto"].len(), 2);
+        assert!(info.instructions["goto"].contains("(goto (reg continue))"));
+        assert_eq!(
+            info.entry_points.iter().collect::<Vec<_>>(),
+            vec!["continue"]
+        );
+        assert_eq!(
+            info.stack_registers.iter().collect::<Vec<_>>(),
+            vec!["continue", "exp"]
+        );
+        let continue_sources: Vec<&String> = info.sources["continue"].iter().collect();
+        assert_eq!(
+            continue_sources,
+            vec!["(label after-fact)", "(label fact-done)"]
+        );
+        assert!(info.sources["val"].contains("(op *) (reg exp) (reg val)"));
+        assert!(info.registers().contains("exp"));
+        assert!(info.to_string().contains("entry points: continue\n"));
+    }
+
+    #[test]
+    fn allocate_registers_works() {
+        let mut memory = Memory::new(20);
+        let mut machine = BasicMachine::new();
+        machine.initialize_op();
+        let text = "(controller
+                        (assign product (const 1))
+                        (assign counter (const 1))
+                      loop
+                        (test (op >) (reg counter) (reg n))
+                        (branch (label done))
+                        (assign product (op *) (reg counter) (reg product))
+                        (assign counter (op +) (reg counter) (const 1))
+                        (goto (label loop))
+                      done)";
+        assemble(text.to_string(), &mut machine, &mut memory).unwrap();
+        let mut names: Vec<&String> = machine.registers.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["counter", "flag", "n", "pc", "product"]);
+        machine.set_register_contents(&"n".to_string(), Object::Integer(5));
+        machine.execute(&mut memory);
+        assert_eq!(
+            machine.get_register_contents(&"product".to_string()),
+            Some(Object::Integer(120))
+        );
+    }
+
+    #[test]
+    fn assemble_rejects_bad_controllers() {
+        let mut memory = Memory::new(20);
+        let mut machine = BasicMachine::new();
+        let text = "(controller (save (n)) (goto (label done)))";
+        let r = assemble(text.to_string(), &mut machine, &mut memory);
+        let diagnostics = r.unwrap_err();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "1:13 (controller+1): save expects a register: (save (n))"
+        );
+        assert!(machine.raw_instructions.is_empty());
+        assert!(machine.labels.is_empty());
+    }
+
+    #[test]
+    fn extensions_work() {
+        let mut memory = Memory::new(20);
+        let mut machine = BasicMachine::new();
+        machine.initialize_op();
+        let text = "(controller
+                        (save-from-to n numbers)
+                        (assign n (op *) ((op +) (reg n) (const 1)) (reg n))
+                        (assign val (reg n))
+                        (restore-to-from n numbers)
+                        (halt)
+                        (assign val (const 0)))";
+        assemble(text.to_string(), &mut machine, &mut memory).unwrap();
+        assert_eq!(machine.info.stack_registers.len(), 1);
+        machine.set_register_contents(&"n".to_string(), Object::Integer(4));
+        machine.execute(&mut memory);
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Integer(20))
+        );
+        assert_eq!(
+            machine.get_register_contents(&"n".to_string()),
+            Some(Object::Integer(4))
+        );
+        assert_eq!(machine.stacks["numbers"].to_string(), "Depth: 0");
+    }
+
+    #[test]
+    fn restore_checks_the_register() {
+        // unchecked unless the stack is tagged, as in SICP
+        let mut memory = Memory::new(20);
+        let mut machine = BasicMachine::new();
+        let text = "(controller (save n) (restore val))";
+        assemble(text.to_string(), &mut machine, &mut memory).unwrap();
+        machine.set_register_contents(&"n".to_string(), Object::Integer(4));
+        machine.execute(&mut memory);
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Integer(4))
+        );
+        let mut memory = Memory::new(20);
+        let mut machine = BasicMachine::new();
+        machine.stack = Stack::with_discipline(100, StackDiscipline::Tagged);
+        let text = "(controller (save n) (restore val))";
+        assemble(text.to_string(), &mut machine, &mut memory).unwrap();
+        machine.set_register_contents(&"n".to_string(), Object::Integer(4));
+        let r = panic::catch_unwind(AssertUnwindSafe(|| machine.execute(&mut memory)));
+        assert_eq!(
+            panic_message(r.unwrap_err()),
+            "restore val: the top of the stack was saved from n"
+        );
+    }
 }
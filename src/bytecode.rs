@@ -0,0 +1,808 @@
+pub mod bytecode {
+    use crate::assembler::assembler::{
+        allocate_registers, is_operation_exp, make_execution_procedure, ControllerInfo,
+    };
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::parser::parser::Span;
+    use crate::parserfordev::parser::{display_exp, exp_to_str};
+    use crate::primitives::primitives::{caddr, cadr, cddr};
+    use crate::tpfordev::type_system::{car, cdr, Exp, Pair};
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::convert::TryInto;
+    use std::fs;
+    use std::path::Path;
+
+    // assembled instructions in a form that can be written to a file and installed
+    // in a machine again without reading the controller text. Registers, ops and
+    // stacks are numbered by their place in the tables of the bytecode, labels are
+    // resolved to the offsets of the instructions.
+    //
+    // The file starts with MAGIC and VERSION, followed by the source name, the
+    // tables, the labels, the instructions and the spans. Numbers are little
+    // endian, strings and lists are preceded by their length as a u32.
+
+    pub const MAGIC: &[u8; 4] = b"RMBC";
+    pub const VERSION: u8 = 1;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Operand {
+        Register(u16),
+        Constant(Exp),
+        Label(u32),
+        // an op applied to its operands, which may be ops themselves
+        Operation(u16, Vec<Operand>),
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Instruction {
+        Assign(u16, Operand),
+        Test(u16, Vec<Operand>),
+        Branch(u32),
+        Goto(Operand),
+        Save(u16),
+        Restore(u16),
+        Perform(u16, Vec<Operand>),
+        // register and stack
+        SaveFromTo(u16, u16),
+        RestoreToFrom(u16, u16),
+        Halt,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct Bytecode {
+        pub source_name: String,
+        pub registers: Vec<String>,
+        pub operations: Vec<String>,
+        pub stacks: Vec<String>,
+        // sorted by offset and name
+        pub labels: Vec<(String, u32)>,
+        pub instructions: Vec<Instruction>,
+        // where each instruction starts in the controller text, may be empty
+        pub spans: Vec<Span>,
+    }
+
+    // the instructions of the machine, which have been checked by the assembler
+    #[allow(dead_code)]
+    pub fn encode(machine: &BasicMachine) -> Bytecode {
+        let mut bytecode = Bytecode {
+            source_name: machine.source_name.clone(),
+            spans: machine.spans.clone(),
+            ..Bytecode::default()
+        };
+        let mut labels: Vec<(String, u32)> = machine
+            .labels
+            .iter()
+            .map(|(name, i)| (name.clone(), *i as u32))
+            .collect();
+        labels.sort_by(|x, y| (x.1, &x.0).cmp(&(y.1, &y.0)));
+        bytecode.labels = labels;
+        for inst in machine.raw_instructions.iter() {
+            let inst = encode_instruction(inst, &mut bytecode, machine);
+            bytecode.instructions.push(inst);
+        }
+        bytecode
+    }
+
+    fn encode_instruction(
+        inst: &Exp,
+        bytecode: &mut Bytecode,
+        machine: &BasicMachine,
+    ) -> Instruction {
+        let kind = display_exp(&car(inst).unwrap());
+        let register = |bytecode: &mut Bytecode| {
+            intern(&mut bytecode.registers, &exp_to_str(cadr(inst).unwrap()))
+        };
+        match kind.as_str() {
+            "assign" => {
+                let reg = register(bytecode);
+                let value = cddr(inst).unwrap();
+                let operand = if is_operation_exp(&value) {
+                    encode_operation(&value, bytecode, machine)
+                } else {
+                    encode_operand(&car(&value).unwrap(), bytecode, machine)
+                };
+                Instruction::Assign(reg, operand)
+            }
+            "test" | "perform" => match encode_operation(&cdr(inst).unwrap(), bytecode, machine) {
+                Operand::Operation(op, operands) if kind == "test" => {
+                    Instruction::Test(op, operands)
+                }
+                Operand::Operation(op, operands) => Instruction::Perform(op, operands),
+                _ => unreachable!(),
+            },
+            "branch" => match encode_operand(&cadr(inst).unwrap(), bytecode, machine) {
+                Operand::Label(x) => Instruction::Branch(x),
+                _ => panic!("Error: branch expects a label: {}", display_exp(inst)),
+            },
+            "goto" => Instruction::Goto(encode_operand(&cadr(inst).unwrap(), bytecode, machine)),
+            "save" => Instruction::Save(register(bytecode)),
+            "restore" => Instruction::Restore(register(bytecode)),
+            "save-from-to" | "restore-to-from" => {
+                let reg = register(bytecode);
+                let stack = intern(&mut bytecode.stacks, &exp_to_str(caddr(inst).unwrap()));
+                if kind == "save-from-to" {
+                    Instruction::SaveFromTo(reg, stack)
+                } else {
+                    Instruction::RestoreToFrom(reg, stack)
+                }
+            }
+            "halt" => Instruction::Halt,
+            _ => panic!("Error: unknown instruction {}", display_exp(inst)),
+        }
+    }
+
+    // ((op +) (reg a) (const 1))
+    fn encode_operation(exp: &Exp, bytecode: &mut Bytecode, machine: &BasicMachine) -> Operand {
+        let op = intern(
+            &mut bytecode.operations,
+            &exp_to_str(cadr(&car(exp).unwrap()).unwrap()),
+        );
+        let mut operands = vec![];
+        let mut rest = cdr(exp).unwrap();
+        while rest.is_pair() {
+            let item = car(&rest).unwrap();
+            let operand = if is_operation_exp(&item) {
+                encode_operation(&item, bytecode, machine)
+            } else {
+                encode_operand(&item, bytecode, machine)
+            };
+            operands.push(operand);
+            rest = cdr(&rest).unwrap();
+        }
+        Operand::Operation(op, operands)
+    }
+
+    fn encode_operand(exp: &Exp, bytecode: &mut Bytecode, machine: &BasicMachine) -> Operand {
+        let kind = display_exp(&car(exp).unwrap());
+        let value = cadr(exp).unwrap();
+        match kind.as_str() {
+            "reg" => Operand::Register(intern(&mut bytecode.registers, &exp_to_str(value))),
+            "const" => Operand::Constant(value),
+            "label" => match machine.labels.get(&exp_to_str(value.clone())) {
+                Some(x) => Operand::Label(*x as u32),
+                None => panic!("Error: unknown label {}", display_exp(&value)),
+            },
+            _ => panic!("Error: unknown operand {}", display_exp(exp)),
+        }
+    }
+
+    fn intern(table: &mut Vec<String>, name: &str) -> u16 {
+        match table.iter().position(|x| x == name) {
+            Some(i) => i as u16,
+            None => {
+                table.push(name.to_string());
+                (table.len() - 1) as u16
+            }
+        }
+    }
+
+    impl Bytecode {
+        // the instructions as they are written in a controller, with the labels
+        // they refer to. Of the labels at the same offset the first in alphabetical
+        // order stands for the offset.
+        #[allow(dead_code)]
+        pub fn instructions_as_exps(&self) -> Result<Vec<Exp>, String> {
+            let names = self.label_names();
+            let mut insts = vec![];
+            for inst in self.instructions.iter() {
+                insts.push(self.decode_instruction(inst, &names)?);
+            }
+            Ok(insts)
+        }
+
+        fn label_names(&self) -> BTreeMap<u32, String> {
+            let mut names = BTreeMap::new();
+            for (name, offset) in self.labels.iter() {
+                names.entry(*offset).or_insert_with(|| name.clone());
+            }
+            names
+        }
+
+        fn decode_instruction(
+            &self,
+            inst: &Instruction,
+            names: &BTreeMap<u32, String>,
+        ) -> Result<Exp, String> {
+            let register = |i: &u16| self.name(&self.registers, *i, "register");
+            let exp = match inst {
+                Instruction::Assign(reg, operand) => {
+                    let mut items = vec![symbol("assign"), symbol(&register(reg)?)];
+                    match operand {
+                        Operand::Operation(op, operands) => {
+                            items.extend(self.decode_operation(*op, operands, names)?)
+                        }
+                        x => items.push(self.decode_operand(x, names)?),
+                    }
+                    list(items)
+                }
+                Instruction::Test(op, operands) | Instruction::Perform(op, operands) => {
+                    let kind = match inst {
+                        Instruction::Test(_x, _y) => "test",
+                        _ => "perform",
+                    };
+                    let mut items = vec![symbol(kind)];
+                    items.extend(self.decode_operation(*op, operands, names)?);
+                    list(items)
+                }
+                Instruction::Branch(x) => list(vec![
+                    symbol("branch"),
+                    self.decode_operand(&Operand::Label(*x), names)?,
+                ]),
+                Instruction::Goto(x) => list(vec![symbol("goto"), self.decode_operand(x, names)?]),
+                Instruction::Save(x) => list(vec![symbol("save"), symbol(&register(x)?)]),
+                Instruction::Restore(x) => list(vec![symbol("restore"), symbol(&register(x)?)]),
+                Instruction::SaveFromTo(x, y) | Instruction::RestoreToFrom(x, y) => {
+                    let kind = match inst {
+                        Instruction::SaveFromTo(_x, _y) => "save-from-to",
+                        _ => "restore-to-from",
+                    };
+                    let stack = self.name(&self.stacks, *y, "stack")?;
+                    list(vec![symbol(kind), symbol(&register(x)?), symbol(&stack)])
+                }
+                Instruction::Halt => list(vec![symbol("halt")]),
+            };
+            Ok(exp)
+        }
+
+        // (op +) followed by the operands
+        fn decode_operation(
+            &self,
+            op: u16,
+            operands: &[Operand],
+            names: &BTreeMap<u32, String>,
+        ) -> Result<Vec<Exp>, String> {
+            let name = self.name(&self.operations, op, "operation")?;
+            let mut items = vec![list(vec![symbol("op"), symbol(&name)])];
+            for operand in operands {
+                items.push(self.decode_operand(operand, names)?);
+            }
+            Ok(items)
+        }
+
+        fn decode_operand(
+            &self,
+            operand: &Operand,
+            names: &BTreeMap<u32, String>,
+        ) -> Result<Exp, String> {
+            let exp = match operand {
+                Operand::Register(x) => list(vec![
+                    symbol("reg"),
+                    symbol(&self.name(&self.registers, *x, "register")?),
+                ]),
+                Operand::Constant(x) => list(vec![symbol("const"), x.clone()]),
+                Operand::Label(x) => match names.get(x) {
+                    Some(name) => list(vec![symbol("label"), symbol(name)]),
+                    None => return Err(format!("no label at offset {}", x)),
+                },
+                Operand::Operation(op, operands) => {
+                    list(self.decode_operation(*op, operands, names)?)
+                }
+            };
+            Ok(exp)
+        }
+
+        fn name(&self, table: &[String], i: u16, kind: &str) -> Result<String, String> {
+            match table.get(i as usize) {
+                Some(x) => Ok(x.clone()),
+                None => Err(format!("no {} {}", kind, i)),
+            }
+        }
+
+        // the controller text of the bytecode, assembling it gives the same
+        // bytecode except for the spans
+        #[allow(dead_code)]
+        pub fn disassemble(&self) -> Result<String, String> {
+            let insts = self.instructions_as_exps()?;
+            let mut labels: BTreeMap<u32, Vec<&String>> = BTreeMap::new();
+            for (name, offset) in self.labels.iter() {
+                labels.entry(*offset).or_default().push(name);
+            }
+            let mut lines = vec![];
+            for i in 0..=insts.len() {
+                for name in labels.get(&(i as u32)).into_iter().flatten() {
+                    lines.push(format!("  {}", name));
+                }
+                if let Some(inst) = insts.get(i) {
+                    lines.push(format!("    {}", display_exp(inst)));
+                }
+            }
+            Ok(format!("(\n{})\n", lines.join("\n")))
+        }
+
+        #[allow(dead_code)]
+        pub fn to_bytes(&self) -> Vec<u8> {
+            let mut output = Output::new();
+            output.bytes.extend_from_slice(MAGIC);
+            output.u8(VERSION);
+            output.string(&self.source_name);
+            for table in [&self.registers, &self.operations, &self.stacks] {
+                output.u32(table.len() as u32);
+                for name in table.iter() {
+                    output.string(name);
+                }
+            }
+            output.u32(self.labels.len() as u32);
+            for (name, offset) in self.labels.iter() {
+                output.string(name);
+                output.u32(*offset);
+            }
+            output.u32(self.instructions.len() as u32);
+            for inst in self.instructions.iter() {
+                output.instruction(inst);
+            }
+            output.u32(self.spans.len() as u32);
+            for span in self.spans.iter() {
+                output.u32(span.line as u32);
+                output.u32(span.column as u32);
+            }
+            output.bytes
+        }
+
+        #[allow(dead_code)]
+        pub fn from_bytes(bytes: &[u8]) -> Result<Bytecode, String> {
+            if !is_bytecode(bytes) {
+                return Err("not bytecode".to_string());
+            }
+            let mut input = Input::new(bytes, MAGIC.len());
+            let version = input.u8()?;
+            if version != VERSION {
+                return Err(format!("bytecode version {} isn't supported", version));
+            }
+            let mut bytecode = Bytecode {
+                source_name: input.string()?,
+                ..Bytecode::default()
+            };
+            for table in [
+                &mut bytecode.registers,
+                &mut bytecode.operations,
+                &mut bytecode.stacks,
+            ] {
+                for _i in 0..input.u32()? {
+                    table.push(input.string()?);
+                }
+            }
+            for _i in 0..input.u32()? {
+                let name = input.string()?;
+                bytecode.labels.push((name, input.u32()?));
+            }
+            for _i in 0..input.u32()? {
+                bytecode.instructions.push(input.instruction()?);
+            }
+            for _i in 0..input.u32()? {
+                let line = input.u32()? as usize;
+                let column = input.u32()? as usize;
+                bytecode.spans.push(Span { line, column });
+            }
+            if !input.at_end() {
+                return Err("trailing bytes after the bytecode".to_string());
+            }
+            Ok(bytecode)
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_bytecode(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    #[allow(dead_code)]
+    pub fn write_bytecode(path: &Path, bytecode: &Bytecode) -> Result<(), String> {
+        fs::write(path, bytecode.to_bytes())
+            .map_err(|e| format!("can't write {}: {}", path.display(), e))
+    }
+
+    #[allow(dead_code)]
+    pub fn read_bytecode(path: &Path) -> Result<Bytecode, String> {
+        let bytes = fs::read(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        Bytecode::from_bytes(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // like assemble the instructions are added after the ones the machine has
+    // already, the offsets of the bytecode are moved along with them. The
+    // operations have to be known to the machine.
+    #[allow(dead_code)]
+    pub fn install(
+        bytecode: &Bytecode,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<(), String> {
+        let unknown: BTreeSet<&String> = bytecode
+            .operations
+            .iter()
+            .filter(|x| !machine.is_semantic_op(x) && !machine.is_machine_op(x))
+            .collect();
+        if let Some(x) = unknown.iter().next() {
+            return Err(format!("unknown operation {}", x));
+        }
+        // as in assemble, a label can't be given twice, neither in the bytecode
+        // nor by the machine it is installed into
+        let mut names: BTreeSet<&String> = machine.labels.keys().collect();
+        for (name, _offset) in bytecode.labels.iter() {
+            if !names.insert(name) {
+                return Err(format!("duplicate label {}", name));
+            }
+        }
+        let insts = bytecode.instructions_as_exps()?;
+        let start = machine.raw_instructions.len();
+        for (name, offset) in bytecode.labels.iter() {
+            machine
+                .labels
+                .insert(name.clone(), start + *offset as usize);
+        }
+        if machine.spans.len() == start && bytecode.spans.len() == insts.len() {
+            machine.spans.extend(bytecode.spans.iter().copied());
+        }
+        machine.source_name = bytecode.source_name.clone();
+        machine.raw_instructions.extend(insts.iter().cloned());
+        machine.info = ControllerInfo::new(&machine.raw_instructions);
+        allocate_registers(machine);
+        for inst in insts {
+            let proc = make_execution_procedure(inst, machine, memory);
+            machine.instruction_sequence.push(Some(proc));
+        }
+        Ok(())
+    }
+
+    fn symbol(name: &str) -> Exp {
+        Exp::Symbol(name.to_string())
+    }
+
+    fn list(items: Vec<Exp>) -> Exp {
+        let mut pair = Pair::Nil;
+        for item in items.into_iter().rev() {
+            pair = Pair::Cons(Box::new(item), Box::new(pair));
+        }
+        Exp::List(pair)
+    }
+
+    // the numbers and strings of the format above, also used for snapshots
+    pub struct Output {
+        pub bytes: Vec<u8>,
+    }
+
+    impl Default for Output {
+        fn default() -> Self {
+            Output::new()
+        }
+    }
+
+    impl Output {
+        pub fn new() -> Self {
+            Output { bytes: vec![] }
+        }
+
+        pub fn u8(&mut self, x: u8) {
+            self.bytes.push(x);
+        }
+
+        pub fn u16(&mut self, x: u16) {
+            self.bytes.extend_from_slice(&x.to_le_bytes());
+        }
+
+        pub fn u32(&mut self, x: u32) {
+            self.bytes.extend_from_slice(&x.to_le_bytes());
+        }
+
+        pub fn string(&mut self, x: &str) {
+            self.u32(x.len() as u32);
+            self.bytes.extend_from_slice(x.as_bytes());
+        }
+
+        fn instruction(&mut self, inst: &Instruction) {
+            match inst {
+                Instruction::Assign(x, y) => {
+                    self.u8(0);
+                    self.u16(*x);
+                    self.operand(y);
+                }
+                Instruction::Test(x, y) => {
+                    self.u8(1);
+                    self.operation(*x, y);
+                }
+                Instruction::Branch(x) => {
+                    self.u8(2);
+                    self.u32(*x);
+                }
+                Instruction::Goto(x) => {
+                    self.u8(3);
+                    self.operand(x);
+                }
+                Instruction::Save(x) => {
+                    self.u8(4);
+                    self.u16(*x);
+                }
+                Instruction::Restore(x) => {
+                    self.u8(5);
+                    self.u16(*x);
+                }
+                Instruction::Perform(x, y) => {
+                    self.u8(6);
+                    self.operation(*x, y);
+                }
+                Instruction::SaveFromTo(x, y) => {
+                    self.u8(7);
+                    self.u16(*x);
+                    self.u16(*y);
+                }
+                Instruction::RestoreToFrom(x, y) => {
+                    self.u8(8);
+                    self.u16(*x);
+                    self.u16(*y);
+                }
+                Instruction::Halt => self.u8(9),
+            }
+        }
+
+        fn operation(&mut self, op: u16, operands: &[Operand]) {
+            self.u16(op);
+            self.u16(operands.len() as u16);
+            for operand in operands {
+                self.operand(operand);
+            }
+        }
+
+        fn operand(&mut self, operand: &Operand) {
+            match operand {
+                Operand::Register(x) => {
+                    self.u8(0);
+                    self.u16(*x);
+                }
+                Operand::Constant(x) => {
+                    self.u8(1);
+                    self.exp(x);
+                }
+                Operand::Label(x) => {
+                    self.u8(2);
+                    self.u32(*x);
+                }
+                Operand::Operation(x, y) => {
+                    self.u8(3);
+                    self.operation(*x, y);
+                }
+            }
+        }
+
+        fn exp(&mut self, exp: &Exp) {
+            match exp {
+                Exp::Integer(x) => {
+                    self.u8(0);
+                    self.bytes.extend_from_slice(&x.to_le_bytes());
+                }
+                Exp::FloatNumber(x) => {
+                    self.u8(1);
+                    self.bytes.extend_from_slice(&x.to_le_bytes());
+                }
+                Exp::Symbol(x) => {
+                    self.u8(2);
+                    self.string(x);
+                }
+                Exp::Quote(x) => {
+                    self.u8(3);
+                    self.string(x);
+                }
+                Exp::SchemeString(x) => {
+                    self.u8(4);
+                    self.string(x);
+                }
+                Exp::Bool(x) => {
+                    self.u8(5);
+                    self.u8(*x as u8);
+                }
+                Exp::Eof => self.u8(8),
+                Exp::List(x) => {
+                    let mut items = vec![];
+                    let mut rest = x;
+                    while let Pair::Cons(lhs, rhs) = rest {
+                        items.push(lhs);
+                        rest = rhs;
+                    }
+                    self.u8(6);
+                    self.u32(items.len() as u32);
+                    for item in items {
+                        self.exp(item);
+                    }
+                }
+                Exp::Index(x) => {
+                    self.u8(7);
+                    self.u32(*x as u32);
+                }
+            }
+        }
+    }
+
+    pub struct Input<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl<'a> Input<'a> {
+        pub fn new(bytes: &'a [u8], position: usize) -> Self {
+            Input { bytes, position }
+        }
+
+        pub fn at_end(&self) -> bool {
+            self.position == self.bytes.len()
+        }
+
+        pub fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+            if self.position + n > self.bytes.len() {
+                return Err("the file ends too early".to_string());
+            }
+            let bytes = &self.bytes[self.position..self.position + n];
+            self.position += n;
+            Ok(bytes)
+        }
+
+        pub fn u8(&mut self) -> Result<u8, String> {
+            Ok(self.take(1)?[0])
+        }
+
+        pub fn u16(&mut self) -> Result<u16, String> {
+            Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+        }
+
+        pub fn u32(&mut self) -> Result<u32, String> {
+            Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+        }
+
+        pub fn string(&mut self) -> Result<String, String> {
+            let n = self.u32()? as usize;
+            String::from_utf8(self.take(n)?.to_vec())
+                .map_err(|_e| "bad string in the bytecode".to_string())
+        }
+
+        fn instruction(&mut self) -> Result<Instruction, String> {
+            let inst = match self.u8()? {
+                0 => Instruction::Assign(self.u16()?, self.operand()?),
+                1 => {
+                    let (op, operands) = self.operation()?;
+                    Instruction::Test(op, operands)
+                }
+                2 => Instruction::Branch(self.u32()?),
+                3 => Instruction::Goto(self.operand()?),
+                4 => Instruction::Save(self.u16()?),
+                5 => Instruction::Restore(self.u16()?),
+                6 => {
+                    let (op, operands) = self.operation()?;
+                    Instruction::Perform(op, operands)
+                }
+                7 => Instruction::SaveFromTo(self.u16()?, self.u16()?),
+                8 => Instruction::RestoreToFrom(self.u16()?, self.u16()?),
+                9 => Instruction::Halt,
+                x => return Err(format!("unknown instruction code {}", x)),
+            };
+            Ok(inst)
+        }
+
+        fn operation(&mut self) -> Result<(u16, Vec<Operand>), String> {
+            let op = self.u16()?;
+            let mut operands = vec![];
+            for _i in 0..self.u16()? {
+                operands.push(self.operand()?);
+            }
+            Ok((op, operands))
+        }
+
+        fn operand(&mut self) -> Result<Operand, String> {
+            let operand = match self.u8()? {
+                0 => Operand::Register(self.u16()?),
+                1 => Operand::Constant(self.exp()?),
+                2 => Operand::Label(self.u32()?),
+                3 => {
+                    let (op, operands) = self.operation()?;
+                    Operand::Operation(op, operands)
+                }
+                x => return Err(format!("unknown operand code {}", x)),
+            };
+            Ok(operand)
+        }
+
+        fn exp(&mut self) -> Result<Exp, String> {
+            let exp = match self.u8()? {
+                0 => Exp::Integer(i32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+                1 => Exp::FloatNumber(f32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+                2 => Exp::Symbol(self.string()?),
+                3 => Exp::Quote(self.string()?),
+                4 => Exp::SchemeString(self.string()?),
+                5 => Exp::Bool(self.u8()? != 0),
+                6 => {
+                    let mut items = vec![];
+                    for _i in 0..self.u32()? {
+                        items.push(self.exp()?);
+                    }
+                    list(items)
+                }
+                7 => Exp::Index(self.u32()? as usize),
+                8 => Exp::Eof,
+                x => return Err(format!("unknown constant code {}", x)),
+            };
+            Ok(exp)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::bytecode::{encode, install, Bytecode};
+    use crate::assembler::assembler::assemble;
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::representation::type_system::Object;
+    use crate::runner::runner::{eval_str, make_evaluator};
+
+    fn new_machine() -> BasicMachine {
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        machine.initialize_op();
+        machine
+    }
+
+    #[test]
+    fn bytecode_round_trips() {
+        let mut memory = Memory::new(5000);
+        let evaluator = make_evaluator(&mut memory);
+        let bytecode = encode(&evaluator);
+        assert_eq!(
+            Bytecode::from_bytes(&bytecode.to_bytes()),
+            Ok(bytecode.clone())
+        );
+        // the disassembled text assembles to the same bytecode
+        let text = bytecode.disassemble().unwrap();
+        let mut machine = new_machine();
+        machine.source_name = bytecode.source_name.clone();
+        assert!(assemble(text, &mut machine, &mut memory).is_ok());
+        let again = encode(&machine);
+        assert_eq!(again.labels, bytecode.labels);
+        assert_eq!(again.registers, bytecode.registers);
+        assert_eq!(again.operations, bytecode.operations);
+        assert_eq!(again.instructions, bytecode.instructions);
+    }
+
+    #[test]
+    fn installed_bytecode_runs() {
+        let mut memory = Memory::new(5000);
+        let evaluator = make_evaluator(&mut memory);
+        let bytes = encode(&evaluator).to_bytes();
+        let mut machine = new_machine();
+        machine.initialize_env(&mut memory);
+        let bytecode = Bytecode::from_bytes(&bytes).unwrap();
+        assert_eq!(install(&bytecode, &mut machine, &mut memory), Ok(()));
+        assert_eq!(machine.spans, evaluator.spans);
+        let r = eval_str("(define (f x) (* x 3))", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Quote("ok".to_string())));
+        let r = eval_str("(f 14)", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Integer(42)));
+        let r = install(&bytecode, &mut machine, &mut memory);
+        assert!(r.unwrap_err().starts_with("duplicate label "));
+        let mut machine = BasicMachine::new();
+        assert_eq!(
+            install(&bytecode, &mut machine, &mut memory),
+            Err("unknown operation adjoin-arg".to_string())
+        );
+    }
+
+    #[test]
+    fn bad_bytecode_is_rejected() {
+        let mut memory = Memory::new(100);
+        let mut machine = new_machine();
+        let text = "(start (assign n (op +) (const 1) ((op *) (reg n) (const 2))) done)";
+        assemble(text.to_string(), &mut machine, &mut memory).unwrap();
+        let bytes = encode(&machine).to_bytes();
+        assert!(Bytecode::from_bytes(&bytes).is_ok());
+        assert_eq!(
+            Bytecode::from_bytes(&bytes[..bytes.len() - 1]),
+            Err("the file ends too early".to_string())
+        );
+        assert_eq!(
+            Bytecode::from_bytes(b"(controller)"),
+            Err("not bytecode".to_string())
+        );
+        let mut bytes = bytes;
+        bytes[4] = 9;
+        assert_eq!(
+            Bytecode::from_bytes(&bytes),
+            Err("bytecode version 9 isn't supported".to_string())
+        );
+    }
+}
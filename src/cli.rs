@@ -0,0 +1,398 @@
+pub mod cli {
+    use crate::bytecode::bytecode::read_bytecode;
+    use crate::infrastructure::stack::{Stack, StackDiscipline};
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::reader::reader::{default_history_file, Reader};
+    use crate::runner::runner::{
+        assemble_file, debug_controller, exec_controller, make_evaluator, register_dump, run_file,
+        run_repl,
+    };
+    use crate::snapshot::snapshot::load_snapshot;
+    use crate::trace::trace::JsonLinesSink;
+    use std::panic;
+    use std::path::PathBuf;
+
+    pub const USAGE: &str = "usage:
+    machine [options] [repl]                       run the evaluator read-eval-print-loop
+    machine [options] [run] FILE...                run Scheme source files in order
+    machine [options] exec CONTROLLER [REG=VALUE]...
+                                                   assemble and run a register-machine
+                                                   controller, REG=VALUE sets the initial
+                                                   content of a register, e.g. n=5,
+                                                   the controller may be bytecode
+    machine [options] assemble CONTROLLER OUTPUT   write the bytecode of a controller
+    machine [options] disassemble BYTECODE         print bytecode as controller text
+
+options:
+    --heap N     number of pairs in the heap (default 50000)
+    --stack N    maximum depth of the stack (default 100)
+    --stack-discipline D  shared (default), tagged, where restore checks the
+                 register the value was saved from, or per-register stacks
+    --trace      print each instruction and the registers it changes
+    --trace-file F  write the trace to F as JSON lines instead
+    --stats      print stack statistics
+    --profile    print instruction counts per label and the time spent in each op
+    --debug      exec: stop before the first instruction and start the debugger
+    --label-operands  allow (label x) as an operand of an op
+    --quiet      no prompts, announcements or final register dump
+    --history F  history file of the repl (default ~/.rusmachine_history)
+    --resume F   repl and run: start from a session saved by ,save
+    -h, --help   print this message";
+
+    #[derive(Debug, PartialEq)]
+    pub enum Mode {
+        Repl,
+        Run(Vec<PathBuf>),
+        Exec(PathBuf, Vec<(String, String)>),
+        Assemble(PathBuf, PathBuf),
+        Disassemble(PathBuf),
+        Help,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct Options {
+        pub mode: Mode,
+        pub heap_size: usize,
+        pub stack_limit: usize,
+        pub stack_discipline: StackDiscipline,
+        pub trace: bool,
+        pub trace_file: Option<PathBuf>,
+        pub statistics: bool,
+        pub profile: bool,
+        pub quiet: bool,
+        pub debug: bool,
+        pub label_operands: bool,
+        pub history: Option<PathBuf>,
+        pub resume: Option<PathBuf>,
+    }
+
+    impl Options {
+        pub fn new() -> Self {
+            Options {
+                mode: Mode::Repl,
+                heap_size: 50000,
+                stack_limit: 100,
+                stack_discipline: StackDiscipline::Shared,
+                trace: false,
+                trace_file: None,
+                statistics: false,
+                profile: false,
+                quiet: false,
+                debug: false,
+                label_operands: false,
+                history: None,
+                resume: None,
+            }
+        }
+    }
+
+    // options may appear anywhere on the command line, the first other argument
+    // selects the mode, a bare file name is the same as run
+    pub fn parse_args(args: &[String]) -> Result<Options, String> {
+        let mut options = Options::new();
+        let mut rest: Vec<String> = vec![];
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--heap" => options.heap_size = parse_number(arg, iter.next())?,
+                "--stack" => options.stack_limit = parse_number(arg, iter.next())?,
+                "--stack-discipline" => {
+                    options.stack_discipline = match iter.next() {
+                        Some(x) => match StackDiscipline::from_name(x) {
+                            Some(y) => y,
+                            None => return Err(format!("unknown stack discipline {}", x)),
+                        },
+                        None => return Err("--stack-discipline expects a discipline".to_string()),
+                    }
+                }
+                "--trace" => options.trace = true,
+                "--trace-file" => match iter.next() {
+                    Some(x) => {
+                        options.trace = true;
+                        options.trace_file = Some(PathBuf::from(x));
+                    }
+                    None => return Err("--trace-file expects a file".to_string()),
+                },
+                "--stats" => options.statistics = true,
+                "--profile" => options.profile = true,
+                "--quiet" => options.quiet = true,
+                "--debug" => options.debug = true,
+                "--label-operands" => options.label_operands = true,
+                "--history" => match iter.next() {
+                    Some(x) => options.history = Some(PathBuf::from(x)),
+                    None => return Err("--history expects a file".to_string()),
+                },
+                "--resume" => match iter.next() {
+                    Some(x) => options.resume = Some(PathBuf::from(x)),
+                    None => return Err("--resume expects a file".to_string()),
+                },
+                "-h" | "--help" => options.mode = Mode::Help,
+                x if x.starts_with("--") => return Err(format!("unknown option {}", x)),
+                x => rest.push(x.to_string()),
+            }
+        }
+        if options.mode == Mode::Help {
+            return Ok(options);
+        }
+        let mut rest = rest.into_iter();
+        options.mode = match rest.next() {
+            None => Mode::Repl,
+            Some(x) if x == "repl" => match rest.next() {
+                None => Mode::Repl,
+                Some(y) => return Err(format!("unexpected argument {} for repl", y)),
+            },
+            Some(x) if x == "run" => {
+                let files: Vec<PathBuf> = rest.map(PathBuf::from).collect();
+                if files.is_empty() {
+                    return Err("run expects at least one file".to_string());
+                }
+                Mode::Run(files)
+            }
+            Some(x) if x == "exec" => {
+                let controller = match rest.next() {
+                    Some(y) => PathBuf::from(y),
+                    None => return Err("exec expects a controller file".to_string()),
+                };
+                let mut registers = vec![];
+                for item in rest {
+                    match item.find('=') {
+                        Some(i) if i > 0 => {
+                            registers.push((item[..i].to_string(), item[i + 1..].to_string()))
+                        }
+                        _ => return Err(format!("expected REG=VALUE, found {}", item)),
+                    }
+                }
+                Mode::Exec(controller, registers)
+            }
+            Some(x) if x == "assemble" => match (rest.next(), rest.next(), rest.next()) {
+                (Some(y), Some(z), None) => Mode::Assemble(PathBuf::from(y), PathBuf::from(z)),
+                _ => return Err("assemble expects a controller and an output file".to_string()),
+            },
+            Some(x) if x == "disassemble" => match (rest.next(), rest.next()) {
+                (Some(y), None) => Mode::Disassemble(PathBuf::from(y)),
+                _ => return Err("disassemble expects a bytecode file".to_string()),
+            },
+            Some(x) => {
+                let mut files = vec![PathBuf::from(x)];
+                files.extend(rest.map(PathBuf::from));
+                Mode::Run(files)
+            }
+        };
+        Ok(options)
+    }
+
+    fn parse_number(option: &str, value: Option<&String>) -> Result<usize, String> {
+        match value.map(|x| x.parse::<usize>()) {
+            Some(Ok(n)) if n > 0 => Ok(n),
+            _ => Err(format!("{} expects a positive number", option)),
+        }
+    }
+
+    // the program of the binary for the arguments after the program name, see
+    // USAGE for the modes and options. The exit status is returned: 2 for usage
+    // errors and 1 for errors while running. The errors are printed by the modes
+    // themselves, so the panic hook is set to keep quiet.
+    pub fn run_cli(args: &[String]) -> i32 {
+        let options = match parse_args(args) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("{}\n{}", e, USAGE);
+                return 2;
+            }
+        };
+        match run_mode(&options) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        }
+    }
+
+    fn run_mode(options: &Options) -> Result<(), String> {
+        let mut memory = Memory::new(options.heap_size);
+        match options.mode {
+            Mode::Help => println!("{}", USAGE),
+            Mode::Repl => {
+                let mut machine = start_evaluator(options, &mut memory)?;
+                configure(&mut machine, options)?;
+                let history = match options.history {
+                    Some(ref x) => Some(x.clone()),
+                    None => default_history_file(),
+                };
+                machine.reader = Reader::interactive(history);
+                if options.quiet {
+                    machine.reader.prompt = "".to_string();
+                    machine.reader.continuation_prompt = "".to_string();
+                }
+                // errors are reported by run_repl
+                panic::set_hook(Box::new(|_info| {}));
+                let result = run_repl(&mut machine, &mut memory);
+                if options.profile {
+                    print!("{}", machine.profile_report());
+                }
+                result?;
+            }
+            Mode::Run(ref files) => {
+                let mut machine = start_evaluator(options, &mut memory)?;
+                configure(&mut machine, options)?;
+                // errors are reported by run_file, the default panic message is not needed
+                panic::set_hook(Box::new(|_info| {}));
+                for file in files {
+                    run_file(file, &mut machine, &mut memory)?;
+                }
+                if options.statistics {
+                    machine.stack.statistics();
+                }
+                if options.profile {
+                    print!("{}", machine.profile_report());
+                }
+            }
+            Mode::Assemble(ref controller, ref output) => {
+                let mut machine = BasicMachine::new();
+                machine.initilize_registers();
+                machine.initialize_op();
+                configure(&mut machine, options)?;
+                panic::set_hook(Box::new(|_info| {}));
+                assemble_file(controller, output, &mut machine, &mut memory)?;
+            }
+            Mode::Disassemble(ref path) => print!("{}", read_bytecode(path)?.disassemble()?),
+            Mode::Exec(ref controller, ref registers) => {
+                let mut machine = BasicMachine::new();
+                machine.initilize_registers();
+                machine.initialize_op();
+                configure(&mut machine, options)?;
+                panic::set_hook(Box::new(|_info| {}));
+                let result = if options.debug {
+                    machine.reader = Reader::interactive(None);
+                    debug_controller(controller, registers, &mut machine, &mut memory)
+                } else {
+                    exec_controller(controller, registers, &mut machine, &mut memory).map(|_x| true)
+                };
+                // false if the user quit the debugger before the end
+                if !result? {
+                    return Ok(());
+                }
+                if !options.quiet {
+                    let mut names: Vec<String> = registers.iter().map(|x| x.0.clone()).collect();
+                    if !names.contains(&"val".to_string()) {
+                        names.push("val".to_string());
+                    }
+                    for (name, value) in register_dump(&names, &machine, &memory) {
+                        println!("{} = {}", name, value);
+                    }
+                }
+                if options.statistics {
+                    machine.stack.statistics();
+                }
+                if options.profile {
+                    print!("{}", machine.profile_report());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the evaluator, or the one saved by ,save when the session is resumed
+    fn start_evaluator(options: &Options, memory: &mut Memory) -> Result<BasicMachine, String> {
+        let path = match options.resume {
+            Some(ref x) => x,
+            None => return Ok(make_evaluator(memory)),
+        };
+        let mut machine = BasicMachine::new();
+        machine.initialize_op();
+        load_snapshot(path, &mut machine, memory)?;
+        Ok(machine)
+    }
+
+    fn configure(machine: &mut BasicMachine, options: &Options) -> Result<(), String> {
+        machine.stack = Stack::with_discipline(options.stack_limit, options.stack_discipline);
+        machine.trace = options.trace;
+        if let Some(ref path) = options.trace_file {
+            match JsonLinesSink::create(path) {
+                Ok(sink) => machine.set_trace_sink(Box::new(sink)),
+                Err(e) => return Err(format!("can't create {}: {}", path.display(), e)),
+            }
+        }
+        machine.statistics = options.statistics;
+        machine.profiling = options.profile;
+        // a resumed session keeps the flags it was saved with
+        machine.label_operands |= options.label_operands;
+        machine.quiet |= options.quiet;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::cli::{parse_args, Mode, Options};
+    use crate::infrastructure::stack::StackDiscipline;
+    use std::path::PathBuf;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_args_works() {
+        assert_eq!(parse_args(&args("")), Ok(Options::new()));
+        let options =
+            parse_args(&args("--quiet repl --heap 100 --history h --resume s.rms")).unwrap();
+        assert_eq!(options.mode, Mode::Repl);
+        assert_eq!(options.history, Some(PathBuf::from("h")));
+        assert_eq!(options.resume, Some(PathBuf::from("s.rms")));
+        assert_eq!(options.heap_size, 100);
+        assert!(options.quiet);
+        let options = parse_args(&args("a.scm b.scm --stats --profile")).unwrap();
+        assert_eq!(
+            options.mode,
+            Mode::Run(vec![PathBuf::from("a.scm"), PathBuf::from("b.scm")])
+        );
+        assert!(options.statistics);
+        assert!(options.profile);
+        let options = parse_args(&args(
+            "--trace exec fact.rm n=5 --stack 10 --debug --label-operands",
+        ))
+        .unwrap();
+        assert_eq!(
+            options.mode,
+            Mode::Exec(
+                PathBuf::from("fact.rm"),
+                vec![("n".to_string(), "5".to_string())]
+            )
+        );
+        assert_eq!(options.stack_limit, 10);
+        assert!(options.debug);
+        assert!(options.label_operands);
+        assert!(options.trace);
+        let options = parse_args(&args("--trace-file t.jsonl a.scm")).unwrap();
+        assert_eq!(options.trace_file, Some(PathBuf::from("t.jsonl")));
+        assert!(options.trace);
+        let options = parse_args(&args("--stack-discipline per-register a.scm")).unwrap();
+        assert_eq!(options.stack_discipline, StackDiscipline::PerRegister);
+        let options = parse_args(&args("assemble fact.rm fact.rmb")).unwrap();
+        assert_eq!(
+            options.mode,
+            Mode::Assemble(PathBuf::from("fact.rm"), PathBuf::from("fact.rmb"))
+        );
+        let options = parse_args(&args("disassemble fact.rmb")).unwrap();
+        assert_eq!(options.mode, Mode::Disassemble(PathBuf::from("fact.rmb")));
+    }
+
+    #[test]
+    fn parse_args_rejects_bad_input() {
+        assert!(parse_args(&args("--heap")).is_err());
+        assert!(parse_args(&args("--heap zero")).is_err());
+        assert!(parse_args(&args("--verbose")).is_err());
+        assert!(parse_args(&args("--history")).is_err());
+        assert!(parse_args(&args("--resume")).is_err());
+        assert!(parse_args(&args("--stack-discipline lifo")).is_err());
+        assert!(parse_args(&args("run")).is_err());
+        assert!(parse_args(&args("exec")).is_err());
+        assert!(parse_args(&args("exec fact.rm n")).is_err());
+        assert!(parse_args(&args("repl a.scm")).is_err());
+        assert!(parse_args(&args("assemble fact.rm")).is_err());
+        assert!(parse_args(&args("disassemble a b")).is_err());
+    }
+}
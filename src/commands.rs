@@ -0,0 +1,270 @@
+pub mod commands {
+    use crate::datum::datum::{materialize, read_datum, Datum, HeapBackend};
+    use crate::gc::garbage_collector::stop_and_copy;
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::parserfordev::parser::exp_to_str;
+    use crate::representation::type_system::Object;
+    use crate::snapshot::snapshot::save_snapshot;
+    use std::path::Path;
+
+    const HELP: &str = "REPL commands:
+    ,regs          contents of the registers
+    ,stack         depth and top of the stack
+    ,env           the global environment
+    ,heap          the cells of the heap in use
+    ,info          instructions, entry points, saved registers and sources of
+                   each register in the controller
+    ,stats         stack statistics of the last evaluation
+    ,profile       instructions per label and time per op of the last evaluation
+    ,profile on|off
+                   time the calls of ops
+    ,trace on|off  print each instruction and the registers it changes
+    ,trace REG on|off
+                   report every change of the register
+    ,watch REG X   pause when the register is set to the atom X, lists
+                   can't be watched
+    ,unwatch REG   remove the watchpoints of the register
+    ,break LABEL N stop before the N-th instruction after the label
+    ,unbreak LABEL N
+                   cancel that breakpoint, ,unbreak alone cancels all
+    ,gc            collect garbage in the heap, only at the top level
+    ,reset         forget all definitions and empty the heap
+    ,save FILE     save the session, machine --resume FILE goes on with it
+    ,help          this message";
+
+    // registers holding a list in memory, the ones that are cleared by ,reset
+    const LIST_REGISTERS: [&str; 7] = ["exp", "env", "val", "unev", "argl", "proc", "benv"];
+
+    // a line read by the REPL that starts with ',' is a command for inspecting
+    // the machine instead of a Scheme form
+    pub fn is_command(form: &str) -> bool {
+        form.starts_with(',')
+    }
+
+    // commands are run by the read op between two top-level forms, when the stack
+    // has just been initialized, and by the debugger in the middle of an
+    // evaluation
+    pub fn run_command(command: &str, machine: &mut BasicMachine, memory: &mut Memory) {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            [",regs"] => print_registers(machine, memory),
+            [",stack"] => {
+                println!("{}", machine.stack);
+                let mut names: Vec<&String> = machine.stacks.keys().collect();
+                names.sort();
+                for name in names {
+                    println!("{}: {}", name, machine.stacks[name]);
+                }
+            }
+            [",env"] => machine.print_register_contents(&"env".to_string(), memory),
+            [",heap"] => {
+                let free = machine.get_register(&"free".to_string()).unwrap();
+                let free = free.get_memory_index();
+                println!("{} of {} pairs in use", free, memory.capacity());
+                println!("{}", memory.live_cells(free));
+            }
+            [",info"] => print!("{}", machine.info),
+            [",stats"] => machine.stack.statistics(),
+            [",profile"] => print!("{}", machine.profile_report()),
+            [",profile", "on"] => machine.profiling = true,
+            [",profile", "off"] => machine.profiling = false,
+            [",trace", "on"] => machine.trace_on(),
+            [",trace", "off"] => machine.trace_off(),
+            [",trace", name, "on"] => {
+                if has_register(machine, name) {
+                    machine.trace_register_on(&name.to_string());
+                }
+            }
+            [",trace", name, "off"] => {
+                if has_register(machine, name) {
+                    machine.trace_register_off(&name.to_string());
+                }
+            }
+            [",watch", name, value @ ..] if !value.is_empty() => {
+                if has_register(machine, name) {
+                    match watch_value(&value.join(" "), machine, memory) {
+                        Some(value) => machine
+                            .add_watchpoint(&name.to_string(), Box::new(move |x| *x == value)),
+                        None => {
+                            println!("Usage: ,watch REG X, where X is an atom such as 3 or done")
+                        }
+                    }
+                }
+            }
+            [",unwatch", name] => machine.remove_watchpoints(&name.to_string()),
+            [",break", label, n] => {
+                if let Some(offset) = breakpoint_offset(machine, label, n) {
+                    machine.set_breakpoint(&label.to_string(), offset);
+                }
+            }
+            [",unbreak", label, n] => {
+                if let Some(offset) = breakpoint_offset(machine, label, n) {
+                    machine.cancel_breakpoint(&label.to_string(), offset);
+                }
+            }
+            [",unbreak"] => machine.cancel_all_breakpoints(),
+            [",gc"] => {
+                // stop_and_copy doesn't scan the stacks, what is saved there would
+                // be left pointing into the old heap
+                if !machine.stack.is_empty() || machine.stacks.values().any(|x| !x.is_empty()) {
+                    println!("The heap is collected only at the top level, not in an evaluation");
+                    return;
+                }
+                let before = machine.get_register(&"free".to_string()).unwrap();
+                let before = before.get_memory_index();
+                let alive = stop_and_copy(machine, memory);
+                println!("{} pairs alive, {} reclaimed", alive, before - alive);
+            }
+            [",reset"] => reset(machine, memory),
+            [",save", path] => match save_snapshot(Path::new(path), machine, memory) {
+                Ok(()) => println!("Saved to {}", path),
+                Err(e) => println!("{}", e),
+            },
+            [",help"] => println!("{}", HELP),
+            _ => println!("Unknown command {}\n{}", command, HELP),
+        }
+    }
+
+    fn has_register(machine: &BasicMachine, name: &str) -> bool {
+        if machine.get_register(&name.to_string()).is_none() {
+            println!("No such register {}", name);
+            return false;
+        }
+        true
+    }
+
+    // a list in a register is an index into the heap, so only atoms are watched
+    fn watch_value(text: &str, machine: &mut BasicMachine, memory: &mut Memory) -> Option<Object> {
+        match read_datum(text) {
+            Ok(Datum::List(_)) | Err(_) => None,
+            Ok(x) => materialize(&x, &mut HeapBackend { machine, memory }).ok(),
+        }
+    }
+
+    // offsets count from 1, as in SICP's (set-breakpoint machine label n)
+    fn breakpoint_offset(machine: &BasicMachine, label: &str, n: &str) -> Option<usize> {
+        let offset = match n.parse::<usize>() {
+            Ok(x) => x,
+            Err(_e) => {
+                println!("{} is not an offset", n);
+                return None;
+            }
+        };
+        match machine.labels.get(label) {
+            Some(x) if offset > 0 && x + offset <= machine.raw_instructions.len() => Some(offset),
+            Some(_x) => {
+                println!("No instruction at {}+{}", label, offset);
+                None
+            }
+            None => {
+                println!("Unknown label {}", label);
+                None
+            }
+        }
+    }
+
+    fn print_registers(machine: &BasicMachine, memory: &Memory) {
+        let mut names: Vec<&String> = machine.registers.keys().collect();
+        names.sort();
+        for name in names {
+            match machine.get_register_contents(name).unwrap() {
+                Object::Quote(x) if x == "unsigned" => {}
+                Object::Index(i) if name == "pc" || name == "continue" => {
+                    match label_at(machine, i) {
+                        Some(label) => println!("{} = {} ({})", name, i, label),
+                        None => println!("{} = {}", name, i),
+                    }
+                }
+                Object::Index(_i) if LIST_REGISTERS.contains(&name.as_str()) => {
+                    print!("{} = ", name);
+                    machine.print_register_contents(name, memory);
+                }
+                Object::Index(i) => println!("{} = {}", name, i),
+                x => println!("{} = {}", name, exp_to_str(x.object_to_exp())),
+            }
+        }
+    }
+
+    // the first label, in alphabetical order, that points to the instruction
+    fn label_at(machine: &BasicMachine, index: usize) -> Option<String> {
+        let mut labels: Vec<&String> = machine
+            .labels
+            .iter()
+            .filter(|(_name, i)| **i == index)
+            .map(|(name, _i)| name)
+            .collect();
+        labels.sort();
+        labels.first().map(|x| (*x).clone())
+    }
+
+    // a fresh global environment written to an empty heap
+    fn reset(machine: &mut BasicMachine, memory: &mut Memory) {
+        for name in LIST_REGISTERS.iter() {
+            machine.set_register_contents(&name.to_string(), Object::Quote("unsigned".to_string()));
+        }
+        machine.stack.initialize();
+        machine.loading.clear();
+        machine.set_register_contents(&"free".to_string(), Object::Index(0));
+        machine.initialize_env(memory);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::commands::{is_command, run_command};
+    use crate::memory::memory::Memory;
+    use crate::representation::type_system::Object;
+    use crate::runner::runner::{eval_str, make_evaluator};
+
+    #[test]
+    fn run_command_works() {
+        let mut memory = Memory::new(5000);
+        let mut machine = make_evaluator(&mut memory);
+        assert!(is_command(",trace on"));
+        assert!(!is_command("(car x)"));
+        run_command(",trace on", &mut machine, &mut memory);
+        assert!(machine.trace);
+        run_command(",trace off", &mut machine, &mut memory);
+        assert!(!machine.trace);
+        run_command(",trace val on", &mut machine, &mut memory);
+        assert!(machine.get_register(&"val".to_string()).unwrap().traced);
+        run_command(",trace val off", &mut machine, &mut memory);
+        assert!(!machine.get_register(&"val".to_string()).unwrap().traced);
+        run_command(",watch val 3", &mut machine, &mut memory);
+        assert_eq!(machine.watchpoints.len(), 1);
+        // lists can't be watched
+        run_command(",watch val (1 2)", &mut machine, &mut memory);
+        run_command(",watch val (1", &mut machine, &mut memory);
+        assert_eq!(machine.watchpoints.len(), 1);
+        run_command(",unwatch val", &mut machine, &mut memory);
+        assert!(machine.watchpoints.is_empty());
+        run_command(",profile on", &mut machine, &mut memory);
+        assert!(machine.profiling);
+        run_command(",break eval-dispatch 1", &mut machine, &mut memory);
+        run_command(",break nowhere 1", &mut machine, &mut memory);
+        assert_eq!(machine.breakpoints.len(), 1);
+        run_command(",unbreak eval-dispatch 1", &mut machine, &mut memory);
+        assert!(machine.breakpoints.is_empty());
+        let r = eval_str("(define (square x) (* x x))", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Quote("ok".to_string())));
+        let free = machine.get_register(&"free".to_string()).unwrap();
+        let before = free.get_memory_index();
+        // in the middle of an evaluation the heap is left as it is
+        machine.stack.push_from("env", Object::Pair(0));
+        run_command(",gc", &mut machine, &mut memory);
+        let free = machine.get_register(&"free".to_string()).unwrap();
+        assert_eq!(free.get_memory_index(), before);
+        assert_eq!(machine.stack.pop_into("env"), Object::Pair(0));
+        run_command(",gc", &mut machine, &mut memory);
+        let free = machine.get_register(&"free".to_string()).unwrap();
+        assert!(free.get_memory_index() < before);
+        let r = eval_str("(square 5)", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Integer(25)));
+        run_command(",reset", &mut machine, &mut memory);
+        let r = eval_str("(square 5)", &mut machine, &mut memory);
+        assert_eq!(r, Err("Error: unbound variable square".to_string()));
+        let r = eval_str("(+ 2 3)", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Integer(5)));
+    }
+}
@@ -0,0 +1,841 @@
+pub mod compiler {
+    use crate::parserfordev::parser::display_exp;
+    use crate::primitives::primitives::{
+        assignment_value, assignment_variable, begin_actions, definition_value,
+        definition_variable, if_alternative, if_consequent, if_predicate, is_application,
+        is_assignment, is_begin, is_definition, is_if, is_lambda, is_self_evaluating, is_variable,
+        lambda_body, lambda_parameters, operands, operator,
+    };
+    use crate::tpfordev::type_system::{car, cdr, scheme_cons, Exp, Pair};
+    use std::collections::BTreeSet;
+
+    // the compiler of SICP 5.5, Scheme expressions are turned into instructions
+    // for the machine of the evaluator, using its registers and ops.
+    //
+    // Environments are values in this machine, define-variable! gives back a new
+    // one, and procedures are applied in the environment of their caller, just like
+    // the procedures of the evaluator. A caller that saved env around a call would
+    // lose the definitions made after the save, so a compiled procedure restores
+    // the environment of its caller before it returns instead. Definitions and
+    // assignments aren't counted as modifying env for the same reason, and no
+    // instruction sequence ever has to preserve it.
+    //
+    // For the same reason only the frame made on entry to a compiled procedure is
+    // known to be there at run time, the frames after it belong to whoever called
+    // it and not to the procedures the lambda is written in. The compile-time
+    // environment has the frames of all of them, but only the variables of the
+    // first one get lexical addresses, SICP exercise 5.42: the parameters and
+    // the internal definitions, which are scanned out and made in the frame on
+    // entry, SICP exercise 5.43. The variables of the enclosing procedures and
+    // the global ones are looked up by name, at run time that finds the binding
+    // of the caller.
+
+    // where to go on after the value of an expression has been computed, SICP 5.5.1
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Linkage {
+        Next,
+        Return,
+        Label(String),
+    }
+
+    // SICP 5.5.4, statements are labels and instructions as they are written in a
+    // controller
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct InstructionSequence {
+        pub needs: BTreeSet<&'static str>,
+        pub modifies: BTreeSet<&'static str>,
+        pub statements: Vec<String>,
+    }
+
+    impl InstructionSequence {
+        fn new(needs: &[&'static str], modifies: &[&'static str], statements: Vec<String>) -> Self {
+            InstructionSequence {
+                needs: needs.iter().copied().collect(),
+                modifies: modifies.iter().copied().collect(),
+                statements,
+            }
+        }
+
+        fn empty() -> Self {
+            InstructionSequence::default()
+        }
+
+        fn label(name: &str) -> Self {
+            InstructionSequence::new(&[], &[], vec![name.to_string()])
+        }
+
+        // the statements one per line with the instructions indented, the way the
+        // controllers are written
+        pub fn to_text(&self) -> String {
+            let lines: Vec<String> = self
+                .statements
+                .iter()
+                .map(|x| {
+                    if x.starts_with('(') {
+                        format!("  {}", x)
+                    } else {
+                        x.clone()
+                    }
+                })
+                .collect();
+            lines.join("\n")
+        }
+    }
+
+    // env is left out, see above
+    const ALL_REGISTERS: [&str; 6] = ["proc", "val", "argl", "continue", "arg1", "arg2"];
+
+    // primitives that are applied with a single op on arg1 and arg2 instead of a
+    // call, SICP exercise 5.38
+    const OPEN_CODED: [&str; 7] = ["+", "-", "*", "/", "=", "<", ">"];
+
+    pub struct Compiler {
+        label_counter: usize,
+        // the compile-time environment, SICP 5.5.6, the innermost frame first
+        frames: Vec<Vec<String>>,
+    }
+
+    impl Compiler {
+        // labels are numbered from first_label on, code that is compiled for the
+        // same machine more than once needs labels that haven't been used yet
+        pub fn new(first_label: usize) -> Self {
+            Compiler {
+                label_counter: first_label,
+                frames: vec![],
+            }
+        }
+
+        fn make_label(&mut self, name: &str) -> String {
+            self.label_counter += 1;
+            format!("{}{}", name, self.label_counter)
+        }
+
+        // the top-level forms of a program, the value of the last one is left in
+        // val and the program returns to continue. The program starts at the
+        // label that is returned as well.
+        #[allow(dead_code)]
+        pub fn compile_program(&mut self, forms: &[Exp]) -> (String, InstructionSequence) {
+            let entry = self.make_label("program");
+            let mut program = InstructionSequence::label(&entry);
+            if forms.is_empty() {
+                let code = InstructionSequence::new(
+                    &["continue"],
+                    &["val"],
+                    vec![
+                        "(assign val (const 'ok))".to_string(),
+                        "(goto (reg continue))".to_string(),
+                    ],
+                );
+                return (entry, append(program, code));
+            }
+            program = append(
+                program,
+                self.compile_sequence(forms, "val", &Linkage::Return),
+            );
+            (entry, program)
+        }
+
+        pub fn compile(
+            &mut self,
+            exp: &Exp,
+            target: &'static str,
+            linkage: &Linkage,
+        ) -> InstructionSequence {
+            match exp {
+                x if is(is_self_evaluating, x) => compile_self_evaluating(x, target, linkage),
+                x if is(is_variable, x) => self.compile_variable(x, target, linkage),
+                x if is(is_assignment, x) => self.compile_assignment(x, target, linkage),
+                x if is(is_definition, x) => self.compile_definition(x, target, linkage),
+                x if is(is_if, x) => self.compile_if(x, target, linkage),
+                x if is(is_lambda, x) => self.compile_lambda(x, target, linkage),
+                x if is(is_begin, x) => {
+                    let actions = list_items(&syntax(begin_actions, x));
+                    self.compile_sequence(&actions, target, linkage)
+                }
+                x if is(is_application, x) && self.is_open_coded(x) => {
+                    self.compile_open_coded(x, target, linkage)
+                }
+                x if is(is_application, x) => self.compile_application(x, target, linkage),
+                x => panic!("Error: unknown expression type: COMPILE {}", display_exp(x)),
+            }
+        }
+
+        fn compile_variable(
+            &mut self,
+            exp: &Exp,
+            target: &'static str,
+            linkage: &Linkage,
+        ) -> InstructionSequence {
+            let variable = display_exp(exp);
+            let (op, operand) = match self.find_variable(&variable) {
+                Some((frame, offset)) => {
+                    ("lexical-address-lookup", format!("({} {})", frame, offset))
+                }
+                None => ("lookup-variable-value", variable),
+            };
+            let code = InstructionSequence::new(
+                &["env"],
+                &[target],
+                vec![format!(
+                    "(assign {} (op {}) (const {}) (reg env))",
+                    target, op, operand
+                )],
+            );
+            end_with_linkage(linkage, code)
+        }
+
+        // SICP exercise 5.41, only the frame of the procedure being compiled is
+        // searched, see above
+        fn find_variable(&self, variable: &str) -> Option<(usize, usize)> {
+            let offset = self.frames.first()?.iter().position(|x| x == variable)?;
+            Some((0, offset))
+        }
+
+        // (set! x 5), the new environment replaces the old one
+        fn compile_assignment(
+            &mut self,
+            exp: &Exp,
+            target: &'static str,
+            linkage: &Linkage,
+        ) -> InstructionSequence {
+            let variable = display_exp(&syntax(assignment_variable, exp));
+            let value = self.compile(&syntax(assignment_value, exp), "val", &Linkage::Next);
+            match self.find_variable(&variable) {
+                Some((frame, offset)) => {
+                    let address = format!("({} {})", frame, offset);
+                    self.compile_binding("lexical-address-set!", &address, value, target, linkage)
+                }
+                None => {
+                    self.compile_binding("set-variable-value!", &variable, value, target, linkage)
+                }
+            }
+        }
+
+        fn compile_definition(
+            &mut self,
+            exp: &Exp,
+            target: &'static str,
+            linkage: &Linkage,
+        ) -> InstructionSequence {
+            let variable = display_exp(&syntax(definition_variable, exp));
+            let value = self.compile(&syntax(definition_value, exp), "val", &Linkage::Next);
+            // an internal definition was scanned out, it has a place in the frame
+            match self.find_variable(&variable) {
+                Some((frame, offset)) => {
+                    let address = format!("({} {})", frame, offset);
+                    self.compile_binding("lexical-address-set!", &address, value, target, linkage)
+                }
+                None => self.compile_binding("define-variable!", &variable, value, target, linkage),
+            }
+        }
+
+        fn compile_binding(
+            &mut self,
+            op: &str,
+            variable: &str,
+            value: InstructionSequence,
+            target: &'static str,
+            linkage: &Linkage,
+        ) -> InstructionSequence {
+            let code = InstructionSequence::new(
+                &["env", "val"],
+                &[target],
+                vec![
+                    format!(
+                        "(assign env (op {}) (const {}) (reg val) (reg env))",
+                        op, variable
+                    ),
+                    format!("(assign {} (const 'ok))", target),
+                ],
+            );
+            end_with_linkage(linkage, preserving(&["env"], value, code))
+        }
+
+        // true? is the test of the machine, so the consequent comes after the
+        // alternative, the other way round than in SICP
+        fn compile_if(
+            &mut self,
+            exp: &Exp,
+            target: &'static str,
+            linkage: &Linkage,
+        ) -> InstructionSequence {
+            let t_branch = self.make_label("true-branch");
+            let f_branch = self.make_label("false-branch");
+            let after_if = self.make_label("after-if");
+            let alternative_linkage = match linkage {
+                Linkage::Next => Linkage::Label(after_if.clone()),
+                x => x.clone(),
+            };
+            let p_code = self.compile(&syntax(if_predicate, exp), "val", &Linkage::Next);
+            let c_code = self.compile(&syntax(if_consequent, exp), target, linkage);
+            let a_code = self.compile(&syntax(if_alternative, exp), target, &alternative_linkage);
+            let test = InstructionSequence::new(
+                &["val"],
+                &[],
+                vec![
+                    "(test (op true?) (reg val))".to_string(),
+                    format!("(branch (label {}))", t_branch),
+                ],
+            );
+            let branches = parallel(
+                append(InstructionSequence::label(&f_branch), a_code),
+                append(InstructionSequence::label(&t_branch), c_code),
+            );
+            preserving(
+                &["env", "continue"],
+                p_code,
+                append(
+                    append(test, branches),
+                    InstructionSequence::label(&after_if),
+                ),
+            )
+        }
+
+        fn compile_sequence(
+            &mut self,
+            exps: &[Exp],
+            target: &'static str,
+            linkage: &Linkage,
+        ) -> InstructionSequence {
+            match exps {
+                [] => panic!("Error: empty sequence: COMPILE"),
+                [x] => self.compile(x, target, linkage),
+                [x, rest @ ..] => {
+                    let first = self.compile(x, target, &Linkage::Next);
+                    let rest = self.compile_sequence(rest, target, linkage);
+                    preserving(&["env", "continue"], first, rest)
+                }
+            }
+        }
+
+        fn compile_lambda(
+            &mut self,
+            exp: &Exp,
+            target: &'static str,
+            linkage: &Linkage,
+        ) -> InstructionSequence {
+            let entry = self.make_label("entry");
+            let after_lambda = self.make_label("after-lambda");
+            let lambda_linkage = match linkage {
+                Linkage::Next => Linkage::Label(after_lambda.clone()),
+                x => x.clone(),
+            };
+            let code = InstructionSequence::new(
+                &[],
+                &[target],
+                vec![format!(
+                    "(assign {} (op make-compiled-procedure) (label {}))",
+                    target, entry
+                )],
+            );
+            let body = self.compile_lambda_body(exp, &entry);
+            append(
+                tack_on(end_with_linkage(&lambda_linkage, code), body),
+                InstructionSequence::label(&after_lambda),
+            )
+        }
+
+        // the environment of the caller is saved on entry and restored before the
+        // value is returned, see above
+        fn compile_lambda_body(&mut self, exp: &Exp, entry: &str) -> InstructionSequence {
+            let parameters = syntax(lambda_parameters, exp);
+            let body = list_items(&syntax(lambda_body, exp));
+            let mut statements = vec![
+                entry.to_string(),
+                "(save env)".to_string(),
+                format!(
+                    "(assign env (op extend-environment) (const {}) (reg argl) (reg env))",
+                    display_exp(&parameters)
+                ),
+            ];
+            let mut frame: Vec<String> = list_items(&parameters).iter().map(display_exp).collect();
+            // the internal definitions are unassigned until their define is run,
+            // one of a parameter sets the parameter
+            for variable in scan_out_defines(&body) {
+                if !frame.contains(&variable) {
+                    statements.push(format!(
+                        "(assign env (op define-variable!) (const {}) (const *unassigned*) (reg env))",
+                        variable
+                    ));
+                    frame.push(variable);
+                }
+            }
+            let code = InstructionSequence::new(&["env", "argl"], &["env"], statements);
+            self.frames.insert(0, frame);
+            let body = self.compile_sequence(&body, "val", &Linkage::Next);
+            self.frames.remove(0);
+            let exit = InstructionSequence::new(
+                &["continue"],
+                &[],
+                vec![
+                    "(restore env)".to_string(),
+                    "(goto (reg continue))".to_string(),
+                ],
+            );
+            append(code, preserving(&["continue"], body, exit))
+        }
+
+        fn compile_application(
+            &mut self,
+            exp: &Exp,
+            target: &'static str,
+            linkage: &Linkage,
+        ) -> InstructionSequence {
+            let proc_code = self.compile(&syntax(operator, exp), "proc", &Linkage::Next);
+            let operand_codes: Vec<InstructionSequence> = list_items(&syntax(operands, exp))
+                .iter()
+                .map(|x| self.compile(x, "val", &Linkage::Next))
+                .collect();
+            preserving(
+                &["env", "continue"],
+                proc_code,
+                preserving(
+                    &["proc", "continue"],
+                    construct_arglist(operand_codes),
+                    self.compile_procedure_call(target, linkage),
+                ),
+            )
+        }
+
+        // the operator is one of OPEN_CODED and not a variable of the compile-time
+        // environment, SICP exercise 5.44. Only - and the comparisons need exactly
+        // two operands, + and * take two or more.
+        fn is_open_coded(&self, exp: &Exp) -> bool {
+            let operator = match syntax(operator, exp) {
+                Exp::Symbol(x) => x,
+                _ => return false,
+            };
+            let count = list_items(&syntax(operands, exp)).len();
+            OPEN_CODED.contains(&operator.as_str())
+                && !self.frames.iter().any(|x| x.contains(&operator))
+                && (count == 2 || (count > 2 && (operator == "+" || operator == "*")))
+        }
+
+        // the operands go to arg1 and arg2, with more than two operands the
+        // result so far is kept in arg1
+        fn compile_open_coded(
+            &mut self,
+            exp: &Exp,
+            target: &'static str,
+            linkage: &Linkage,
+        ) -> InstructionSequence {
+            let op = display_exp(&syntax(operator, exp));
+            let operands = list_items(&syntax(operands, exp));
+            let mut code = self.compile(&operands[0], "arg1", &Linkage::Next);
+            for (i, operand) in operands.iter().enumerate().skip(1) {
+                let result = if i + 1 == operands.len() {
+                    target
+                } else {
+                    "arg1"
+                };
+                let operand_code = self.compile(operand, "arg2", &Linkage::Next);
+                let apply = InstructionSequence::new(
+                    &["arg1", "arg2"],
+                    &[result],
+                    vec![format!(
+                        "(assign {} (op {}) (reg arg1) (reg arg2))",
+                        result, op
+                    )],
+                );
+                code = preserving(&["env"], code, preserving(&["arg1"], operand_code, apply));
+            }
+            end_with_linkage(linkage, code)
+        }
+
+        // compiled procedures are entered directly, primitives are applied right
+        // here and anything else such as load or a procedure of the evaluator is
+        // handed to apply-dispatch of the evaluator, SICP exercise 5.47
+        fn compile_procedure_call(
+            &mut self,
+            target: &'static str,
+            linkage: &Linkage,
+        ) -> InstructionSequence {
+            let primitive_branch = self.make_label("primitive-branch");
+            let compiled_branch = self.make_label("compiled-branch");
+            let interpreted_branch = self.make_label("interpreted-branch");
+            let after_call = self.make_label("after-call");
+            let call_linkage = match linkage {
+                Linkage::Next => Linkage::Label(after_call.clone()),
+                x => x.clone(),
+            };
+            let tests = InstructionSequence::new(
+                &["proc"],
+                &[],
+                vec![
+                    "(test (op load-procedure?) (reg proc))".to_string(),
+                    format!("(branch (label {}))", interpreted_branch),
+                    "(test (op primitive-procedure?) (reg proc))".to_string(),
+                    format!("(branch (label {}))", primitive_branch),
+                    "(test (op compiled-procedure?) (reg proc))".to_string(),
+                    format!("(branch (label {}))", compiled_branch),
+                ],
+            );
+            let primitive = end_with_linkage(
+                linkage,
+                InstructionSequence::new(
+                    &["proc", "argl"],
+                    &[target],
+                    vec![format!(
+                        "(assign {} (op meta-apply-primitive-procedure) (reg proc) (reg argl))",
+                        target
+                    )],
+                ),
+            );
+            let compiled = self.compile_proc_appl(target, &call_linkage, false);
+            let interpreted = self.compile_proc_appl(target, &call_linkage, true);
+            let branches = parallel(
+                append(InstructionSequence::label(&interpreted_branch), interpreted),
+                parallel(
+                    append(InstructionSequence::label(&compiled_branch), compiled),
+                    append(InstructionSequence::label(&primitive_branch), primitive),
+                ),
+            );
+            append(
+                append(tests, branches),
+                InstructionSequence::label(&after_call),
+            )
+        }
+
+        // SICP 5.5.3, apply-dispatch of the evaluator expects continue on the
+        // stack, compiled procedures take their entry from proc
+        fn compile_proc_appl(
+            &mut self,
+            target: &'static str,
+            linkage: &Linkage,
+            interpreted: bool,
+        ) -> InstructionSequence {
+            let jump = if interpreted {
+                vec![
+                    "(save continue)".to_string(),
+                    "(goto (label apply-dispatch))".to_string(),
+                ]
+            } else {
+                vec![
+                    "(assign val (op compiled-procedure-entry) (reg proc))".to_string(),
+                    "(goto (reg val))".to_string(),
+                ]
+            };
+            match linkage {
+                Linkage::Return if target == "val" => {
+                    InstructionSequence::new(&["proc", "continue"], &ALL_REGISTERS, jump)
+                }
+                Linkage::Return => {
+                    panic!("Error: return linkage, target not val: COMPILE {}", target)
+                }
+                Linkage::Label(x) if target == "val" => {
+                    let mut statements = vec![format!("(assign continue (label {}))", x)];
+                    statements.extend(jump);
+                    InstructionSequence::new(&["proc"], &ALL_REGISTERS, statements)
+                }
+                Linkage::Label(x) => {
+                    let proc_return = self.make_label("proc-return");
+                    let mut statements = vec![format!("(assign continue (label {}))", proc_return)];
+                    statements.extend(jump);
+                    statements.push(proc_return);
+                    statements.push(format!("(assign {} (reg val))", target));
+                    statements.push(format!("(goto (label {}))", x));
+                    InstructionSequence::new(&["proc"], &ALL_REGISTERS, statements)
+                }
+                Linkage::Next => panic!("Error: next linkage of a call: COMPILE"),
+            }
+        }
+    }
+
+    fn compile_self_evaluating(
+        exp: &Exp,
+        target: &'static str,
+        linkage: &Linkage,
+    ) -> InstructionSequence {
+        let code = InstructionSequence::new(
+            &[],
+            &[target],
+            vec![format!("(assign {} (const {}))", target, display_exp(exp))],
+        );
+        end_with_linkage(linkage, code)
+    }
+
+    // the operands are evaluated from left to right and adjoined to argl, the
+    // first one makes argl such that argl doesn't have to be saved around it
+    fn construct_arglist(operand_codes: Vec<InstructionSequence>) -> InstructionSequence {
+        let mut codes = operand_codes.into_iter();
+        let first = match codes.next() {
+            None => {
+                return InstructionSequence::new(
+                    &[],
+                    &["argl"],
+                    vec!["(assign argl (op empty-arglist))".to_string()],
+                )
+            }
+            Some(x) => append(
+                x,
+                InstructionSequence::new(
+                    &["val"],
+                    &["argl"],
+                    vec![
+                        "(assign argl (op empty-arglist))".to_string(),
+                        "(assign argl (op adjoin-arg) (reg val) (reg argl))".to_string(),
+                    ],
+                ),
+            ),
+        };
+        let rest: Vec<InstructionSequence> = codes
+            .map(|x| {
+                let adjoin = InstructionSequence::new(
+                    &["val", "argl"],
+                    &["argl"],
+                    vec!["(assign argl (op adjoin-arg) (reg val) (reg argl))".to_string()],
+                );
+                preserving(&["argl"], x, adjoin)
+            })
+            .collect();
+        let mut code = InstructionSequence::empty();
+        for x in rest.into_iter().rev() {
+            code = preserving(&["env"], x, code);
+        }
+        preserving(&["env"], first, code)
+    }
+
+    fn compile_linkage(linkage: &Linkage) -> InstructionSequence {
+        match linkage {
+            Linkage::Return => InstructionSequence::new(
+                &["continue"],
+                &[],
+                vec!["(goto (reg continue))".to_string()],
+            ),
+            Linkage::Next => InstructionSequence::empty(),
+            Linkage::Label(x) => {
+                InstructionSequence::new(&[], &[], vec![format!("(goto (label {}))", x)])
+            }
+        }
+    }
+
+    fn end_with_linkage(linkage: &Linkage, code: InstructionSequence) -> InstructionSequence {
+        preserving(&["continue"], code, compile_linkage(linkage))
+    }
+
+    // SICP 5.5.4, combining instruction sequences
+    fn append(a: InstructionSequence, b: InstructionSequence) -> InstructionSequence {
+        let mut needs = a.needs.clone();
+        needs.extend(b.needs.difference(&a.modifies).copied());
+        let mut modifies = a.modifies;
+        modifies.extend(b.modifies);
+        let mut statements = a.statements;
+        statements.extend(b.statements);
+        InstructionSequence {
+            needs,
+            modifies,
+            statements,
+        }
+    }
+
+    // the registers that a modifies and b needs are saved around a
+    fn preserving(
+        registers: &[&'static str],
+        a: InstructionSequence,
+        b: InstructionSequence,
+    ) -> InstructionSequence {
+        let mut a = a;
+        for register in registers.iter().copied() {
+            if a.modifies.contains(register) && b.needs.contains(register) {
+                let mut statements = vec![format!("(save {})", register)];
+                statements.append(&mut a.statements);
+                statements.push(format!("(restore {})", register));
+                a.needs.insert(register);
+                a.modifies.remove(register);
+                a.statements = statements;
+            }
+        }
+        append(a, b)
+    }
+
+    // the body of a procedure is not run where it appears
+    fn tack_on(code: InstructionSequence, body: InstructionSequence) -> InstructionSequence {
+        let mut code = code;
+        code.statements.extend(body.statements);
+        code
+    }
+
+    // either a or b is run
+    fn parallel(a: InstructionSequence, b: InstructionSequence) -> InstructionSequence {
+        let mut needs = a.needs;
+        needs.extend(b.needs);
+        let mut modifies = a.modifies;
+        modifies.extend(b.modifies);
+        let mut statements = a.statements;
+        statements.extend(b.statements);
+        InstructionSequence {
+            needs,
+            modifies,
+            statements,
+        }
+    }
+
+    // the variables of the definitions among the expressions of a body, SICP
+    // exercise 5.43
+    fn scan_out_defines(body: &[Exp]) -> Vec<String> {
+        body.iter()
+            .filter(|x| is(is_definition, x))
+            .map(|x| display_exp(&syntax(definition_variable, x)))
+            .collect()
+    }
+
+    // the syntax ops of the evaluator take their argument in a list
+    fn syntax(f: fn(&Exp) -> Exp, exp: &Exp) -> Exp {
+        f(&scheme_cons(exp.clone(), Exp::List(Pair::Nil)))
+    }
+
+    fn is(f: fn(&Exp) -> Exp, exp: &Exp) -> bool {
+        syntax(f, exp) == Exp::Bool(true)
+    }
+
+    fn list_items(exp: &Exp) -> Vec<Exp> {
+        let mut items = vec![];
+        let mut rest = exp.clone();
+        while rest.is_pair() {
+            items.push(car(&rest).unwrap());
+            rest = cdr(&rest).unwrap();
+        }
+        items
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::compiler::{Compiler, Linkage};
+    use crate::parserfordev::parser::str_to_exp;
+
+    fn compile(text: &str, target: &'static str, linkage: &Linkage) -> Vec<String> {
+        let exp = str_to_exp(text.to_string());
+        Compiler::new(0).compile(&exp, target, linkage).statements
+    }
+
+    #[test]
+    fn compile_works() {
+        assert_eq!(
+            compile("x", "val", &Linkage::Return),
+            vec![
+                "(assign val (op lookup-variable-value) (const x) (reg env))",
+                "(goto (reg continue))",
+            ]
+        );
+        assert_eq!(
+            compile("(define y 5)", "val", &Linkage::Next),
+            vec![
+                "(assign val (const 5))",
+                "(assign env (op define-variable!) (const y) (reg val) (reg env))",
+                "(assign val (const 'ok))",
+            ]
+        );
+        let code = compile("(if (< n 2) n 1)", "val", &Linkage::Next);
+        assert_eq!(code[code.len() - 3], "true-branch1");
+        assert_eq!(code.last().unwrap(), "after-if3");
+    }
+
+    #[test]
+    fn preserving_saves_only_what_is_needed() {
+        // only the interpreted branch saves continue for apply-dispatch when the
+        // operands are constants, proc and argl are saved around the call that
+        // computes the second operand
+        let code = compile("(f 1 2)", "val", &Linkage::Return);
+        let saves: Vec<&String> = code.iter().filter(|x| x.starts_with("(save")).collect();
+        assert_eq!(saves, vec!["(save continue)"]);
+        let code = compile("(f 1 (g 2))", "val", &Linkage::Return);
+        assert!(code.contains(&"(save proc)".to_string()));
+        assert!(code.contains(&"(save argl)".to_string()));
+        assert!(!code.contains(&"(save env)".to_string()));
+        let code = compile("(lambda (x) (f x) x)", "val", &Linkage::Next);
+        assert_eq!(
+            code[0],
+            "(assign val (op make-compiled-procedure) (label entry1))"
+        );
+        assert_eq!(code[3], "(save env)");
+        assert!(code.contains(&"(save continue)".to_string()));
+    }
+
+    #[test]
+    fn primitives_are_open_coded() {
+        assert_eq!(
+            compile("(+ 1 2 3)", "val", &Linkage::Next),
+            vec![
+                "(assign arg1 (const 1))",
+                "(assign arg2 (const 2))",
+                "(assign arg1 (op +) (reg arg1) (reg arg2))",
+                "(assign arg2 (const 3))",
+                "(assign val (op +) (reg arg1) (reg arg2))",
+            ]
+        );
+        // arg1 is saved around a call
+        let code = compile("(- 1 (f 2))", "val", &Linkage::Next);
+        assert_eq!(code[1], "(save arg1)");
+        // wrong number of operands, or + is a parameter
+        let code = compile("(- 1 2 3)", "val", &Linkage::Next);
+        assert!(code.contains(&"(assign argl (op empty-arglist))".to_string()));
+        let code = compile("(lambda (+) (+ 1 2))", "val", &Linkage::Next);
+        assert!(!code.iter().any(|x| x.contains("(op +)")));
+    }
+
+    #[test]
+    fn parameters_have_lexical_addresses() {
+        let code = compile("(lambda (x y) (set! y x) z)", "val", &Linkage::Next);
+        let body = &code[code.len() - 7..code.len() - 1];
+        assert_eq!(
+            body,
+            vec![
+                "(assign val (op lexical-address-lookup) (const (0 0)) (reg env))",
+                "(assign env (op lexical-address-set!) (const (0 1)) (reg val) (reg env))",
+                "(assign val (const 'ok))",
+                "(assign val (op lookup-variable-value) (const z) (reg env))",
+                "(restore env)",
+                "(goto (reg continue))",
+            ]
+        );
+        // only the frame of the procedure itself is addressed, a variable of the
+        // enclosing one is looked up by name
+        let code = compile("(lambda (x) (lambda (y) (f x y)))", "val", &Linkage::Next);
+        assert!(code
+            .contains(&"(assign val (op lookup-variable-value) (const x) (reg env))".to_string()));
+        assert!(code.contains(
+            &"(assign val (op lexical-address-lookup) (const (0 0)) (reg env))".to_string()
+        ));
+        // but it still isn't open coded
+        let code = compile("(lambda (+) (lambda (y) (+ y 1)))", "val", &Linkage::Next);
+        assert!(!code.iter().any(|x| x.contains("(op +)")));
+    }
+
+    #[test]
+    fn internal_definitions_are_scanned_out() {
+        let code = compile(
+            "(lambda (x) (define (g) x) (define x 2) (define y 3) (g))",
+            "val",
+            &Linkage::Next,
+        );
+        // g and y are made on entry, x is the parameter
+        assert_eq!(
+            code[5..7],
+            [
+                "(assign env (op define-variable!) (const g) (const *unassigned*) (reg env))",
+                "(assign env (op define-variable!) (const y) (const *unassigned*) (reg env))",
+            ]
+        );
+        assert!(!code
+            .iter()
+            .any(|x| x.contains("(const x) (const *unassigned*)")));
+        let sets: Vec<&String> = code
+            .iter()
+            .filter(|x| x.contains("lexical-address-set!"))
+            .collect();
+        assert_eq!(
+            sets,
+            vec![
+                "(assign env (op lexical-address-set!) (const (0 1)) (reg val) (reg env))",
+                "(assign env (op lexical-address-set!) (const (0 0)) (reg val) (reg env))",
+                "(assign env (op lexical-address-set!) (const (0 2)) (reg val) (reg env))",
+            ]
+        );
+        // at the top level a definition is made by name
+        let code = compile("(define (g) 1)", "val", &Linkage::Next);
+        assert!(code
+            .iter()
+            .any(|x| x.contains("(op define-variable!) (const g)")));
+    }
+}
@@ -0,0 +1,434 @@
+pub mod controller_file {
+    use crate::assembler::assembler::{assemble, referenced_registers};
+    use crate::datum::datum::{lex, read_datum, Datum, TokenKind};
+    use crate::diagnostics::diagnostics::diagnostics_to_string;
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::parser::parser::Span;
+    use crate::parserfordev::parser::{display_exp, str_to_exp};
+    use crate::tpfordev::type_system::{car, cdr, Exp};
+    use std::fs;
+    use std::ops::Range;
+    use std::path::{Path, PathBuf};
+
+    // a controller read from a .rm file, e.g.
+    //   ; factorial, SICP 5.1
+    //   (registers n val continue)
+    //   (operations = - *)
+    //   (controller
+    //       (include "fact-loop.rm")
+    //     fact-done)
+    // Comments are ; up to the end of the line and #| ... |#, which may nest.
+    // The registers and operations headers are optional. Text is the controller
+    // with the comments and headers blanked out and the includes spliced in, such
+    // that the lines and columns of what is left are those of the file.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ControllerSource {
+        pub name: String,
+        pub registers: Option<Vec<String>>,
+        pub operations: Option<Vec<String>>,
+        pub text: String,
+    }
+
+    #[allow(dead_code)]
+    pub fn read_controller_file(path: &Path) -> Result<ControllerSource, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) => return Err(format!("can't read {}: {}", path.display(), e)),
+        };
+        let name = path.display().to_string();
+        let mut including = vec![canonical(path)];
+        read_controller_in(&text, &name, path.parent(), &mut including)
+    }
+
+    // includes are looked up in dir, a controller without a dir such as one built
+    // into the binary can't include anything
+    pub fn read_controller(
+        text: &str,
+        name: &str,
+        dir: Option<&Path>,
+    ) -> Result<ControllerSource, String> {
+        read_controller_in(text, name, dir, &mut vec![])
+    }
+
+    fn read_controller_in(
+        text: &str,
+        name: &str,
+        dir: Option<&Path>,
+        including: &mut Vec<PathBuf>,
+    ) -> Result<ControllerSource, String> {
+        let mut text = strip_comments(text).map_err(|(x, e)| format!("{}:{}: {}", name, x, e))?;
+        let mut registers = None;
+        let mut operations = None;
+        let mut controller = None;
+        for range in elements(&text, 0..text.len()) {
+            let error =
+                |message: &str| format!("{}:{}: {}", name, span_at(&text, range.start), message);
+            let form = &text[range.clone()];
+            match head_of(form).as_deref() {
+                Some("registers") if registers.is_none() => {
+                    registers = Some(header_names(form).ok_or_else(|| error("bad registers"))?)
+                }
+                Some("operations") if operations.is_none() => {
+                    operations = Some(header_names(form).ok_or_else(|| error("bad operations"))?)
+                }
+                Some("controller") if controller.is_none() => {
+                    controller = Some(range.clone());
+                    continue;
+                }
+                Some(x @ ("registers" | "operations" | "controller")) => {
+                    return Err(error(&format!("more than one {}", x)))
+                }
+                _ => return Err(error(&format!("unexpected {}", form))),
+            }
+            blank(&mut text, range);
+        }
+        let controller = match controller {
+            Some(x) => x,
+            None => return Err(format!("{}: no controller", name)),
+        };
+        let body = controller.start + 1..controller.end - 1;
+        let expanded = expand_includes(&text, body.clone(), name, dir, including)?;
+        text.replace_range(body, &expanded);
+        Ok(ControllerSource {
+            name: name.to_string(),
+            registers,
+            operations,
+            text,
+        })
+    }
+
+    // replace every (include "file") among the elements of the range by the
+    // elements of the file, which sit on the line of the include. The included
+    // file is just labels and instructions, without (controller ...) around them.
+    fn expand_includes(
+        text: &str,
+        range: Range<usize>,
+        name: &str,
+        dir: Option<&Path>,
+        including: &mut Vec<PathBuf>,
+    ) -> Result<String, String> {
+        let mut result = String::new();
+        let mut last = range.start;
+        for element in elements(text, range.clone()) {
+            let form = &text[element.clone()];
+            if head_of(form).as_deref() != Some("include") {
+                continue;
+            }
+            let error =
+                |message: String| format!("{}:{}: {}", name, span_at(text, element.start), message);
+            let file = include_file(form).ok_or_else(|| error(format!("bad include {}", form)))?;
+            let dir = dir.ok_or_else(|| error(format!("can't include {} here", file)))?;
+            let path = dir.join(&file);
+            let key = canonical(&path);
+            if including.contains(&key) {
+                return Err(error(format!("{} includes itself", file)));
+            }
+            let included = match fs::read_to_string(&path) {
+                Ok(x) => x,
+                Err(e) => return Err(error(format!("can't read {}: {}", path.display(), e))),
+            };
+            let included_name = path.display().to_string();
+            let included = strip_comments(&included)
+                .map_err(|(x, e)| format!("{}:{}: {}", included_name, x, e))?;
+            including.push(key);
+            let expanded = expand_includes(
+                &included,
+                0..included.len(),
+                &included_name,
+                path.parent(),
+                including,
+            )?;
+            including.pop();
+            result.push_str(&text[last..element.start]);
+            result.push_str(&expanded.replace('\n', " "));
+            last = element.end;
+        }
+        result.push_str(&text[last..range.end]);
+        Ok(result)
+    }
+
+    // the registers and operations the header declares have to be there, the
+    // registers are allocated if the machine doesn't have them yet. A controller
+    // with a header can't use what the header leaves out.
+    #[allow(dead_code)]
+    pub fn check_header(
+        source: &ControllerSource,
+        machine: &mut BasicMachine,
+    ) -> Result<(), String> {
+        let mut errors = vec![];
+        let insts = list_items(&str_to_exp(source.text.clone()));
+        if let Some(ref operations) = source.operations {
+            for op in operations.iter() {
+                if !machine.is_semantic_op(op) && !machine.is_machine_op(op) {
+                    errors.push(format!("the machine has no operation {}", op));
+                }
+            }
+            for op in referenced_operations(&insts) {
+                if !operations.contains(&op) {
+                    errors.push(format!("operation {} is not declared", op));
+                }
+            }
+        }
+        if let Some(ref registers) = source.registers {
+            for name in referenced_registers(&insts) {
+                if !registers.contains(&name) && name != "pc" && name != "flag" {
+                    errors.push(format!("register {} is not declared", name));
+                }
+            }
+            for name in registers.iter() {
+                if machine.get_register(name).is_none() {
+                    machine.allocate_register(name);
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            let lines: Vec<String> = errors
+                .iter()
+                .map(|x| format!("{}: {}", source.name, x))
+                .collect();
+            Err(lines.join("\n"))
+        }
+    }
+
+    // check the header and assemble the controller into the machine, the
+    // diagnostics of assemble are given one per line
+    #[allow(dead_code)]
+    pub fn assemble_controller(
+        source: &ControllerSource,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<(), String> {
+        check_header(source, machine)?;
+        machine.source_name = source.name.clone();
+        assemble(source.text.clone(), machine, memory)
+            .map_err(|e| diagnostics_to_string(&source.name, &e))
+    }
+
+    // comments become spaces, newlines are kept. An unterminated #| or string is
+    // reported with where it starts.
+    pub fn strip_comments(text: &str) -> Result<String, (Span, String)> {
+        let mut result = String::with_capacity(text.len());
+        let mut last = 0;
+        for token in lex(text)? {
+            result.push_str(&blanked(&text[last..token.range.start]));
+            result.push_str(&text[token.range.clone()]);
+            last = token.range.end;
+        }
+        result.push_str(&blanked(&text[last..]));
+        Ok(result)
+    }
+
+    fn span_at(text: &str, offset: usize) -> Span {
+        let mut span = Span::new();
+        text[..offset].chars().for_each(|c| span.advance(c));
+        span
+    }
+
+    // the byte ranges of the lists, atoms and strings directly inside the range,
+    // a quote goes with what follows it
+    fn elements(text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+        let tokens = lex(&text[range.clone()]).unwrap_or_default();
+        let mut result = vec![];
+        let mut start = None;
+        let mut depth = 0;
+        for token in tokens {
+            let first = *start.get_or_insert(token.range.start + range.start);
+            match token.kind {
+                TokenKind::Open => depth += 1,
+                TokenKind::Close if depth > 0 => depth -= 1,
+                TokenKind::Quote => continue,
+                _ => {}
+            }
+            if depth == 0 {
+                result.push(first..token.range.end + range.start);
+                start = None;
+            }
+        }
+        if let Some(x) = start {
+            result.push(x..range.end);
+        }
+        result
+    }
+
+    // the items of a list such as (registers n val)
+    fn form_items(form: &str) -> Option<Vec<Datum>> {
+        match read_datum(form) {
+            Ok(Datum::List(items)) => Some(items),
+            _ => None,
+        }
+    }
+
+    // the first word of a list such as controller in (controller ...)
+    fn head_of(form: &str) -> Option<String> {
+        match form_items(form)?.first() {
+            Some(Datum::Symbol(x)) => Some(x.clone()),
+            _ => None,
+        }
+    }
+
+    // the symbols after the head of (registers n val) or (operations + -)
+    fn header_names(form: &str) -> Option<Vec<String>> {
+        form_items(form)?
+            .iter()
+            .skip(1)
+            .map(|x| match x {
+                Datum::Symbol(x) => Some(x.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // the file of (include "file")
+    fn include_file(form: &str) -> Option<String> {
+        match form_items(form)?.as_slice() {
+            [Datum::Symbol(x), Datum::Str(file)] if x == "include" && !file.is_empty() => {
+                Some(file.clone())
+            }
+            _ => None,
+        }
+    }
+
+    // whitespace and comments as spaces, newlines are kept
+    fn blanked(text: &str) -> String {
+        text.chars()
+            .map(|c| if c == '\n' { '\n' } else { ' ' })
+            .collect()
+    }
+
+    fn blank(text: &mut String, range: Range<usize>) {
+        let blanked = blanked(&text[range.clone()]);
+        text.replace_range(range, &blanked);
+    }
+
+    fn canonical(path: &Path) -> PathBuf {
+        fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    // the names in (op name) of the instructions, operands included
+    fn referenced_operations(insts: &[Exp]) -> Vec<String> {
+        let mut names = vec![];
+        for inst in insts.iter() {
+            collect_operations(inst, &mut names);
+        }
+        names
+    }
+
+    fn collect_operations(exp: &Exp, names: &mut Vec<String>) {
+        let items = list_items(exp);
+        match items.as_slice() {
+            [x, _] if display_exp(x) == "const" => {}
+            [x, y] if display_exp(x) == "op" => {
+                let name = display_exp(y);
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            _ => {
+                for item in items.iter() {
+                    collect_operations(item, names);
+                }
+            }
+        }
+    }
+
+    fn list_items(exp: &Exp) -> Vec<Exp> {
+        let mut items = vec![];
+        let mut rest = exp.clone();
+        while rest.is_pair() {
+            items.push(car(&rest).unwrap());
+            rest = cdr(&rest).unwrap();
+        }
+        items
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::controller_file::{
+        assemble_controller, read_controller, read_controller_file, strip_comments,
+    };
+    use crate::machine::basic_machine::{BasicMachine, Status};
+    use crate::memory::memory::Memory;
+    use crate::representation::type_system::Object;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn strip_comments_works() {
+        let text = "(a ; b\n #| c #| d |# e\n |# \"; #|\" f)";
+        let stripped = strip_comments(text).unwrap();
+        assert_eq!(stripped.len(), text.len());
+        let words: Vec<&str> = stripped.split_whitespace().collect();
+        assert_eq!(words, vec!["(a", "\";", "#|\"", "f)"]);
+        assert_eq!(stripped.lines().count(), 3);
+        let e = strip_comments("(a\n  #| b").unwrap_err();
+        assert_eq!(e.0.to_string(), "2:3");
+    }
+
+    #[test]
+    fn read_controller_works() {
+        let text = "; counts down\n(registers n)\n(operations -)\n(controller\n  loop ; here\n  (assign n (op -) (reg n) (const 1)))";
+        let source = read_controller(text, "down.rm", None).unwrap();
+        assert_eq!(source.registers, Some(vec!["n".to_string()]));
+        assert_eq!(source.operations, Some(vec!["-".to_string()]));
+        assert_eq!(source.text.lines().count(), 6);
+        assert!(source.text.trim_start().starts_with("(controller"));
+        let e = read_controller("(registers n)", "x.rm", None).unwrap_err();
+        assert_eq!(e, "x.rm: no controller");
+        let e = read_controller("(controller)\n(controller)", "x.rm", None).unwrap_err();
+        assert_eq!(e, "x.rm:2:1: more than one controller");
+        let e = read_controller("(controller (include \"a.rm\"))", "x.rm", None).unwrap_err();
+        assert_eq!(e, "x.rm:1:13: can't include a.rm here");
+    }
+
+    #[test]
+    fn include_works() {
+        let dir = env::temp_dir().join("rusmachine_include_works");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        let main = "(registers n val)\n(operations = *)\n(controller\n  (assign val (const 1))\n  (include \"lib/loop.rm\")\n  done)";
+        fs::write(dir.join("main.rm"), main).unwrap();
+        let looping = "; multiplies val by n\nloop\n  (test (op =) (reg n) (const 0))\n  #| done |#\n  (include \"step.rm\")";
+        fs::write(dir.join("lib/loop.rm"), looping).unwrap();
+        let step = "(branch (label done))\n(assign val (op *) (reg n) (reg val))\n(assign n (op -) (reg n) (const 1))\n(goto (label loop))";
+        fs::write(dir.join("lib/step.rm"), step).unwrap();
+        let source = read_controller_file(&dir.join("main.rm")).unwrap();
+        let mut machine = BasicMachine::new();
+        machine.initialize_op();
+        let mut memory = Memory::new(20);
+        // - isn't declared
+        let e = assemble_controller(&source, &mut machine, &mut memory).unwrap_err();
+        assert!(e.ends_with("main.rm: operation - is not declared"));
+        fs::write(dir.join("main.rm"), main.replace("= *", "= * -")).unwrap();
+        let source = read_controller_file(&dir.join("main.rm")).unwrap();
+        assemble_controller(&source, &mut machine, &mut memory).unwrap();
+        // the included instructions are on the line of the include
+        assert_eq!(machine.spans[1].line, 5);
+        assert_eq!(machine.spans.last().unwrap().line, 5);
+        machine.set_register_contents(&"n".to_string(), Object::Integer(5));
+        assert_eq!(machine.execute(&mut memory), Status::Done);
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Integer(120))
+        );
+        fs::write(dir.join("lib/step.rm"), "(include \"loop.rm\")").unwrap();
+        let e = read_controller_file(&dir.join("main.rm")).unwrap_err();
+        assert!(e.ends_with("step.rm:1:1: loop.rm includes itself"));
+    }
+
+    #[test]
+    fn check_header_works() {
+        let text = "(registers n)\n(operations foo)\n(controller\n  (assign val (reg n)))";
+        let source = read_controller(text, "x.rm", None).unwrap();
+        let mut machine = BasicMachine::new();
+        machine.initialize_op();
+        let mut memory = Memory::new(20);
+        let e = assemble_controller(&source, &mut machine, &mut memory).unwrap_err();
+        assert_eq!(
+            e,
+            "x.rm: the machine has no operation foo\nx.rm: register val is not declared"
+        );
+    }
+}
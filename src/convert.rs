@@ -0,0 +1,470 @@
+pub mod convert {
+    use crate::datum::datum::{allocate, exp_to_object, quoted_datum, write_datum, Datum};
+    use crate::embed::embed::value_of;
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::representation::type_system::Object;
+    use crate::tpfordev::type_system::Exp;
+    use crate::trace::trace::display_object;
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+    use std::hash::Hash;
+
+    // Rust values as Scheme data in the heap and back, e.g.
+    //   let x = vec![(1, "a".to_string())].into_scheme(&mut machine, &mut memory)?;
+    //   let y: Vec<(i32, String)> = FromScheme::from_scheme(&x, &memory)?;
+    // A list is an Object::Index into the heap, as it is in a register, a list in
+    // a car is an Object::Pair. There are no dotted pairs, so a tuple is a list of
+    // its items and a HashMap an alist of (key value) lists. None is #f, which
+    // makes Some(false) read back as None. Quoted data such as '(1 2) reads the
+    // same as the list in the heap.
+
+    pub trait FromScheme: Sized {
+        fn from_scheme(object: &Object, memory: &Memory) -> Result<Self, String>;
+    }
+
+    // new pairs are taken from the heap at register free of the machine
+    pub trait IntoScheme {
+        fn into_scheme(
+            self,
+            machine: &mut BasicMachine,
+            memory: &mut Memory,
+        ) -> Result<Object, String>;
+    }
+
+    // the items of the list object points to, lists among them are Object::Pair,
+    // or quoted for a quoted list
+    pub fn list_items(object: &Object, memory: &Memory) -> Result<Vec<Object>, String> {
+        let start = match object {
+            Object::Index(x) | Object::Pair(x) => *x,
+            Object::Nil => return Ok(vec![]),
+            Object::Quote(x) => match quoted_datum(x) {
+                Datum::List(items) => return Ok(items.iter().map(quoted_item).collect()),
+                _ => return Err(mismatch(object, "a list")),
+            },
+            x => return Err(mismatch(x, "a list")),
+        };
+        let not_a_list = || format!("no list at @{}", start);
+        let mut items = vec![];
+        let mut index = start;
+        loop {
+            if index >= memory.the_cars.len() || items.len() > memory.the_cars.len() {
+                return Err(not_a_list());
+            }
+            match memory.car(index) {
+                Object::Nil => {}
+                Object::Index(_) | Object::Empty => return Err(not_a_list()),
+                x => items.push(x),
+            }
+            match memory.cdr(index) {
+                Object::Nil => break,
+                Object::Pair(x) => index = x,
+                _ => return Err(not_a_list()),
+            }
+        }
+        Ok(items)
+    }
+
+    // an item of a quoted list as it is when it is quoted by itself, the list
+    // '(a (1 2)) has the items a and '(1 2)
+    fn quoted_item(datum: &Datum) -> Object {
+        match datum {
+            Datum::Bool(x) => Object::Bool(*x),
+            Datum::Integer(x) => Object::Integer(*x),
+            Datum::Number(x) => Object::Number(*x),
+            Datum::Symbol(x) => Object::Symbol(x.clone()),
+            Datum::Str(x) => Object::LispString(x.clone()),
+            Datum::Quote(x) => Object::Quote(write_datum(x)),
+            Datum::List(_) => Object::Quote(write_datum(datum)),
+            Datum::Eof => Object::Eof,
+        }
+    }
+
+    // a list of the items, which are given as they are in a register
+    pub fn make_list(
+        items: Vec<Object>,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<Object, String> {
+        // the empty list is a pair with nothing in it, as the parser writes it
+        let mut next = Object::Nil;
+        let mut head = None;
+        for item in items.into_iter().rev() {
+            let item = match item {
+                Object::Index(x) => Object::Pair(x),
+                x => x,
+            };
+            let index = allocate(item, next, machine, memory)?;
+            next = Object::Pair(index);
+            head = Some(index);
+        }
+        let head = match head {
+            Some(x) => x,
+            None => allocate(Object::Nil, Object::Nil, machine, memory)?,
+        };
+        Ok(Object::Index(head))
+    }
+
+    fn mismatch(object: &Object, expected: &str) -> String {
+        format!("{} isn't {}", display_object(object), expected)
+    }
+
+    macro_rules! integer {
+        ($($t:ty),*) => {
+            $(
+                impl FromScheme for $t {
+                    fn from_scheme(object: &Object, _memory: &Memory) -> Result<Self, String> {
+                        match object {
+                            Object::Integer(x) => <$t>::try_from(*x).map_err(|_e| {
+                                format!("{} is out of range for {}", x, stringify!($t))
+                            }),
+                            x => Err(mismatch(x, "an integer")),
+                        }
+                    }
+                }
+
+                impl IntoScheme for $t {
+                    fn into_scheme(
+                        self,
+                        _machine: &mut BasicMachine,
+                        _memory: &mut Memory,
+                    ) -> Result<Object, String> {
+                        i32::try_from(self)
+                            .map(Object::Integer)
+                            .map_err(|_e| format!("{} is out of range for an integer", self))
+                    }
+                }
+            )*
+        };
+    }
+
+    integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+    impl FromScheme for f32 {
+        fn from_scheme(object: &Object, _memory: &Memory) -> Result<Self, String> {
+            match object {
+                Object::Number(x) => Ok(*x),
+                Object::Integer(x) => Ok(*x as f32),
+                x => Err(mismatch(x, "a number")),
+            }
+        }
+    }
+
+    impl IntoScheme for f32 {
+        fn into_scheme(
+            self,
+            _machine: &mut BasicMachine,
+            _memory: &mut Memory,
+        ) -> Result<Object, String> {
+            Ok(Object::Number(self))
+        }
+    }
+
+    // numbers are f32 in the machine
+    impl FromScheme for f64 {
+        fn from_scheme(object: &Object, memory: &Memory) -> Result<Self, String> {
+            f32::from_scheme(object, memory).map(|x| x as f64)
+        }
+    }
+
+    impl IntoScheme for f64 {
+        fn into_scheme(
+            self,
+            _machine: &mut BasicMachine,
+            _memory: &mut Memory,
+        ) -> Result<Object, String> {
+            Ok(Object::Number(self as f32))
+        }
+    }
+
+    impl FromScheme for bool {
+        fn from_scheme(object: &Object, _memory: &Memory) -> Result<Self, String> {
+            match object {
+                Object::Bool(x) => Ok(*x),
+                x => Err(mismatch(x, "a boolean")),
+            }
+        }
+    }
+
+    impl IntoScheme for bool {
+        fn into_scheme(
+            self,
+            _machine: &mut BasicMachine,
+            _memory: &mut Memory,
+        ) -> Result<Object, String> {
+            Ok(Object::Bool(self))
+        }
+    }
+
+    // a symbol reads as its name, such as the keys of an alist
+    impl FromScheme for String {
+        fn from_scheme(object: &Object, _memory: &Memory) -> Result<Self, String> {
+            match object {
+                Object::LispString(x) | Object::Symbol(x) => Ok(x.clone()),
+                Object::Quote(x) => match quoted_datum(x) {
+                    Datum::Symbol(x) => Ok(x),
+                    _ => Err(mismatch(object, "a string")),
+                },
+                x => Err(mismatch(x, "a string")),
+            }
+        }
+    }
+
+    impl IntoScheme for String {
+        fn into_scheme(
+            self,
+            _machine: &mut BasicMachine,
+            _memory: &mut Memory,
+        ) -> Result<Object, String> {
+            Ok(Object::LispString(self))
+        }
+    }
+
+    impl IntoScheme for &str {
+        fn into_scheme(
+            self,
+            _machine: &mut BasicMachine,
+            _memory: &mut Memory,
+        ) -> Result<Object, String> {
+            Ok(Object::LispString(self.to_string()))
+        }
+    }
+
+    impl<T: FromScheme> FromScheme for Vec<T> {
+        fn from_scheme(object: &Object, memory: &Memory) -> Result<Self, String> {
+            list_items(object, memory)?
+                .iter()
+                .map(|x| T::from_scheme(x, memory))
+                .collect()
+        }
+    }
+
+    impl<T: IntoScheme> IntoScheme for Vec<T> {
+        fn into_scheme(
+            self,
+            machine: &mut BasicMachine,
+            memory: &mut Memory,
+        ) -> Result<Object, String> {
+            let mut items = vec![];
+            for item in self {
+                items.push(item.into_scheme(machine, memory)?);
+            }
+            make_list(items, machine, memory)
+        }
+    }
+
+    impl<T: FromScheme> FromScheme for Option<T> {
+        fn from_scheme(object: &Object, memory: &Memory) -> Result<Self, String> {
+            match object {
+                Object::Bool(false) => Ok(None),
+                x => T::from_scheme(x, memory).map(Some),
+            }
+        }
+    }
+
+    impl<T: IntoScheme> IntoScheme for Option<T> {
+        fn into_scheme(
+            self,
+            machine: &mut BasicMachine,
+            memory: &mut Memory,
+        ) -> Result<Object, String> {
+            match self {
+                Some(x) => x.into_scheme(machine, memory),
+                None => Ok(Object::Bool(false)),
+            }
+        }
+    }
+
+    macro_rules! tuple {
+        ($n:expr; $($t:ident $i:tt),*) => {
+            impl<$($t: FromScheme),*> FromScheme for ($($t,)*) {
+                fn from_scheme(object: &Object, memory: &Memory) -> Result<Self, String> {
+                    let items = list_items(object, memory)?;
+                    if items.len() != $n {
+                        return Err(format!("a list of {} items isn't a tuple of {}", items.len(), $n));
+                    }
+                    Ok(($($t::from_scheme(&items[$i], memory)?,)*))
+                }
+            }
+
+            impl<$($t: IntoScheme),*> IntoScheme for ($($t,)*) {
+                fn into_scheme(self, machine: &mut BasicMachine, memory: &mut Memory) -> Result<Object, String> {
+                    let items = vec![$(self.$i.into_scheme(machine, memory)?),*];
+                    make_list(items, machine, memory)
+                }
+            }
+        };
+    }
+
+    tuple!(1; A 0);
+    tuple!(2; A 0, B 1);
+    tuple!(3; A 0, B 1, C 2);
+    tuple!(4; A 0, B 1, C 2, D 3);
+
+    // an alist of (key value) lists
+    impl<K: FromScheme + Eq + Hash, V: FromScheme> FromScheme for HashMap<K, V> {
+        fn from_scheme(object: &Object, memory: &Memory) -> Result<Self, String> {
+            let pairs: Vec<(K, V)> = FromScheme::from_scheme(object, memory)?;
+            Ok(pairs.into_iter().collect())
+        }
+    }
+
+    impl<K: IntoScheme, V: IntoScheme> IntoScheme for HashMap<K, V> {
+        fn into_scheme(
+            self,
+            machine: &mut BasicMachine,
+            memory: &mut Memory,
+        ) -> Result<Object, String> {
+            self.into_iter()
+                .collect::<Vec<(K, V)>>()
+                .into_scheme(machine, memory)
+        }
+    }
+
+    impl FromScheme for Object {
+        fn from_scheme(object: &Object, _memory: &Memory) -> Result<Self, String> {
+            Ok(object.clone())
+        }
+    }
+
+    impl IntoScheme for Object {
+        fn into_scheme(
+            self,
+            _machine: &mut BasicMachine,
+            _memory: &mut Memory,
+        ) -> Result<Object, String> {
+            Ok(self)
+        }
+    }
+
+    impl FromScheme for Exp {
+        fn from_scheme(object: &Object, memory: &Memory) -> Result<Self, String> {
+            match object {
+                Object::Pair(x) => value_of(&Object::Index(*x), memory),
+                x => value_of(x, memory),
+            }
+        }
+    }
+
+    impl IntoScheme for Exp {
+        fn into_scheme(
+            self,
+            machine: &mut BasicMachine,
+            memory: &mut Memory,
+        ) -> Result<Object, String> {
+            exp_to_object(&self, machine, memory)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::convert::{FromScheme, IntoScheme};
+    use crate::embed::embed::Interpreter;
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::parserfordev::parser::str_to_exp;
+    use crate::representation::type_system::Object;
+    use std::collections::HashMap;
+
+    fn new_machine() -> BasicMachine {
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        machine
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let mut machine = new_machine();
+        let mut memory = Memory::new(100);
+        let x = vec![(1, "a".to_string()), (2, "b".to_string())];
+        let object = x.clone().into_scheme(&mut machine, &mut memory).unwrap();
+        assert!(matches!(object, Object::Index(_)));
+        let y: Vec<(i32, String)> = FromScheme::from_scheme(&object, &memory).unwrap();
+        assert_eq!(x, y);
+        let list = machine.display_register_value("val", &object, &memory);
+        assert_eq!(list, "((1 \"a\") (2 \"b\"))");
+        let x = (true, 2.5, vec![Some(1u8), None], Vec::<i64>::new());
+        let object = x.clone().into_scheme(&mut machine, &mut memory).unwrap();
+        assert_eq!(FromScheme::from_scheme(&object, &memory), Ok(x));
+        let mut x = HashMap::new();
+        x.insert("a".to_string(), vec![1, 2]);
+        x.insert("b".to_string(), vec![]);
+        let object = x.clone().into_scheme(&mut machine, &mut memory).unwrap();
+        assert_eq!(FromScheme::from_scheme(&object, &memory), Ok(x));
+        let exp = str_to_exp("(1 (a \"s\") () 2.5)".to_string());
+        let object = exp.clone().into_scheme(&mut machine, &mut memory).unwrap();
+        assert_eq!(FromScheme::from_scheme(&object, &memory), Ok(exp));
+    }
+
+    #[test]
+    fn conversions_check_the_values() {
+        let mut machine = new_machine();
+        let mut memory = Memory::new(3);
+        let r = i8::from_scheme(&Object::Integer(300), &memory);
+        assert_eq!(r, Err("300 is out of range for i8".to_string()));
+        let r = bool::from_scheme(&Object::Integer(1), &memory);
+        assert_eq!(r, Err("1 isn't a boolean".to_string()));
+        let r = u64::MAX.into_scheme(&mut machine, &mut memory);
+        assert_eq!(
+            r,
+            Err("18446744073709551615 is out of range for an integer".to_string())
+        );
+        let object = (1, 2).into_scheme(&mut machine, &mut memory).unwrap();
+        let r = <(i32, i32, i32)>::from_scheme(&object, &memory);
+        assert_eq!(r, Err("a list of 2 items isn't a tuple of 3".to_string()));
+        let r = vec![1, 2].into_scheme(&mut machine, &mut memory);
+        assert_eq!(r, Err("the heap is full".to_string()));
+    }
+
+    #[test]
+    fn data_goes_in_and_out_of_programs() {
+        let mut interpreter = Interpreter::with_heap(50000);
+        let mut prices = HashMap::new();
+        prices.insert("apple".to_string(), 3);
+        prices.insert("pear".to_string(), 5);
+        interpreter.define("prices", prices).unwrap();
+        interpreter
+            .define("basket", vec!["pear", "apple", "pear"])
+            .unwrap();
+        let program = "
+            (define (price name alist)
+              (if (null? alist)
+                  0
+                  (if (= (car (car alist)) name)
+                      (car (cdr (car alist)))
+                      (price name (cdr alist)))))
+            (define (total basket)
+              (if (null? basket)
+                  0
+                  (+ (price (car basket) prices) (total (cdr basket)))))";
+        interpreter.eval(program).unwrap();
+        assert_eq!(interpreter.eval_as::<i32>("(total basket)"), Ok(13));
+        let r = interpreter.eval_as::<Vec<String>>("(cdr basket)");
+        assert_eq!(r, Ok(vec!["apple".to_string(), "pear".to_string()]));
+        let r = interpreter.eval_as::<bool>("(total basket)");
+        assert_eq!(r, Err("13 isn't a boolean".to_string()));
+    }
+
+    #[test]
+    fn quoted_lists_are_lists() {
+        let mut interpreter = Interpreter::with_heap(5000);
+        let r = interpreter.eval_as::<Vec<String>>("'(a b c)");
+        let names: Vec<String> = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        assert_eq!(r, Ok(names));
+        let r = interpreter.eval_as::<Vec<(String, Vec<i32>)>>("'((x (1 2)) (y ()))");
+        assert_eq!(
+            r,
+            Ok(vec![
+                ("x".to_string(), vec![1, 2]),
+                ("y".to_string(), vec![])
+            ])
+        );
+        let r = interpreter.eval_as::<HashMap<String, i32>>("'((a 1))");
+        assert_eq!(r.map(|x| x.get("a").copied()), Ok(Some(1)));
+        let r = interpreter.eval_as::<Vec<i32>>("'x");
+        assert_eq!(r, Err("'x isn't a list".to_string()));
+        let r = interpreter.eval_as::<String>("'(a)");
+        assert_eq!(r, Err("'(a) isn't a string".to_string()));
+    }
+}
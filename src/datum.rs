@@ -0,0 +1,546 @@
+pub mod datum {
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::parser::parser::Span;
+    use crate::representation::type_system::Object;
+    use crate::tpfordev::type_system::{scheme_cons, Exp, Pair};
+    use std::ops::Range;
+
+    // The reader of Scheme text. It reads text into a Datum, which a Backend then
+    // materializes, as an Exp with ExpBackend or into the heap with HeapBackend,
+    // so str_to_exp and memory.write read text the same way:
+    //   true, false                  Bool
+    //   a token that starts with a letter, e.g. nan or x1    Symbol
+    //   12, -3                       Integer
+    //   3.14, 1e3                    Number
+    //   "winter is coming"           Str
+    //   'x, '(1 (2 3))               Quote of the datum after the quote
+    //   anything else, e.g. <= or Nil    Symbol
+    // Eof isn't read from text, it is there such that every exp has a datum.
+    // Quoted data is kept as the text write_datum gives for it, (1 (2 3)), in an
+    // Exp or an Object.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Datum {
+        Bool(bool),
+        Integer(i32),
+        Number(f32),
+        Symbol(String),
+        Str(String),
+        Quote(Box<Datum>),
+        List(Vec<Datum>),
+        Eof,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum TokenKind {
+        Open,
+        Close,
+        Quote,
+        Str,
+        Atom,
+    }
+
+    // a token and where it is, the range is in bytes and a string token has its
+    // double quotes in it
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Token {
+        pub kind: TokenKind,
+        pub range: Range<usize>,
+        pub span: Span,
+    }
+
+    // the lexer that all Scheme text and controllers are read with. Comments are
+    // ; up to the end of the line and #| ... |#, which may nest. A string is a
+    // single token with its text as it is written, whitespace, parentheses and ;
+    // in it included. An unterminated string or #| is an error at where it
+    // starts.
+    pub fn lex(text: &str) -> Result<Vec<Token>, (Span, String)> {
+        let chars: Vec<(usize, char)> = text.char_indices().collect();
+        let offset = |i: usize| chars.get(i).map_or(text.len(), |x| x.0);
+        let char_at = |i: usize| chars.get(i).map(|x| x.1);
+        let mut tokens = vec![];
+        let mut here = Span::new();
+        let mut i = 0;
+        while let Some(c) = char_at(i) {
+            let at = here;
+            let mut end = i + 1;
+            let kind = match c {
+                x if x.is_whitespace() => None,
+                ';' => {
+                    while char_at(end).is_some_and(|x| x != '\n') {
+                        end += 1;
+                    }
+                    None
+                }
+                '#' if char_at(i + 1) == Some('|') => {
+                    let mut opened = vec![];
+                    let mut j = i;
+                    let mut span = here;
+                    loop {
+                        match (char_at(j), char_at(j + 1)) {
+                            (Some('#'), Some('|')) => opened.push(span),
+                            (Some('|'), Some('#')) => {
+                                opened.pop();
+                            }
+                            (Some(x), _) => {
+                                span.advance(x);
+                                j += 1;
+                                continue;
+                            }
+                            (None, _) => {
+                                let start = opened.last().copied().unwrap_or(at);
+                                return Err((start, "unterminated #|".to_string()));
+                            }
+                        }
+                        span.advance(' ');
+                        span.advance(' ');
+                        j += 2;
+                        if opened.is_empty() {
+                            break;
+                        }
+                    }
+                    end = j;
+                    None
+                }
+                '(' => Some(TokenKind::Open),
+                ')' => Some(TokenKind::Close),
+                '\'' => Some(TokenKind::Quote),
+                '"' => {
+                    while char_at(end).is_some_and(|x| x != '"') {
+                        end += 1;
+                    }
+                    if char_at(end).is_none() {
+                        return Err((at, "unterminated string".to_string()));
+                    }
+                    end += 1;
+                    Some(TokenKind::Str)
+                }
+                _ => {
+                    while char_at(end).is_some_and(|x| !x.is_whitespace() && !"()\";".contains(x)) {
+                        end += 1;
+                    }
+                    Some(TokenKind::Atom)
+                }
+            };
+            if let Some(kind) = kind {
+                tokens.push(Token {
+                    kind,
+                    range: offset(i)..offset(end),
+                    span: at,
+                });
+            }
+            for &(_, x) in &chars[i..end] {
+                here.advance(x);
+            }
+            i = end;
+        }
+        Ok(tokens)
+    }
+
+    // the datum of text, which holds a single form
+    pub fn read_datum(text: &str) -> Result<Datum, String> {
+        let tokens = lex(text).map_err(|(_span, e)| e)?;
+        let tokens: Vec<String> = tokens
+            .iter()
+            .map(|x| text[x.range.clone()].to_string())
+            .collect();
+        read_tokens(&tokens)
+    }
+
+    // the same as read_datum, for the tokens of parser::tokenizer
+    pub fn read_tokens(tokens: &[String]) -> Result<Datum, String> {
+        if tokens.is_empty() {
+            return Err("nothing to read".to_string());
+        }
+        // a stack, the next token is at the end
+        let mut tokens: Vec<String> = tokens.iter().rev().cloned().collect();
+        let datum = read_next(&mut tokens)?;
+        match tokens.pop() {
+            Some(x) => Err(format!("{} after the end of the form", x)),
+            None => Ok(datum),
+        }
+    }
+
+    fn read_next(tokens: &mut Vec<String>) -> Result<Datum, String> {
+        let token = match tokens.pop() {
+            Some(x) => x,
+            None => return Err("unexpected end of the form".to_string()),
+        };
+        match token.as_str() {
+            "(" => {
+                let mut items = vec![];
+                loop {
+                    match tokens.last().map(|x| x.as_str()) {
+                        Some(")") => {
+                            tokens.pop();
+                            return Ok(Datum::List(items));
+                        }
+                        Some(_) => items.push(read_next(tokens)?),
+                        None => return Err("unbalanced parentheses".to_string()),
+                    }
+                }
+            }
+            ")" => Err("unbalanced parentheses".to_string()),
+            "'" => Ok(Datum::Quote(Box::new(read_next(tokens)?))),
+            x if x.starts_with('"') => {
+                if x.len() > 1 && x.ends_with('"') {
+                    Ok(Datum::Str(x[1..(x.len() - 1)].to_string()))
+                } else {
+                    Err("unterminated string".to_string())
+                }
+            }
+            x => Ok(read_atom(x)),
+        }
+    }
+
+    // the atom a single token stands for
+    pub fn read_atom(token: &str) -> Datum {
+        match token {
+            "true" => Datum::Bool(true),
+            "false" => Datum::Bool(false),
+            x if x.starts_with(char::is_alphabetic) => Datum::Symbol(x.to_string()),
+            x => match (x.parse::<i32>(), x.parse::<f32>()) {
+                (Ok(i), _) => Datum::Integer(i),
+                (_, Ok(f)) => Datum::Number(f),
+                _ => Datum::Symbol(x.to_string()),
+            },
+        }
+    }
+
+    // the text of a datum, such that read_datum gives it back, e.g. (1 "a b" 'x)
+    pub fn write_datum(datum: &Datum) -> String {
+        match datum {
+            Datum::Bool(x) => x.to_string(),
+            Datum::Integer(x) => x.to_string(),
+            // with a point such that 2.0 isn't read as an integer
+            Datum::Number(x) => format!("{:?}", x),
+            Datum::Symbol(x) => x.clone(),
+            Datum::Str(x) => format!("\"{}\"", x),
+            Datum::Quote(x) => format!("'{}", write_datum(x)),
+            Datum::List(items) => {
+                let items: Vec<String> = items.iter().map(write_datum).collect();
+                format!("({})", items.join(" "))
+            }
+            Datum::Eof => "#<eof>".to_string(),
+        }
+    }
+
+    // what a datum is materialized as, the items of a list are left to the
+    // backend so that it can choose the order they are built in
+    pub trait Backend {
+        type Value;
+        fn atom(&mut self, datum: &Datum) -> Result<Self::Value, String>;
+        fn list(&mut self, items: &[Datum]) -> Result<Self::Value, String>;
+    }
+
+    pub fn materialize<B: Backend>(datum: &Datum, backend: &mut B) -> Result<B::Value, String> {
+        match datum {
+            Datum::List(items) => backend.list(items),
+            x => backend.atom(x),
+        }
+    }
+
+    pub struct ExpBackend;
+
+    impl Backend for ExpBackend {
+        type Value = Exp;
+
+        fn atom(&mut self, datum: &Datum) -> Result<Exp, String> {
+            Ok(match datum {
+                Datum::Bool(x) => Exp::Bool(*x),
+                Datum::Integer(x) => Exp::Integer(*x),
+                Datum::Number(x) => Exp::FloatNumber(*x),
+                Datum::Symbol(x) => Exp::Symbol(x.clone()),
+                Datum::Str(x) => Exp::SchemeString(x.clone()),
+                Datum::Quote(x) => Exp::Quote(write_datum(x)),
+                Datum::List(x) => return self.list(x),
+                Datum::Eof => Exp::Eof,
+            })
+        }
+
+        fn list(&mut self, items: &[Datum]) -> Result<Exp, String> {
+            let mut list = Exp::List(Pair::Nil);
+            for item in items.iter().rev() {
+                list = scheme_cons(materialize(item, self)?, list);
+            }
+            Ok(list)
+        }
+    }
+
+    // a list is written into the heap from register free on, a pair for each
+    // item in the order they are read, and is the Object::Index of its first
+    // pair. The empty list is a pair with nothing in it.
+    pub struct HeapBackend<'a> {
+        pub machine: &'a mut BasicMachine,
+        pub memory: &'a mut Memory,
+    }
+
+    impl<'a> Backend for HeapBackend<'a> {
+        type Value = Object;
+
+        fn atom(&mut self, datum: &Datum) -> Result<Object, String> {
+            Ok(match datum {
+                Datum::Bool(x) => Object::Bool(*x),
+                Datum::Integer(x) => Object::Integer(*x),
+                Datum::Number(x) => Object::Number(*x),
+                Datum::Symbol(x) => Object::Symbol(x.clone()),
+                Datum::Str(x) => Object::LispString(x.clone()),
+                Datum::Quote(x) => Object::Quote(write_datum(x)),
+                Datum::List(x) => return self.list(x),
+                Datum::Eof => Object::Eof,
+            })
+        }
+
+        fn list(&mut self, items: &[Datum]) -> Result<Object, String> {
+            let head = allocate(Object::Nil, Object::Nil, self.machine, self.memory)?;
+            let mut index = head;
+            for (n, item) in items.iter().enumerate() {
+                if n > 0 {
+                    let next = allocate(Object::Nil, Object::Nil, self.machine, self.memory)?;
+                    self.memory.update("cdr", Object::Pair(next), index);
+                    index = next;
+                }
+                let item = match materialize(item, self)? {
+                    Object::Index(x) => Object::Pair(x),
+                    x => x,
+                };
+                self.memory.update("car", item, index);
+            }
+            Ok(Object::Index(head))
+        }
+    }
+
+    // a pair at register free, which is advanced past it
+    pub fn allocate(
+        car: Object,
+        cdr: Object,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<usize, String> {
+        let free = match machine.get_register_contents(&"free".to_string()) {
+            Some(Object::Index(x)) => x,
+            _ => return Err("the machine has no register free".to_string()),
+        };
+        if free >= memory.the_cars.len() {
+            return Err("the heap is full".to_string());
+        }
+        memory.update("car", car, free);
+        memory.update("cdr", cdr, free);
+        machine.advance_free();
+        Ok(free)
+    }
+
+    // the datum an exp stands for, an index in a list is its number, as it is
+    // when the list is written as text
+    pub fn exp_to_datum(exp: &Exp) -> Datum {
+        match exp {
+            Exp::Bool(x) => Datum::Bool(*x),
+            Exp::Integer(x) => Datum::Integer(*x),
+            Exp::FloatNumber(x) => Datum::Number(*x),
+            Exp::Symbol(x) => Datum::Symbol(x.clone()),
+            Exp::SchemeString(x) => Datum::Str(x.clone()),
+            Exp::Quote(x) => Datum::Quote(Box::new(quoted_datum(x))),
+            Exp::Index(x) => Datum::Integer(*x as i32),
+            Exp::Eof => Datum::Eof,
+            Exp::List(pair) => {
+                let mut items = vec![];
+                let mut pair = pair;
+                while let Pair::Cons(x, rest) = pair {
+                    items.push(exp_to_datum(x));
+                    pair = rest;
+                }
+                Datum::List(items)
+            }
+        }
+    }
+
+    // the datum of the text of a quote, text that isn't a datum such as a quote
+    // made by hand is a symbol
+    pub fn quoted_datum(text: &str) -> Datum {
+        read_datum(text).unwrap_or_else(|_e| Datum::Symbol(text.to_string()))
+    }
+
+    // an exp as it is kept in a register, a list is written into the heap
+    pub fn exp_to_object(
+        exp: &Exp,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<Object, String> {
+        match exp {
+            Exp::Index(x) => Ok(Object::Index(*x)),
+            x => materialize(&exp_to_datum(x), &mut HeapBackend { machine, memory }),
+        }
+    }
+
+    // the exp an object of a register or of the heap stands for, a list is read
+    // from the heap and an index that doesn't point to one, e.g. the offset of
+    // an instruction, is an Exp::Index
+    pub fn object_to_exp(object: &Object, memory: &Memory) -> Exp {
+        match object {
+            Object::Index(x) | Object::Pair(x) => list_at(*x, memory).unwrap_or(Exp::Index(*x)),
+            x => x.object_to_exp(),
+        }
+    }
+
+    // the list beginning at index i, None if the pairs there don't hold one
+    pub fn list_at(i: usize, memory: &Memory) -> Option<Exp> {
+        let mut items = vec![];
+        let mut index = i;
+        loop {
+            if index >= memory.the_cars.len() || items.len() > memory.the_cars.len() {
+                return None;
+            }
+            match memory.car(index) {
+                Object::Nil => {}
+                Object::Pair(x) => items.push(list_at(x, memory)?),
+                Object::Index(_) | Object::Empty => return None,
+                x => items.push(x.object_to_exp()),
+            }
+            match memory.cdr(index) {
+                Object::Nil => break,
+                Object::Pair(x) => index = x,
+                _ => return None,
+            }
+        }
+        let mut list = Exp::List(Pair::Nil);
+        for item in items.into_iter().rev() {
+            list = scheme_cons(item, list);
+        }
+        Some(list)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::datum::{
+        exp_to_datum, exp_to_object, lex, materialize, object_to_exp, read_atom, read_datum,
+        write_datum, Datum, ExpBackend, HeapBackend, TokenKind,
+    };
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::parserfordev::parser::str_to_exp;
+    use crate::representation::type_system::Object;
+    use crate::tpfordev::type_system::{Exp, Pair};
+
+    #[test]
+    fn read_datum_works() {
+        assert_eq!(read_atom("nan"), Datum::Symbol("nan".to_string()));
+        assert_eq!(read_atom("Nil"), Datum::Symbol("Nil".to_string()));
+        assert_eq!(read_atom("<="), Datum::Symbol("<=".to_string()));
+        assert_eq!(read_atom("-"), Datum::Symbol("-".to_string()));
+        assert_eq!(read_atom("-3"), Datum::Integer(-3));
+        assert_eq!(read_atom("1e3"), Datum::Number(1000.0));
+        assert_eq!(
+            read_datum("(a \"b  (c)\" '(1 (2)) ())"),
+            Ok(Datum::List(vec![
+                Datum::Symbol("a".to_string()),
+                Datum::Str("b  (c)".to_string()),
+                Datum::Quote(Box::new(Datum::List(vec![
+                    Datum::Integer(1),
+                    Datum::List(vec![Datum::Integer(2)]),
+                ]))),
+                Datum::List(vec![]),
+            ]))
+        );
+        let text = "(a \"b  (c)\" '(1 (2.0 'x)) () false)";
+        assert_eq!(write_datum(&read_datum(text).unwrap()), text);
+        assert_eq!(read_datum(""), Err("nothing to read".to_string()));
+        assert_eq!(
+            read_datum("(1 2"),
+            Err("unbalanced parentheses".to_string())
+        );
+        assert_eq!(read_datum(")("), Err("unbalanced parentheses".to_string()));
+        assert_eq!(
+            read_datum("(1) 2"),
+            Err("2 after the end of the form".to_string())
+        );
+        assert_eq!(read_datum("\"a b"), Err("unterminated string".to_string()));
+        assert_eq!(
+            read_datum("'"),
+            Err("unexpected end of the form".to_string())
+        );
+    }
+
+    #[test]
+    fn lex_works() {
+        let text = "(a ; b \"\n #| c #| d |# |#'x \"; (e)\")";
+        let tokens: Vec<(TokenKind, &str, String)> = lex(text)
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.kind, &text[x.range], x.span.to_string()))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::Open, "(", "1:1".to_string()),
+                (TokenKind::Atom, "a", "1:2".to_string()),
+                (TokenKind::Quote, "'", "2:17".to_string()),
+                (TokenKind::Atom, "x", "2:18".to_string()),
+                (TokenKind::Str, "\"; (e)\"", "2:20".to_string()),
+                (TokenKind::Close, ")", "2:27".to_string()),
+            ]
+        );
+        let r = lex("(a \"b").map_err(|(x, e)| format!("{} {}", x, e));
+        assert_eq!(r, Err("1:4 unterminated string".to_string()));
+        let r = lex("#| a\n #| b |#").map_err(|(x, e)| format!("{} {}", x, e));
+        assert_eq!(r, Err("1:1 unterminated #|".to_string()));
+    }
+
+    #[test]
+    fn backends_agree() {
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        let mut memory = Memory::new(50);
+        for text in &[
+            "(define x '(+ 1 2))",
+            "(1 (2 () 3) 4 (() 5))",
+            "()",
+            "3.5",
+            "nan",
+        ] {
+            let datum = read_datum(text).unwrap();
+            let exp = materialize(&datum, &mut ExpBackend).unwrap();
+            let mut heap = HeapBackend {
+                machine: &mut machine,
+                memory: &mut memory,
+            };
+            let object = materialize(&datum, &mut heap).unwrap();
+            assert_eq!(object_to_exp(&object, &memory), exp);
+            let object = exp_to_object(&exp, &mut machine, &mut memory).unwrap();
+            assert_eq!(object_to_exp(&object, &memory), exp);
+        }
+        let mut memory = Memory::new(2);
+        let r = exp_to_object(
+            &str_to_exp("(1 2 3)".to_string()),
+            &mut machine,
+            &mut memory,
+        );
+        assert_eq!(r, Err("the heap is full".to_string()));
+    }
+
+    #[test]
+    fn conversions_are_total() {
+        let memory = Memory::new(4);
+        assert_eq!(object_to_exp(&Object::Nil, &memory), Exp::List(Pair::Nil));
+        assert_eq!(object_to_exp(&Object::Empty, &memory), Exp::List(Pair::Nil));
+        assert_eq!(object_to_exp(&Object::Index(2), &memory), Exp::Index(2));
+        assert_eq!(object_to_exp(&Object::Pair(9), &memory), Exp::Index(9));
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        let mut memory = Memory::new(4);
+        let object = exp_to_object(&Exp::List(Pair::Nil), &mut machine, &mut memory).unwrap();
+        assert_eq!(object_to_exp(&object, &memory), Exp::List(Pair::Nil));
+        assert_eq!(
+            exp_to_object(&Exp::Eof, &mut machine, &mut memory),
+            Ok(Object::Eof)
+        );
+        let datum = exp_to_datum(&Exp::Eof);
+        assert_eq!(datum, Datum::Eof);
+        assert_eq!(materialize(&datum, &mut ExpBackend), Ok(Exp::Eof));
+        let exp = Exp::Quote("( 1 ( 2 3))".to_string());
+        assert_eq!(
+            materialize(&exp_to_datum(&exp), &mut ExpBackend),
+            Ok(Exp::Quote("(1 (2 3))".to_string()))
+        );
+    }
+}
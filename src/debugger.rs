@@ -0,0 +1,185 @@
+pub mod debugger {
+    use crate::commands::commands::run_command;
+    use crate::machine::basic_machine::{BasicMachine, Status};
+    use crate::memory::memory::Memory;
+    use crate::parserfordev::parser::display_exp;
+    use crate::reader::reader::Line;
+
+    const HELP: &str = "debugger commands:
+    s, step            run the next instruction
+    n, next            run until the next (goto (reg continue)) has been run
+    c, continue        run until a breakpoint, a watchpoint or the end
+    b LABEL N          set a breakpoint before the N-th instruction after LABEL
+    d LABEL N          cancel that breakpoint
+    r, regs            contents of the registers
+    k, stack           depth and top of the stack
+    w, where           the instruction the machine stopped at
+    q, quit            leave the debugger, the rest of the run is dropped
+    ,COMMAND           any REPL command such as ,env
+    h, help            this message";
+
+    // talk to the user about a machine that has stopped with the given status,
+    // lines are read through the reader of the machine. Returns true when the
+    // machine has run to the end and false when the user quits.
+    pub fn run_debugger(machine: &mut BasicMachine, memory: &mut Memory, status: Status) -> bool {
+        let mut status = status;
+        loop {
+            match status {
+                Status::Done => return true,
+                Status::Watchpoint(ref name, ref value) => {
+                    let value = machine.display_register_value(name, value, memory);
+                    println!("watchpoint {} = {}", name, value);
+                    where_am_i(machine);
+                }
+                Status::Breakpoint(ref label, offset) => {
+                    println!("breakpoint {}+{}", label, offset);
+                    where_am_i(machine);
+                }
+                Status::Stepped => where_am_i(machine),
+            }
+            status = loop {
+                let line = match machine.reader.read_line("debug> ") {
+                    Line::Text(x) => x,
+                    Line::Interrupted => continue,
+                    Line::Eof => return false,
+                };
+                let words: Vec<&str> = line.split_whitespace().collect();
+                match words.as_slice() {
+                    [] => continue,
+                    ["s"] | ["step"] => break machine.step(memory),
+                    ["n"] | ["next"] => break machine.step_over(memory),
+                    ["c"] | ["continue"] => break machine.proceed(memory),
+                    ["b", label, n] => {
+                        run_command(&format!(",break {} {}", label, n), machine, memory)
+                    }
+                    ["d", label, n] => {
+                        run_command(&format!(",unbreak {} {}", label, n), machine, memory)
+                    }
+                    ["r"] | ["regs"] => run_command(",regs", machine, memory),
+                    ["k"] | ["stack"] => run_command(",stack", machine, memory),
+                    ["w"] | ["where"] => where_am_i(machine),
+                    ["q"] | ["quit"] => return false,
+                    [x, ..] if x.starts_with(',') => run_command(line.trim(), machine, memory),
+                    _ => println!("{}", HELP),
+                }
+            };
+        }
+    }
+
+    // e.g. "next fact-loop+3 (line 6): (save continue)"
+    fn where_am_i(machine: &BasicMachine) {
+        let index = machine
+            .get_register(&"pc".to_string())
+            .unwrap()
+            .get_memory_index();
+        if index == machine.raw_instructions.len() {
+            println!("at the end");
+        } else {
+            let line = match machine.spans.get(index) {
+                Some(x) => format!(" (line {})", x.line),
+                None => "".to_string(),
+            };
+            println!(
+                "next {}{}: {}",
+                machine.location_of(index),
+                line,
+                display_exp(&machine.raw_instructions[index])
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::debugger::run_debugger;
+    use crate::assembler::assembler::assemble;
+    use crate::machine::basic_machine::{BasicMachine, Status};
+    use crate::machine_cases::machine_case::MachineCase;
+    use crate::memory::memory::Memory;
+    use crate::reader::reader::{Reader, TextSource};
+    use crate::representation::type_system::Object;
+
+    fn factorial(memory: &mut Memory, n: i32) -> BasicMachine {
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        machine.initialize_op();
+        let text = MachineCase::test_case().controller_text.to_string();
+        assemble(text, &mut machine, memory).unwrap();
+        machine.set_register_contents(&"exp".to_string(), Object::Integer(n));
+        machine
+    }
+
+    fn exp(machine: &BasicMachine) -> Option<Object> {
+        machine.get_register_contents(&"exp".to_string())
+    }
+
+    #[test]
+    fn breakpoint_works() {
+        let mut memory = Memory::new(20);
+        let mut machine = factorial(&mut memory, 3);
+        // (save exp)
+        machine.set_breakpoint(&"fact-loop".to_string(), 4);
+        let status = machine.execute(&mut memory);
+        assert_eq!(status, Status::Breakpoint("fact-loop".to_string(), 4));
+        assert_eq!(exp(&machine), Some(Object::Integer(3)));
+        assert_eq!(machine.location_of(4), "fact-loop+4");
+        // execute without proceed stays at the breakpoint
+        assert_eq!(machine.execute(&mut memory), status);
+        assert_eq!(machine.proceed(&mut memory), status);
+        assert_eq!(exp(&machine), Some(Object::Integer(2)));
+        machine.cancel_breakpoint(&"fact-loop".to_string(), 4);
+        assert_eq!(machine.proceed(&mut memory), Status::Done);
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Integer(6))
+        );
+    }
+
+    #[test]
+    fn step_works() {
+        let mut memory = Memory::new(20);
+        let mut machine = factorial(&mut memory, 3);
+        machine.set_breakpoint(&"after-fact".to_string(), 1);
+        assert_eq!(machine.step(&mut memory), Status::Stepped);
+        assert_eq!(
+            machine
+                .get_register(&"pc".to_string())
+                .unwrap()
+                .get_memory_index(),
+            1
+        );
+        // from the first after-fact up to its (goto (reg continue))
+        assert_eq!(
+            machine.execute(&mut memory),
+            Status::Breakpoint("after-fact".to_string(), 1)
+        );
+        assert_eq!(machine.step_over(&mut memory), Status::Stepped);
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Integer(2))
+        );
+        // the return lands on the breakpoint, which step_over doesn't stop at
+        assert_eq!(machine.step_over(&mut memory), Status::Stepped);
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Integer(6))
+        );
+        assert_eq!(machine.step_over(&mut memory), Status::Done);
+    }
+
+    #[test]
+    fn run_debugger_works() {
+        let mut memory = Memory::new(20);
+        let mut machine = factorial(&mut memory, 4);
+        let input = "s\nb fact-loop 1\nb nowhere 1\nc\nc\nd fact-loop 1\nr\nc\n";
+        machine.reader = Reader::new(Box::new(TextSource::new(input)), false);
+        assert!(run_debugger(&mut machine, &mut memory, Status::Stepped));
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Integer(24))
+        );
+        let mut machine = factorial(&mut memory, 4);
+        machine.reader = Reader::new(Box::new(TextSource::new("n\nq\n")), false);
+        assert!(!run_debugger(&mut machine, &mut memory, Status::Stepped));
+    }
+}
@@ -0,0 +1,313 @@
+pub mod diagnostics {
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::parser::parser::Span;
+    use crate::parserfordev::parser::display_exp;
+    use crate::tpfordev::type_system::{car, cdr, Exp};
+    use std::collections::HashSet;
+    use std::fmt;
+
+    // an error found in a controller before it is run. Position is the offset of
+    // the instruction in the instruction sequence and location the same in the
+    // label+n form breakpoints are given in, span is where the offending
+    // instruction or label starts in the text and text is the instruction or label.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Diagnostic {
+        pub position: usize,
+        pub location: String,
+        pub span: Option<Span>,
+        pub text: String,
+        pub message: String,
+    }
+
+    impl fmt::Display for Diagnostic {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.span {
+                Some(x) => write!(f, "{}", x)?,
+                None => write!(f, "{}", self.position)?,
+            }
+            write!(f, " ({}): {}: {}", self.location, self.message, self.text)
+        }
+    }
+
+    // one diagnostic per line, each starting with the name of the controller text
+    // such as its file, e.g. fact.rm:5:5 (fact-loop+2): unknown operation foo: ...
+    #[allow(dead_code)]
+    pub fn diagnostics_to_string(name: &str, diagnostics: &[Diagnostic]) -> String {
+        let lines: Vec<String> = diagnostics
+            .iter()
+            .map(|x| format!("{}:{}", name, x))
+            .collect();
+        lines.join("\n")
+    }
+
+    // everything that is wrong with a controller such as
+    // (controller (assign n (const 1)) loop (goto (label loop))), the ops are
+    // looked up in the machine. Spans are the starts of the labels and
+    // instructions in the text, see inner_element_spans. The labels of the
+    // machine are known to the controller as well.
+    pub fn check_controller(text: &Exp, spans: &[Span], machine: &BasicMachine) -> Vec<Diagnostic> {
+        let items = list_items(text);
+        let mut diagnostics = vec![];
+        let mut labels: HashSet<String> = machine.labels.keys().cloned().collect();
+        let mut offset = 0;
+        for (i, item) in items.iter().enumerate() {
+            if item.is_symbol() {
+                let name = display_exp(item);
+                if !labels.insert(name.clone()) {
+                    diagnostics.push(Diagnostic {
+                        position: offset,
+                        location: name.clone(),
+                        span: spans.get(i).copied(),
+                        text: name,
+                        message: "duplicate label".to_string(),
+                    });
+                }
+            } else {
+                offset += 1;
+            }
+        }
+        let mut offset = 0;
+        let mut label = ("".to_string(), 0);
+        for (i, item) in items.iter().enumerate() {
+            if item.is_symbol() {
+                label = (display_exp(item), offset);
+                continue;
+            }
+            let location = if label.0.is_empty() {
+                offset.to_string()
+            } else {
+                format!("{}+{}", label.0, offset - label.1 + 1)
+            };
+            for message in check_instruction(item, &labels, machine) {
+                diagnostics.push(Diagnostic {
+                    position: offset,
+                    location: location.clone(),
+                    span: spans.get(i).copied(),
+                    text: display_exp(item),
+                    message,
+                });
+            }
+            offset += 1;
+        }
+        diagnostics.sort_by_key(|x| x.position);
+        diagnostics
+    }
+
+    fn check_instruction(
+        inst: &Exp,
+        labels: &HashSet<String>,
+        machine: &BasicMachine,
+    ) -> Vec<String> {
+        let items = list_items(inst);
+        if items.is_empty() || !items[0].is_symbol() {
+            return vec!["not an instruction".to_string()];
+        }
+        let kind = display_exp(&items[0]);
+        let args = &items[1..];
+        let mut errors = vec![];
+        match kind.as_str() {
+            "assign" => {
+                if args.is_empty() || !args[0].is_symbol() {
+                    errors.push("assign expects a register".to_string());
+                } else if args.len() > 1 && tag_of(&args[1]) == Some("op".to_string()) {
+                    check_operation(&args[1..], labels, machine, &mut errors);
+                } else if args.len() == 2 {
+                    check_operand(&args[1], labels, &mut errors);
+                } else {
+                    errors.push("assign expects one value or an operation".to_string());
+                }
+            }
+            "test" | "perform" => {
+                if !args.is_empty() && tag_of(&args[0]) == Some("op".to_string()) {
+                    check_operation(args, labels, machine, &mut errors);
+                } else {
+                    errors.push(format!("{} expects an operation", kind));
+                }
+            }
+            "branch" => match args {
+                [x] if tag_of(x) == Some("label".to_string()) => {
+                    check_operand(x, labels, &mut errors)
+                }
+                _ => errors.push("branch expects a label".to_string()),
+            },
+            "goto" => match args {
+                [x] if tag_of(x) == Some("label".to_string())
+                    || tag_of(x) == Some("reg".to_string()) =>
+                {
+                    check_operand(x, labels, &mut errors)
+                }
+                _ => errors.push("goto expects a label or a register".to_string()),
+            },
+            "save" | "restore" => match args {
+                [x] if x.is_symbol() => {}
+                _ => errors.push(format!("{} expects a register", kind)),
+            },
+            "save-from-to" | "restore-to-from" => match args {
+                [x, y] if x.is_symbol() && y.is_symbol() => {}
+                _ => errors.push(format!("{} expects a register and a stack", kind)),
+            },
+            "halt" => {
+                if !args.is_empty() {
+                    errors.push("halt expects nothing".to_string());
+                }
+            }
+            _ => errors.push(format!("unknown instruction type {}", kind)),
+        }
+        errors
+    }
+
+    // ((op name) operand ...), the operands of an op are registers, constants and
+    // operations such as ((op +) (reg n) (const 1)). Labels are only allowed when
+    // the machine says so.
+    fn check_operation(
+        items: &[Exp],
+        labels: &HashSet<String>,
+        machine: &BasicMachine,
+        errors: &mut Vec<String>,
+    ) {
+        let name = match list_items(&items[0]).as_slice() {
+            [_op, x] if x.is_symbol() => display_exp(x),
+            _ => {
+                errors.push(format!("bad operation {}", display_exp(&items[0])));
+                return;
+            }
+        };
+        if !machine.is_semantic_op(&name) && !machine.is_machine_op(&name) {
+            errors.push(format!("unknown operation {}", name));
+        }
+        for operand in items[1..].iter() {
+            let nested = list_items(operand);
+            if !nested.is_empty() && tag_of(&nested[0]) == Some("op".to_string()) {
+                check_operation(&nested, labels, machine, errors);
+            } else if tag_of(operand) == Some("label".to_string()) && !machine.label_operands {
+                errors.push(format!(
+                    "operation {} applied to {}",
+                    name,
+                    display_exp(operand)
+                ));
+            } else {
+                check_operand(operand, labels, errors);
+            }
+        }
+    }
+
+    // (reg x), (const x) or (label x)
+    fn check_operand(operand: &Exp, labels: &HashSet<String>, errors: &mut Vec<String>) {
+        let items = list_items(operand);
+        let text = display_exp(operand);
+        match tag_of(operand).as_deref() {
+            Some("reg") => match items.as_slice() {
+                [_reg, x] if x.is_symbol() => {}
+                _ => errors.push(format!("bad register {}", text)),
+            },
+            Some("const") => {
+                if items.len() != 2 {
+                    errors.push(format!("bad constant {}", text));
+                }
+            }
+            Some("label") => match items.as_slice() {
+                [_label, x] if x.is_symbol() => {
+                    if !labels.contains(&display_exp(x)) {
+                        errors.push(format!("undefined label {}", display_exp(x)));
+                    }
+                }
+                _ => errors.push(format!("bad label {}", text)),
+            },
+            _ => errors.push(format!("unknown operand {}", text)),
+        }
+    }
+
+    // the symbol a list starts with
+    fn tag_of(exp: &Exp) -> Option<String> {
+        match car(exp) {
+            Ok(x) if x.is_symbol() => Some(display_exp(&x)),
+            _ => None,
+        }
+    }
+
+    fn list_items(exp: &Exp) -> Vec<Exp> {
+        let mut items = vec![];
+        let mut rest = exp.clone();
+        while rest.is_pair() {
+            items.push(car(&rest).unwrap());
+            rest = cdr(&rest).unwrap();
+        }
+        items
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::diagnostics::check_controller;
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::machine_cases::machine_case::MachineCase;
+    use crate::parser::parser::inner_element_spans;
+    use crate::parserfordev::parser::str_to_exp;
+
+    fn check(text: &str) -> Vec<(usize, String, String)> {
+        let mut machine = BasicMachine::new();
+        machine.initialize_op();
+        let spans = inner_element_spans(text);
+        check_controller(&str_to_exp(text.to_string()), &spans, &machine)
+            .into_iter()
+            .map(|x| (x.position, x.location, x.message))
+            .collect()
+    }
+
+    #[test]
+    fn check_controller_works() {
+        assert!(check(MachineCase::new().controller_text).is_empty());
+        assert!(check(MachineCase::test_case().controller_text).is_empty());
+        let text = "(controller
+                        (assgin n (const 1))
+                      loop
+                        (goto (label nowhere))
+                      loop
+                        (test (op foo) (reg n))
+                        (assign n (op +) (label loop) (const 1))
+                        (save (n)))";
+        let checkout = [
+            (0, "controller+1", "unknown instruction type assgin"),
+            (1, "loop+1", "undefined label nowhere"),
+            (2, "loop", "duplicate label"),
+            (2, "loop+1", "unknown operation foo"),
+            (3, "loop+2", "operation + applied to (label loop)"),
+            (4, "loop+3", "save expects a register"),
+        ];
+        let checkout: Vec<(usize, String, String)> = checkout
+            .iter()
+            .map(|(i, x, y)| (*i, x.to_string(), y.to_string()))
+            .collect();
+        assert_eq!(check(text), checkout);
+    }
+
+    #[test]
+    fn check_controller_knows_the_extensions() {
+        let text = "(controller
+                      loop
+                        (save-from-to n numbers)
+                        (restore-to-from n)
+                        (assign n (op +) ((op foo) (reg n)) (label loop))
+                        (halt))";
+        let checkout = [
+            (
+                1,
+                "loop+2",
+                "restore-to-from expects a register and a stack",
+            ),
+            (2, "loop+3", "unknown operation foo"),
+            (2, "loop+3", "operation + applied to (label loop)"),
+        ];
+        let checkout: Vec<(usize, String, String)> = checkout
+            .iter()
+            .map(|(i, x, y)| (*i, x.to_string(), y.to_string()))
+            .collect();
+        assert_eq!(check(text), checkout);
+        let mut machine = BasicMachine::new();
+        machine.initialize_op();
+        machine.label_operands = true;
+        let text = "(controller loop (assign n (op +) (label loop) (const 1)))";
+        let spans = inner_element_spans(text);
+        assert!(check_controller(&str_to_exp(text.to_string()), &spans, &machine).is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+pub mod embed {
+    use crate::convert::convert::{FromScheme, IntoScheme};
+    use crate::datum::datum::list_at;
+    use crate::machine::basic_machine::{BasicMachine, CallbackExp};
+    use crate::memory::memory::Memory;
+    use crate::parser::parser::read_scheme_forms;
+    use crate::representation::type_system::Object;
+    use crate::runner::runner::{eval_str, make_evaluator, panic_message};
+    use crate::tpfordev::type_system::{Exp, Pair};
+    use crate::trace::trace::display_object;
+    use std::cell::Cell;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::{Arc, Mutex};
+
+    // the explicit-control evaluator and its heap, for programs that embed it:
+    //   let mut interpreter = Interpreter::new();
+    //   interpreter.eval("(define (square x) (* x x))")?;
+    //   assert_eq!(interpreter.eval("(square 12)")?, Exp::Integer(144));
+    // Errors of the evaluated program are returned, the interpreter can be used
+    // on after them. The evaluator reports them by panicking, so this needs
+    // panic = "unwind", the default; under panic = "abort" the first error ends
+    // the host process.
+    pub struct Interpreter {
+        machine: BasicMachine,
+        memory: Memory,
+    }
+
+    impl Default for Interpreter {
+        fn default() -> Self {
+            Interpreter::new()
+        }
+    }
+
+    impl Interpreter {
+        pub fn new() -> Self {
+            Interpreter::with_heap(50000)
+        }
+
+        // size is the number of pairs in the heap
+        pub fn with_heap(size: usize) -> Self {
+            let mut memory = Memory::new(size);
+            let machine = make_evaluator(&mut memory);
+            Interpreter { machine, memory }
+        }
+
+        // evaluate the forms of text one after another in the global environment,
+        // the value of the last one is returned, lists are read from the heap
+        pub fn eval(&mut self, text: &str) -> Result<Exp, String> {
+            let object = self.eval_object(text)?;
+            self.value_of(&object)
+        }
+
+        // the same as eval, the value is left as it is in register val, a list
+        // is an index into the heap then
+        pub fn eval_object(&mut self, text: &str) -> Result<Object, String> {
+            let (machine, memory) = (&mut self.machine, &mut self.memory);
+            quietly(|| {
+                let forms =
+                    panic::catch_unwind(|| read_scheme_forms(text)).map_err(panic_message)?;
+                let mut value = None;
+                for form in forms {
+                    value = Some(eval_str(&form, machine, memory)?);
+                }
+                value.ok_or_else(|| "nothing to evaluate".to_string())
+            })
+        }
+
+        // the same as eval, the value is converted to T, e.g.
+        //   let xs: Vec<i32> = interpreter.eval_as("(cons 1 (cons 2 ()))")?;
+        pub fn eval_as<T: FromScheme>(&mut self, text: &str) -> Result<T, String> {
+            let object = self.eval_object(text)?;
+            T::from_scheme(&object, &self.memory)
+        }
+
+        // bind name to value in the global environment, for the programs
+        // evaluated after it
+        pub fn define<T: IntoScheme>(&mut self, name: &str, value: T) -> Result<(), String> {
+            let object = value.into_scheme(&mut self.machine, &mut self.memory)?;
+            let value = self.value_of(&object)?;
+            self.machine.define_global(name, value, &mut self.memory);
+            Ok(())
+        }
+
+        // see BasicMachine::define_primitive, e.g.
+        //   fn twice(args: &Exp) -> Exp { ... }
+        //   interpreter.define_primitive("twice", twice);
+        pub fn define_primitive(&mut self, name: &str, func: CallbackExp) {
+            self.machine.define_primitive(name, func, &mut self.memory);
+        }
+
+        // the value an object of a register or of the heap stands for
+        pub fn value_of(&self, object: &Object) -> Result<Exp, String> {
+            value_of(object, &self.memory)
+        }
+
+        pub fn machine(&self) -> &BasicMachine {
+            &self.machine
+        }
+
+        pub fn machine_mut(&mut self) -> &mut BasicMachine {
+            &mut self.machine
+        }
+
+        pub fn heap(&self) -> &Memory {
+            &self.memory
+        }
+
+        // the pairs written so far, the heap isn't collected while evaluating
+        pub fn heap_in_use(&self) -> usize {
+            match self.machine.get_register_contents(&"free".to_string()) {
+                Some(Object::Index(x)) => x,
+                _ => 0,
+            }
+        }
+    }
+
+    // the panics f ends in are errors returned to the host, the panic hook
+    // mustn't print them to its stderr. The hook is swapped for one that keeps
+    // quiet on this thread and hands the panics of other threads on to the old
+    // hook. Afterwards the old hook itself is put back, not a wrapper around it,
+    // such that evaluating doesn't grow a chain of hooks. Swapping is serialized
+    // as the hook is global.
+    pub(crate) static HOOK: Mutex<()> = Mutex::new(());
+
+    thread_local! {
+        static QUIET: Cell<bool> = const { Cell::new(false) };
+    }
+
+    fn quietly<T>(f: impl FnOnce() -> T) -> T {
+        let _guard = HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let hook = Arc::new(panic::take_hook());
+        let old = Arc::clone(&hook);
+        panic::set_hook(Box::new(move |info| {
+            if !QUIET.with(|quiet| quiet.get()) {
+                old(info);
+            }
+        }));
+        QUIET.with(|quiet| quiet.set(true));
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        QUIET.with(|quiet| quiet.set(false));
+        // dropping the quiet hook drops its share of the old one
+        drop(panic::take_hook());
+        match Arc::try_unwrap(hook) {
+            Ok(hook) => panic::set_hook(hook),
+            Err(_hook) => unreachable!("the quiet hook is still installed"),
+        }
+        match result {
+            Ok(x) => x,
+            Err(e) => panic::resume_unwind(e),
+        }
+    }
+
+    // lists are read from the heap, an index that doesn't point to a proper list
+    // there is an error
+    pub fn value_of(object: &Object, memory: &Memory) -> Result<Exp, String> {
+        match object {
+            Object::Index(i) => match list_at(*i, memory) {
+                Some(x) => Ok(x),
+                None => Err(format!("no list at {}", display_object(object))),
+            },
+            Object::Nil => Ok(Exp::List(Pair::Nil)),
+            Object::Pair(_) | Object::Empty => {
+                Err(format!("{} isn't a value", display_object(object)))
+            }
+            x => Ok(x.object_to_exp()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::embed::Interpreter;
+    use crate::parserfordev::parser::str_to_exp;
+    use crate::primitives::primitives::add;
+    use crate::representation::type_system::Object;
+    use crate::tpfordev::type_system::{car, Exp};
+    use std::cell::Cell;
+    use std::panic;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn double(args: &Exp) -> Exp {
+        match car(args) {
+            Ok(Exp::Integer(x)) => Exp::Integer(2 * x),
+            _ => panic!("Error: double expects an integer"),
+        }
+    }
+
+    #[test]
+    fn interpreter_works() {
+        let mut interpreter = Interpreter::with_heap(5000);
+        let r = interpreter.eval("(define (square x) (* x x)) (square 12)");
+        assert_eq!(r, Ok(Exp::Integer(144)));
+        let r = interpreter.eval("(cons 1 (cons (square 2) ()))");
+        assert_eq!(r, Ok(str_to_exp("(1 4)".to_string())));
+        let r = interpreter.eval("(cube 2)");
+        assert_eq!(r, Err("Error: unbound variable cube".to_string()));
+        assert_eq!(interpreter.eval(""), Err("nothing to evaluate".to_string()));
+        assert_eq!(interpreter.eval("(square 3)"), Ok(Exp::Integer(9)));
+        let value = interpreter.eval_object("(cons 1 ())").unwrap();
+        assert!(matches!(value, Object::Index(_)));
+        assert!(interpreter.heap_in_use() > 0);
+        assert_eq!(
+            interpreter.value_of(&value),
+            Ok(str_to_exp("(1)".to_string()))
+        );
+    }
+
+    #[test]
+    fn host_primitives_work() {
+        let mut interpreter = Interpreter::with_heap(5000);
+        interpreter.define_primitive("double", double);
+        interpreter.define_primitive("plus", add);
+        let r = interpreter.eval("(define (f x) (plus (double x) 1)) (f 20)");
+        assert_eq!(r, Ok(Exp::Integer(41)));
+        let r = interpreter.eval("(double 'a)");
+        assert_eq!(r, Err("Error: double expects an integer".to_string()));
+        // the built-in primitives are still there
+        assert_eq!(interpreter.eval("(car (cons 1 2))"), Ok(Exp::Integer(1)));
+    }
+
+    thread_local! {
+        static REPORTED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[test]
+    fn errors_are_not_reported_by_the_panic_hook() {
+        // the hook stands for the host's, the default one writes to stderr
+        let guard = super::embed::HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_info| REPORTED.with(|n| n.set(n.get() + 1))));
+        drop(guard);
+        let mut interpreter = Interpreter::with_heap(5000);
+        let r = interpreter.eval("(car 1)");
+        assert!(r.is_err());
+        assert!(interpreter.eval("(+ 1").is_err());
+        assert_eq!(REPORTED.with(|n| n.get()), 0);
+        // the host's hook is back afterwards
+        let guard = super::embed::HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let r = panic::catch_unwind(|| panic!("Error: a host panic"));
+        assert!(r.is_err());
+        assert_eq!(REPORTED.with(|n| n.get()), 1);
+        panic::set_hook(hook);
+        drop(guard);
+    }
+
+    #[test]
+    fn the_panic_hook_is_put_back() {
+        let guard = super::embed::HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let old = panic::take_hook();
+        // not zero sized, such that the box has an address of its own
+        let count = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&count);
+        let hook: Box<dyn Fn(&panic::PanicHookInfo) + Sync + Send> = Box::new(move |_info| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+        let address = &*hook as *const _ as *const u8;
+        panic::set_hook(hook);
+        drop(guard);
+        let mut interpreter = Interpreter::with_heap(5000);
+        for _i in 0..100 {
+            assert!(interpreter.eval("(car 1)").is_err());
+        }
+        let guard = super::embed::HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let hook = panic::take_hook();
+        assert_eq!(&*hook as *const _ as *const u8, address);
+        panic::set_hook(old);
+        drop(guard);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+}
@@ -243,7 +243,139 @@ pub mod garbage_collector {
             _ => panic!("not a proper label!"),
         }
     }
+
+    // registers that hold a label or an index of the machine itself rather than
+    // a list in memory, together with the registers of the collector above
+    const NOT_ROOTS: [&str; 9] = [
+        "pc",
+        "continue",
+        "free",
+        "scan",
+        "root",
+        "old",
+        "oldcr",
+        "new",
+        "relocate_continue",
+    ];
+
+    // a stop-and-copy collection in the style of garbage_collector, but driven by
+    // loops instead of the register-machine labels. Every other register that
+    // holds an index is a root. The stack is not scanned since saved labels and
+    // saved lists can't be told apart, so this must only run when the stack is
+    // empty, e.g. between two top-level forms. Returns the number of pairs alive.
+    #[allow(dead_code)]
+    pub fn stop_and_copy(machine: &mut BasicMachine, memory: &mut Memory) -> usize {
+        let mut free: usize = 0;
+        let mut roots: Vec<String> = machine
+            .registers
+            .keys()
+            .filter(|x| !NOT_ROOTS.contains(&x.as_str()))
+            .cloned()
+            .collect();
+        roots.sort();
+        for name in roots {
+            if let Some(Object::Index(i)) = machine.get_register_contents(&name) {
+                let new = relocate(i, memory, &mut free);
+                machine.set_register_contents(&name, Object::Index(new));
+            }
+        }
+        let mut scan: usize = 0;
+        while scan < free {
+            if let Object::Pair(i) = memory.new_car(scan) {
+                let new = relocate(i, memory, &mut free);
+                memory.update("new_car", Object::Pair(new), scan);
+            }
+            if let Object::Pair(i) = memory.new_cdr(scan) {
+                let new = relocate(i, memory, &mut free);
+                memory.update("new_cdr", Object::Pair(new), scan);
+            }
+            scan += 1;
+        }
+        memory.flip();
+        // the old memory is full of broken hearts now, clear it for the next flip
+        for i in 0..memory.new_cars.len() {
+            memory.update("new_car", Object::Empty, i);
+            memory.update("new_cdr", Object::Empty, i);
+        }
+        machine.set_register_contents(&"free".to_string(), Object::Index(free));
+        free
+    }
+
+    // move a pair from the working memory to the new memory unless it has been
+    // moved already, and return its new index
+    fn relocate(old: usize, memory: &mut Memory, free: &mut usize) -> usize {
+        let broken_heart = Object::Symbol("broken_heart".to_string());
+        if memory.car(old) == broken_heart {
+            if let Object::Index(new) = memory.cdr(old) {
+                return new;
+            }
+        }
+        let new = *free;
+        *free += 1;
+        memory.update("new_car", memory.car(old), new);
+        memory.update("new_cdr", memory.cdr(old), new);
+        memory.update("car", broken_heart, old);
+        memory.update("cdr", Object::Index(new), old);
+        new
+    }
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::garbage_collector::stop_and_copy;
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::representation::type_system::Object;
+
+    #[test]
+    fn stop_and_copy_works() {
+        let mut memory = Memory::new(100);
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        let env = "(((x y) 1 (2 \"two\")) ((z) ()))";
+        machine.set_register_contents_as_in_memory(
+            &"val".to_string(),
+            "(a b c)".to_string(),
+            &mut memory,
+        );
+        machine.set_register_contents_as_in_memory(
+            &"env".to_string(),
+            env.to_string(),
+            &mut memory,
+        );
+        machine.set_register_contents_as_in_memory(
+            &"exp".to_string(),
+            "(1 2)".to_string(),
+            &mut memory,
+        );
+        let exp = machine.get_register_contents_as_in_memory(&"exp".to_string(), &memory);
+        // val becomes garbage
+        machine.set_register_contents(&"val".to_string(), Object::Integer(3));
+        machine.set_register_contents(&"continue".to_string(), Object::Index(42));
+        let before = machine
+            .get_register(&"free".to_string())
+            .unwrap()
+            .get_memory_index();
+        let env_before = machine.get_register_contents_as_in_memory(&"env".to_string(), &memory);
+        let alive = stop_and_copy(&mut machine, &mut memory);
+        assert_eq!(alive, before - 3);
+        assert_eq!(
+            machine.get_register_contents(&"free".to_string()),
+            Some(Object::Index(alive))
+        );
+        assert_eq!(
+            machine.get_register_contents_as_in_memory(&"env".to_string(), &memory),
+            env_before
+        );
+        assert_eq!(
+            machine.get_register_contents_as_in_memory(&"exp".to_string(), &memory),
+            exp
+        );
+        assert_eq!(
+            machine.get_register_contents(&"continue".to_string()),
+            Some(Object::Index(42))
+        );
+        // collecting again moves nothing
+        assert_eq!(stop_and_copy(&mut machine, &mut memory), alive);
+    }
+}
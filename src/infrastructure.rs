@@ -1,10 +1,12 @@
 pub mod register {
     use crate::memory::memory::Memory;
     use crate::representation::type_system::Object;
-    use std::{fmt, usize};
+    use std::{fmt, mem, usize};
     pub struct Register {
-        pub name: &'static str,
+        pub name: String,
         pub contents: Object,
+        // report every change of the contents, see SICP exercise 5.18
+        pub traced: bool,
     }
 
     impl fmt::Display for Register {
@@ -14,18 +16,20 @@ pub mod register {
     }
 
     impl Register {
-        pub fn new(s: &'static str) -> Self {
+        pub fn new(s: &str) -> Self {
             Register {
-                name: s,
+                name: s.to_string(),
                 contents: Object::Quote("unsigned".to_string()),
+                traced: false,
             }
         }
         pub fn get(&self) -> &Object {
             &self.contents
         }
 
-        pub fn set(&mut self, val: Object) {
-            self.contents = val;
+        // the old contents are returned
+        pub fn set(&mut self, val: Object) -> Object {
+            mem::replace(&mut self.contents, val)
         }
 
         pub fn get_memory_index(&self) -> usize {
@@ -37,13 +41,7 @@ pub mod register {
         }
 
         pub fn print_list(&self, memory: &Memory) {
-            print!("(");
-            let i = self.get_memory_index();
-            let car_item = memory.car(i);
-            let cdr_item = memory.cdr(i);
-
-            print_list_iter(&car_item, &cdr_item, memory);
-            print!("\n");
+            println!("{}", self.get_list_frome_memory_as_str(memory));
         }
 
         pub fn get_list_frome_memory_as_str(&self, memory: &Memory) -> String {
@@ -132,102 +130,371 @@ pub mod register {
             }
         }
     }
+}
 
-    fn print_list_iter(car_item: &Object, cdr_item: &Object, memory: &Memory) {
-        match car_item {
-            Object::Bool(x) => print!("{} ", *x),
-            Object::Integer(x) => print!("{} ", *x),
-            Object::LispString(x) => print!("{} ", *x),
-            Object::Number(x) => print!("{} ", *x),
-            Object::Quote(x) => print!("{} ", *x),
-            Object::Symbol(x) => print!("{} ", *x),
-            Object::Pair(x) => {
-                print!("(");
-                let car_item = &memory.car(*x);
-                let cdr_item = &memory.cdr(*x);
-                print_list_iter(car_item, cdr_item, memory);
-            }
-            _ => {
-                panic!("not a proper object in car position of A Lisp List!");
-            }
-        }
+pub mod stack {
+    use crate::representation::type_system::Object;
+    use std::collections::{BTreeMap, HashMap};
+    use std::fmt;
 
-        match cdr_item {
-            Object::Nil => {
-                print!(")");
-            }
-            Object::Pair(x) => {
-                let car_item = &memory.car(*x);
-                let cdr_item = &memory.cdr(*x);
-                print_list_iter(car_item, cdr_item, memory);
-            }
-            _ => {
-                panic!("not a proper object in cdr position of A Lisp List!");
+    // how save and restore share the stack, see SICP exercise 5.11
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum StackDiscipline {
+        // a single stack, restore takes whatever is on top
+        Shared,
+        // a single stack, restoring a value into another register than the one
+        // it was saved from is an error
+        Tagged,
+        // a stack per register, restore takes the last value saved from the
+        // register itself
+        PerRegister,
+    }
+
+    impl StackDiscipline {
+        pub fn from_name(name: &str) -> Option<Self> {
+            match name {
+                "shared" => Some(StackDiscipline::Shared),
+                "tagged" => Some(StackDiscipline::Tagged),
+                "per-register" => Some(StackDiscipline::PerRegister),
+                _ => None,
             }
         }
     }
-}
 
-pub mod stack {
-    use crate::representation::type_system::Object;
-    use std::fmt;
+    // the pushes of the values saved from one register and how many of them
+    // were on the stack at the same time
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    pub struct RegisterStatistics {
+        pub pushes: i32,
+        pub depth: i32,
+        pub max_depth: i32,
+    }
+
+    // everything a stack holds, in an order that doesn't depend on hashing, such
+    // that it can be saved and a stack made from it again
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct StackState {
+        pub capacity: usize,
+        pub discipline: StackDiscipline,
+        pub push_count: i32,
+        pub current_depth: i32,
+        pub max_depth: i32,
+        // the values from the bottom up with the registers they were saved from
+        pub items: Vec<(String, Object)>,
+        pub per_register: BTreeMap<String, Vec<Object>>,
+        pub registers: BTreeMap<String, RegisterStatistics>,
+    }
+
     pub struct Stack {
         capacity: usize,
+        discipline: StackDiscipline,
         push_count: i32,
         current_depth: i32,
         max_depth: i32,
         container: Vec<Object>,
+        // the register each item was saved from, empty when it is not known
+        sources: Vec<String>,
+        // the stacks of PerRegister, the container is left empty then
+        per_register: HashMap<String, Vec<Object>>,
+        registers: BTreeMap<String, RegisterStatistics>,
     }
 
     impl Stack {
         pub fn new() -> Self {
+            Stack::with_capacity(100)
+        }
+
+        pub fn with_capacity(capacity: usize) -> Self {
+            Stack::with_discipline(capacity, StackDiscipline::Shared)
+        }
+
+        // capacity is the number of values on the stack in total, whatever the
+        // discipline is
+        pub fn with_discipline(capacity: usize, discipline: StackDiscipline) -> Self {
             Stack {
-                capacity: 100,
+                capacity,
+                discipline,
                 push_count: 0,
                 current_depth: 0,
                 max_depth: 0,
                 container: Vec::new(),
+                sources: Vec::new(),
+                per_register: HashMap::new(),
+                registers: BTreeMap::new(),
+            }
+        }
+
+        #[allow(dead_code)]
+        pub fn state(&self) -> StackState {
+            StackState {
+                capacity: self.capacity,
+                discipline: self.discipline,
+                push_count: self.push_count,
+                current_depth: self.current_depth,
+                max_depth: self.max_depth,
+                items: self
+                    .sources
+                    .iter()
+                    .cloned()
+                    .zip(self.container.iter().cloned())
+                    .collect(),
+                per_register: self
+                    .per_register
+                    .iter()
+                    .map(|(name, x)| (name.clone(), x.clone()))
+                    .collect(),
+                registers: self.registers.clone(),
+            }
+        }
+
+        #[allow(dead_code)]
+        pub fn from_state(state: StackState) -> Self {
+            let (sources, container) = state.items.into_iter().unzip();
+            Stack {
+                capacity: state.capacity,
+                discipline: state.discipline,
+                push_count: state.push_count,
+                current_depth: state.current_depth,
+                max_depth: state.max_depth,
+                container,
+                sources,
+                per_register: state.per_register.into_iter().collect(),
+                registers: state.registers,
             }
         }
 
+        #[allow(dead_code)]
+        pub fn capacity(&self) -> usize {
+            self.capacity
+        }
+
+        pub fn discipline(&self) -> StackDiscipline {
+            self.discipline
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.current_depth == 0
+        }
+
+        // empty the stack and reset the statistics, while the capacity and the
+        // discipline are kept
+        pub fn initialize(&mut self) {
+            self.push_count = 0;
+            self.current_depth = 0;
+            self.max_depth = 0;
+            self.container.clear();
+            self.sources.clear();
+            self.per_register.clear();
+            self.registers.clear();
+        }
+
+        #[allow(dead_code)]
         pub fn push(&mut self, item: Object) {
-            if self.container.len() < self.capacity {
+            self.push_from("", item);
+        }
+
+        // push the contents of a register, see pop_into
+        pub fn push_from(&mut self, register: &str, item: Object) {
+            if self.current_depth as usize >= self.capacity {
+                panic!("Maximum depth violated!");
+            }
+            if self.discipline == StackDiscipline::PerRegister {
+                self.per_register
+                    .entry(register.to_string())
+                    .or_default()
+                    .push(item);
+            } else {
                 self.container.push(item);
-                self.push_count += 1;
-                self.current_depth += 1;
-                if self.current_depth > self.max_depth {
-                    self.max_depth = self.current_depth;
+                self.sources.push(register.to_string());
+            }
+            self.push_count += 1;
+            self.current_depth += 1;
+            if self.current_depth > self.max_depth {
+                self.max_depth = self.current_depth;
+            }
+            if !register.is_empty() {
+                let statistics = self.registers.entry(register.to_string()).or_default();
+                statistics.pushes += 1;
+                statistics.depth += 1;
+                if statistics.depth > statistics.max_depth {
+                    statistics.max_depth = statistics.depth;
                 }
-            } else {
-                panic!("Maximum depth violated!");
             }
         }
 
+        // the last value given to push
+        #[allow(dead_code)]
         pub fn pop(&mut self) -> Option<Object> {
+            self.take("")
+        }
+
+        // restore a register, what is taken depends on the discipline. Only the
+        // Tagged discipline checks the register a value was saved from (SICP
+        // exercise 5.11 b), restore takes the top of the stack as it is otherwise.
+        pub fn pop_into(&mut self, register: &str) -> Object {
+            if self.discipline == StackDiscipline::Tagged {
+                match self.sources.last() {
+                    Some(x) if !x.is_empty() && x != register => panic!(
+                        "restore {}: the top of the stack was saved from {}",
+                        register, x
+                    ),
+                    _ => {}
+                }
+            }
+            match self.take(register) {
+                Some(x) => x,
+                None if self.discipline == StackDiscipline::PerRegister => {
+                    panic!(
+                        "restore {}: nothing has been saved from {}",
+                        register, register
+                    )
+                }
+                None => panic!("restore {}: the stack is empty", register),
+            }
+        }
+
+        fn take(&mut self, register: &str) -> Option<Object> {
+            let (item, source) = if self.discipline == StackDiscipline::PerRegister {
+                let item = self.per_register.get_mut(register)?.pop()?;
+                (item, register.to_string())
+            } else {
+                let item = self.container.pop()?;
+                (item, self.sources.pop().unwrap())
+            };
             self.current_depth -= 1;
-            self.container.pop()
+            if let Some(x) = self.registers.get_mut(&source) {
+                x.depth -= 1;
+            }
+            Some(item)
         }
 
         #[allow(dead_code)]
         pub fn peek(&self) -> Option<&Object> {
-            self.container.last()
+            if self.discipline == StackDiscipline::PerRegister {
+                self.per_register.get("").and_then(|x| x.last())
+            } else {
+                self.container.last()
+            }
+        }
+
+        // by the name of the register the values were saved from
+        #[allow(dead_code)]
+        pub fn register_statistics(&self) -> &BTreeMap<String, RegisterStatistics> {
+            &self.registers
         }
 
         pub fn statistics(&self) {
             println!("Stack statistics => ");
             println!("total-pushes = {}", self.push_count);
             println!("maximum-depth = {}", self.max_depth);
+            for (name, x) in self.registers.iter() {
+                println!(
+                    "  {}: pushes = {}, maximum-depth = {}",
+                    name, x.pushes, x.max_depth
+                );
+            }
         }
     }
 
     impl fmt::Display for Stack {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            write!(
-                f,
-                "Depth: {}, top: {:?}",
-                self.container.len(),
-                self.container[self.container.len() - 1]
-            )
+            if self.discipline == StackDiscipline::PerRegister {
+                write!(f, "Depth: {}", self.current_depth)?;
+                let mut names: Vec<&String> = self.per_register.keys().collect();
+                names.sort();
+                for name in names {
+                    if let Some(x) = self.per_register[name].last() {
+                        write!(f, ", top of {}: {:?}", name, x)?;
+                    }
+                }
+                return Ok(());
+            }
+            match self.container.last() {
+                Some(x) => write!(f, "Depth: {}, top: {:?}", self.container.len(), x),
+                None => write!(f, "Depth: 0"),
+            }
+        }
+    }
+}
+
+pub mod profile {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    // calls of one op and the time spent in them
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    pub struct OpProfile {
+        pub calls: u64,
+        pub time: Duration,
+    }
+
+    // SICP exercise 5.15, instructions are counted by their offset in the
+    // instruction sequence such that counting is cheap, they are summed up per
+    // label when a report is made
+    pub struct Profile {
+        instruction_count: u64,
+        instruction_counts: Vec<u64>,
+        ops: HashMap<String, OpProfile>,
+    }
+
+    impl Default for Profile {
+        fn default() -> Self {
+            Profile::new()
+        }
+    }
+
+    impl Profile {
+        pub fn new() -> Self {
+            Profile {
+                instruction_count: 0,
+                instruction_counts: Vec::new(),
+                ops: HashMap::new(),
+            }
+        }
+
+        pub fn reset(&mut self) {
+            self.instruction_count = 0;
+            self.instruction_counts.clear();
+            self.ops.clear();
+        }
+
+        pub fn count_instruction(&mut self, index: usize) {
+            if index >= self.instruction_counts.len() {
+                self.instruction_counts.resize(index + 1, 0);
+            }
+            self.instruction_counts[index] += 1;
+            self.instruction_count += 1;
+        }
+
+        pub fn count_op(&mut self, name: &str, time: Duration) {
+            match self.ops.get_mut(name) {
+                Some(x) => {
+                    x.calls += 1;
+                    x.time += time;
+                }
+                None => {
+                    let x = OpProfile { calls: 1, time };
+                    self.ops.insert(name.to_string(), x);
+                }
+            }
+        }
+
+        pub fn instruction_count(&self) -> u64 {
+            self.instruction_count
+        }
+
+        // how often the instruction at each offset has been run
+        pub fn instruction_counts(&self) -> &[u64] {
+            &self.instruction_counts
+        }
+
+        // the most expensive op first
+        #[allow(dead_code)]
+        pub fn ops(&self) -> Vec<(String, OpProfile)> {
+            let mut ops: Vec<(String, OpProfile)> =
+                self.ops.iter().map(|(k, v)| (k.clone(), *v)).collect();
+            ops.sort_by(|x, y| y.1.time.cmp(&x.1.time).then(x.0.cmp(&y.0)));
+            ops
         }
     }
 }
@@ -240,7 +507,10 @@ mod test {
     use crate::parser::parser::tokenizer;
     use crate::representation::type_system::Object;
 
-    use super::{register::Register, stack::Stack};
+    use super::stack::{RegisterStatistics, StackDiscipline};
+    use super::{profile::Profile, register::Register, stack::Stack};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::time::Duration;
 
     #[test]
     fn register_get_works() {
@@ -251,8 +521,40 @@ mod test {
     #[test]
     fn register_set_works() {
         let mut r = Register::new("Alpha");
-        r.set(Object::Quote("apple".to_string()));
+        let old = r.set(Object::Quote("apple".to_string()));
         assert_eq!(Object::Quote("apple".to_string()), *r.get());
+        assert_eq!(Object::Quote("unsigned".to_string()), old);
+    }
+
+    #[test]
+    fn stack_disciplines_work() {
+        let one = || Object::Integer(1);
+        let two = || Object::Integer(2);
+        let mut s = Stack::with_discipline(10, StackDiscipline::Shared);
+        s.push_from("n", one());
+        s.push_from("val", two());
+        assert_eq!(s.pop_into("n"), two());
+        let mut s = Stack::with_discipline(10, StackDiscipline::Tagged);
+        s.push_from("n", one());
+        s.push_from("val", two());
+        let r = panic::catch_unwind(AssertUnwindSafe(|| s.pop_into("n")));
+        assert!(r.is_err());
+        let mut s = Stack::with_discipline(10, StackDiscipline::PerRegister);
+        s.push_from("n", one());
+        s.push_from("val", two());
+        s.push_from("n", two());
+        assert_eq!(s.pop_into("n"), two());
+        assert_eq!(s.pop_into("n"), one());
+        assert_eq!(s.to_string(), "Depth: 1, top of val: Integer(2)");
+        let checkout = RegisterStatistics {
+            pushes: 2,
+            depth: 0,
+            max_depth: 2,
+        };
+        assert_eq!(s.register_statistics()["n"], checkout);
+        assert_eq!(s.register_statistics()["val"].pushes, 1);
+        let r = panic::catch_unwind(AssertUnwindSafe(|| s.pop_into("n")));
+        assert!(r.is_err());
     }
 
     #[test]
@@ -263,6 +565,38 @@ mod test {
         assert_eq!(item, Object::Quote("Winter".to_string()));
     }
 
+    #[test]
+    fn stack_initialize_works() {
+        let mut s = Stack::with_capacity(2);
+        s.push(Object::Integer(1));
+        s.push(Object::Integer(2));
+        s.initialize();
+        assert_eq!(s.peek(), None);
+        assert_eq!(s.capacity(), 2);
+        s.push(Object::Integer(3));
+        s.push(Object::Integer(4));
+    }
+
+    #[test]
+    fn profile_works() {
+        let mut p = Profile::new();
+        p.count_instruction(2);
+        p.count_instruction(0);
+        p.count_instruction(2);
+        assert_eq!(p.instruction_count(), 3);
+        assert_eq!(p.instruction_counts(), &[1, 0, 2]);
+        p.count_op("car", Duration::from_micros(5));
+        p.count_op("cdr", Duration::from_micros(8));
+        p.count_op("car", Duration::from_micros(4));
+        let ops = p.ops();
+        assert_eq!(ops[0].0, "car");
+        assert_eq!(ops[0].1.calls, 2);
+        assert_eq!(ops[1].0, "cdr");
+        p.reset();
+        assert_eq!(p.instruction_count(), 0);
+        assert!(p.ops().is_empty());
+    }
+
     #[test]
     fn get_list_from_memory_as_str_works() {
         let mut memory = Memory::new(30);
@@ -272,15 +606,15 @@ mod test {
                            (() 
                                3  
                                   ))";
-        let mut tokens = tokenizer(s.to_string());
-        let root = build_syntax_tree_into_memeory(&mut tokens, &mut memory, &mut machine);
+        let tokens = tokenizer(s.to_string());
+        let root = build_syntax_tree_into_memeory(&tokens, &mut memory, &mut machine);
         machine.set_register_contents(&"root".to_string(), Object::Index(root));
         let reg = machine.get_register(&"root".to_string()).unwrap();
         let s = String::from("( 1 2(() 3))");
         assert_eq!(s, reg.get_list_frome_memory_as_str(&memory));
         let ss = "(()( 7 8) 9)";
-        let mut ttokens = tokenizer(ss.to_string());
-        let another_root = build_syntax_tree_into_memeory(&mut ttokens, &mut memory, &mut machine);
+        let ttokens = tokenizer(ss.to_string());
+        let another_root = build_syntax_tree_into_memeory(&ttokens, &mut memory, &mut machine);
         machine.set_register_contents(&"root".to_string(), Object::Index(another_root));
         let s = String::from("(()( 7 8) 9)");
         assert_eq!(
@@ -291,15 +625,15 @@ mod test {
                 .get_list_frome_memory_as_str(&memory)
         );
         let s = "()";
-        let mut tokens = tokenizer(s.to_string());
-        let root = build_syntax_tree_into_memeory(&mut tokens, &mut memory, &mut machine);
+        let tokens = tokenizer(s.to_string());
+        let root = build_syntax_tree_into_memeory(&tokens, &mut memory, &mut machine);
         machine.set_register_contents(&"root".to_string(), Object::Index(root));
         let reg = machine.get_register(&"root".to_string()).unwrap();
         let s = String::from("()");
         assert_eq!(s, reg.get_list_frome_memory_as_str(&memory));
         let s = "(1 2 (3 () 4) (() 5))";
-        let mut tokens = tokenizer(s.to_string());
-        let root = build_syntax_tree_into_memeory(&mut tokens, &mut memory, &mut machine);
+        let tokens = tokenizer(s.to_string());
+        let root = build_syntax_tree_into_memeory(&tokens, &mut memory, &mut machine);
         machine.set_register_contents(&"root".to_string(), Object::Index(root));
         let reg = machine.get_register(&"root".to_string()).unwrap();
         let s = String::from("( 1 2( 3() 4)(() 5))");
@@ -0,0 +1,397 @@
+pub mod json {
+    use crate::convert::convert::{FromScheme, IntoScheme};
+    use crate::datum::datum::{materialize, quoted_datum, Datum, ExpBackend};
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::representation::type_system::Object;
+    use crate::tpfordev::type_system::{car, scheme_cons, Exp, Pair};
+    use serde::de::{
+        self, Deserialize, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor,
+    };
+    use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+    use std::collections::BTreeSet;
+    use std::convert::TryFrom;
+    use std::fmt;
+
+    // Scheme data as JSON, built with the serde feature. A list is an array, but
+    // an alist of (key value) lists whose keys are strings or symbols is an
+    // object, such that ((name "x") (size 2)) is {"name":"x","size":2}. Symbols
+    // are strings and quoted data is written as the data, '(1 2) as [1,2] and 'x
+    // as "x". An object read from JSON is an alist with string keys and null is
+    // the empty list, the same as [].
+    //
+    // The shape decides, so the conversion loses what JSON can't tell apart: a
+    // list of two-item lists that begin with distinct strings or symbols is
+    // written as an object even if it isn't meant as an alist, ((a 1) (b 2))
+    // comes back as (("a" 1) ("b" 2)). An alist with a key that appears more
+    // than once is written as an array instead, an object would keep only one
+    // of its values.
+    //
+    // Objects of the machine are written with HeapValue, which reads their lists
+    // from the heap, and read with HeapSeed, which writes them into it.
+
+    impl Serialize for Exp {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self {
+                Exp::Integer(x) => serializer.serialize_i32(*x),
+                Exp::FloatNumber(x) => serializer.serialize_f32(*x),
+                Exp::Bool(x) => serializer.serialize_bool(*x),
+                Exp::SchemeString(x) | Exp::Symbol(x) => serializer.serialize_str(x),
+                Exp::Quote(x) => materialize(&quoted_datum(x), &mut ExpBackend)
+                    .map_err(ser::Error::custom)?
+                    .serialize(serializer),
+                Exp::Index(x) => serializer.serialize_u64(*x as u64),
+                Exp::Eof => Err(ser::Error::custom("the eof object has no JSON form")),
+                Exp::List(_) => {
+                    let items = items(self);
+                    match alist_entries(&items) {
+                        Some(entries) => {
+                            let mut map = serializer.serialize_map(Some(entries.len()))?;
+                            for (key, value) in entries {
+                                map.serialize_entry(&key, value)?;
+                            }
+                            map.end()
+                        }
+                        None => {
+                            let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                            for item in items.iter() {
+                                seq.serialize_element(item)?;
+                            }
+                            seq.end()
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Exp {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(ExpVisitor)
+        }
+    }
+
+    struct ExpVisitor;
+
+    impl<'de> Visitor<'de> for ExpVisitor {
+        type Value = Exp;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a JSON value")
+        }
+
+        fn visit_bool<E: de::Error>(self, x: bool) -> Result<Exp, E> {
+            Ok(Exp::Bool(x))
+        }
+
+        // integers beyond i32 become floats, as they do in the reader
+        fn visit_i64<E: de::Error>(self, x: i64) -> Result<Exp, E> {
+            Ok(i32::try_from(x).map_or(Exp::FloatNumber(x as f32), Exp::Integer))
+        }
+
+        fn visit_u64<E: de::Error>(self, x: u64) -> Result<Exp, E> {
+            Ok(i32::try_from(x).map_or(Exp::FloatNumber(x as f32), Exp::Integer))
+        }
+
+        fn visit_f64<E: de::Error>(self, x: f64) -> Result<Exp, E> {
+            Ok(Exp::FloatNumber(x as f32))
+        }
+
+        fn visit_str<E: de::Error>(self, x: &str) -> Result<Exp, E> {
+            Ok(Exp::SchemeString(x.to_string()))
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Exp, E> {
+            Ok(Exp::List(Pair::Nil))
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Exp, A::Error> {
+            let mut items = vec![];
+            while let Some(x) = seq.next_element()? {
+                items.push(x);
+            }
+            Ok(list(items))
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Exp, A::Error> {
+            let mut items = vec![];
+            while let Some((key, value)) = map.next_entry::<String, Exp>()? {
+                items.push(list(vec![Exp::SchemeString(key), value]));
+            }
+            Ok(list(items))
+        }
+    }
+
+    fn items(exp: &Exp) -> Vec<&Exp> {
+        let mut items = vec![];
+        if let Exp::List(pair) = exp {
+            let mut pair = pair;
+            while let Pair::Cons(x, rest) = pair {
+                items.push(&**x);
+                pair = rest;
+            }
+        }
+        items
+    }
+
+    // the keys and values of an alist, None if the items aren't (key value) lists
+    // or a key appears twice
+    fn alist_entries<'a>(items: &[&'a Exp]) -> Option<Vec<(String, &'a Exp)>> {
+        if items.is_empty() {
+            return None;
+        }
+        let mut entries: Vec<(String, &Exp)> = vec![];
+        for item in items {
+            match items_of_pair(item)? {
+                (Exp::SchemeString(x), value) | (Exp::Symbol(x), value) => {
+                    entries.push((x.clone(), value))
+                }
+                // a quoted symbol, 'name
+                (Exp::Quote(x), value) => match quoted_datum(x) {
+                    Datum::Symbol(x) => entries.push((x, value)),
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        }
+        let keys: BTreeSet<&str> = entries.iter().map(|(key, _value)| key.as_str()).collect();
+        if keys.len() < entries.len() {
+            return None;
+        }
+        Some(entries)
+    }
+
+    fn items_of_pair(exp: &Exp) -> Option<(&Exp, &Exp)> {
+        match items(exp).as_slice() {
+            [key, value] if exp.is_list() => Some((key, value)),
+            _ => None,
+        }
+    }
+
+    fn list(items: Vec<Exp>) -> Exp {
+        items
+            .into_iter()
+            .rev()
+            .fold(Exp::List(Pair::Nil), |rest, x| scheme_cons(x, rest))
+    }
+
+    // an object of a register or of the heap as JSON, e.g.
+    //   serde_json::to_string(&HeapValue::new(&object, interpreter.heap()))
+    pub struct HeapValue<'a> {
+        object: &'a Object,
+        memory: &'a Memory,
+    }
+
+    impl<'a> HeapValue<'a> {
+        pub fn new(object: &'a Object, memory: &'a Memory) -> Self {
+            HeapValue { object, memory }
+        }
+    }
+
+    impl<'a> Serialize for HeapValue<'a> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            Exp::from_scheme(self.object, self.memory)
+                .map_err(ser::Error::custom)?
+                .serialize(serializer)
+        }
+    }
+
+    // JSON read into the heap, the object that stands for it is the value, e.g.
+    //   HeapSeed::new(machine, memory).deserialize(&mut deserializer)
+    pub struct HeapSeed<'a> {
+        machine: &'a mut BasicMachine,
+        memory: &'a mut Memory,
+    }
+
+    impl<'a> HeapSeed<'a> {
+        pub fn new(machine: &'a mut BasicMachine, memory: &'a mut Memory) -> Self {
+            HeapSeed { machine, memory }
+        }
+    }
+
+    impl<'a, 'de> DeserializeSeed<'de> for HeapSeed<'a> {
+        type Value = Object;
+
+        fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Object, D::Error> {
+            Exp::deserialize(deserializer)?
+                .into_scheme(self.machine, self.memory)
+                .map_err(de::Error::custom)
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn exp_to_json(exp: &Exp) -> Result<String, String> {
+        serde_json::to_string(exp).map_err(|e| e.to_string())
+    }
+
+    #[allow(dead_code)]
+    pub fn json_to_exp(text: &str) -> Result<Exp, String> {
+        serde_json::from_str(text).map_err(|e| format!("bad JSON: {}", e))
+    }
+
+    // the data object stands for, lists are read from the heap
+    #[allow(dead_code)]
+    pub fn object_to_json(object: &Object, memory: &Memory) -> Result<String, String> {
+        serde_json::to_string(&HeapValue::new(object, memory)).map_err(|e| e.to_string())
+    }
+
+    // the data of the JSON text written into the heap
+    #[allow(dead_code)]
+    pub fn json_to_object(
+        text: &str,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<Object, String> {
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        let object = HeapSeed::new(machine, memory)
+            .deserialize(&mut deserializer)
+            .map_err(|e| format!("bad JSON: {}", e))?;
+        deserializer.end().map_err(|e| format!("bad JSON: {}", e))?;
+        Ok(object)
+    }
+
+    // the primitives json->scheme and scheme->json
+    #[allow(dead_code)]
+    pub fn json_to_scheme(args: &Exp) -> Exp {
+        match car(args) {
+            Ok(Exp::SchemeString(x)) => match json_to_exp(&x) {
+                Ok(x) => x,
+                Err(e) => panic!("Error: json->scheme: {}", e),
+            },
+            _ => panic!("Error: json->scheme expects a string"),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn scheme_to_json(args: &Exp) -> Exp {
+        match car(args).map(|x| exp_to_json(&x)) {
+            Ok(Ok(x)) => Exp::SchemeString(x),
+            Ok(Err(e)) => panic!("Error: scheme->json: {}", e),
+            Err(_e) => panic!("Error: scheme->json expects an argument"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::json::{
+        exp_to_json, json_to_exp, json_to_object, object_to_json, HeapSeed, HeapValue,
+    };
+    use crate::embed::embed::{value_of, Interpreter};
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::parserfordev::parser::str_to_exp;
+    use crate::representation::type_system::Object;
+    use crate::tpfordev::type_system::Exp;
+    use serde::de::DeserializeSeed;
+
+    #[test]
+    fn json_round_trips() {
+        let exp = str_to_exp("((name \"x\") (size 2) (tags (1 2.5 true ())))".to_string());
+        let text = exp_to_json(&exp).unwrap();
+        assert_eq!(text, r#"{"name":"x","size":2,"tags":[1,2.5,true,[]]}"#);
+        let back = json_to_exp(&text).unwrap();
+        assert_eq!(
+            back,
+            str_to_exp("((\"name\" \"x\") (\"size\" 2) (\"tags\" (1 2.5 true ())))".to_string())
+        );
+        assert_eq!(exp_to_json(&back).unwrap(), text);
+        assert_eq!(json_to_exp("null"), Ok(str_to_exp("()".to_string())));
+        assert_eq!(json_to_exp("3000000000"), Ok(Exp::FloatNumber(3e9)));
+        assert!(json_to_exp("[1,").unwrap_err().starts_with("bad JSON: "));
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        let mut memory = Memory::new(100);
+        let object = json_to_object("[[1,2],{\"a\":[]}]", &mut machine, &mut memory).unwrap();
+        let text = object_to_json(&object, &memory).unwrap();
+        assert_eq!(text, r#"[[1,2],{"a":[]}]"#);
+    }
+
+    #[test]
+    fn lossy_shapes_are_handled() {
+        // a key that appears twice keeps the alist an array
+        let exp = str_to_exp("((a 1) (b 2) (a 3))".to_string());
+        assert_eq!(exp_to_json(&exp).unwrap(), r#"[["a",1],["b",2],["a",3]]"#);
+        // a list that looks like an alist is an object, its symbols come back
+        // as strings
+        let exp = str_to_exp("((a 1) (b 2))".to_string());
+        let text = exp_to_json(&exp).unwrap();
+        assert_eq!(text, r#"{"a":1,"b":2}"#);
+        assert_eq!(
+            json_to_exp(&text),
+            Ok(str_to_exp("((\"a\" 1) (\"b\" 2))".to_string()))
+        );
+    }
+
+    #[test]
+    fn quoted_data_round_trips() {
+        for (text, json) in &[
+            ("'(1 2 3)", "[1,2,3]"),
+            ("'((a 1) (b (2 3)))", r#"{"a":1,"b":[2,3]}"#),
+            ("'((a 1) (a 2))", r#"[["a",1],["a",2]]"#),
+            ("('x 'y)", r#"["x","y"]"#),
+        ] {
+            let exp = str_to_exp(text.to_string());
+            assert_eq!(exp_to_json(&exp).unwrap(), *json);
+            let back = json_to_exp(json).unwrap();
+            assert_eq!(exp_to_json(&back).unwrap(), *json);
+        }
+        let mut interpreter = Interpreter::with_heap(50000);
+        let r = interpreter.eval("(scheme->json '((width 3) (height 4)))");
+        assert_eq!(
+            r,
+            Ok(Exp::SchemeString(r#"{"width":3,"height":4}"#.to_string()))
+        );
+    }
+
+    #[test]
+    fn heap_values_work() {
+        let mut interpreter = Interpreter::with_heap(5000);
+        let object = interpreter
+            .eval_object("(cons 1 (cons (cons 2 ()) ()))")
+            .unwrap();
+        let text = serde_json::to_string(&HeapValue::new(&object, interpreter.heap())).unwrap();
+        assert_eq!(text, "[1,[2]]");
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        let mut memory = Memory::new(100);
+        let mut deserializer = serde_json::Deserializer::from_str(r#"{"k":[true]}"#);
+        let object = HeapSeed::new(&mut machine, &mut memory)
+            .deserialize(&mut deserializer)
+            .unwrap();
+        assert!(matches!(object, Object::Index(_)));
+        assert_eq!(
+            value_of(&object, &memory),
+            Ok(str_to_exp("((\"k\" (true)))".to_string()))
+        );
+        let r = serde_json::to_string(&HeapValue::new(&Object::Index(1000), &memory));
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn json_primitives_work() {
+        let mut interpreter = Interpreter::with_heap(50000);
+        let config = json_to_exp(r#"{"width":3,"height":4}"#).unwrap();
+        interpreter.define("config", config).unwrap();
+        let program = "
+            (define (lookup key alist)
+              (if (= (car (car alist)) key)
+                  (car (cdr (car alist)))
+                  (lookup key (cdr alist))))
+            (* (lookup \"width\" config) (lookup \"height\" config))";
+        assert_eq!(interpreter.eval(program), Ok(Exp::Integer(12)));
+        let r = interpreter.eval("(scheme->json (cons 1 (cons (= 1 1) ())))");
+        assert_eq!(r, Ok(Exp::SchemeString("[1,true]".to_string())));
+        let r = interpreter.eval("(scheme->json config)");
+        assert_eq!(
+            r,
+            Ok(Exp::SchemeString(r#"{"width":3,"height":4}"#.to_string()))
+        );
+        let r = interpreter.eval("(lookup \"height\" (json->scheme (scheme->json config)))");
+        assert_eq!(r, Ok(Exp::Integer(4)));
+        let r = interpreter.eval("(json->scheme \"[1,\")");
+        assert!(r
+            .unwrap_err()
+            .starts_with("Error: json->scheme: bad JSON: "));
+    }
+}
@@ -0,0 +1,52 @@
+// the register machine of SICP chapter 5 with the explicit-control evaluator
+// running on it. The API is the re-exports below: Interpreter to embed the
+// evaluator, the values it reads and returns, the conversions between them
+// and Rust types, and Memory and value_of to look into its heap, with the
+// serde feature HeapValue and HeapSeed to write its objects as JSON and read
+// them. MachineBuilder and BasicMachine build and run machines of other
+// controllers, and run_cli is the whole binary. The modules are private.
+
+pub(crate) mod assembler;
+pub(crate) mod bytecode;
+pub(crate) mod cli;
+pub(crate) mod commands;
+pub(crate) mod compiler;
+pub(crate) mod controller_file;
+pub(crate) mod convert;
+pub(crate) mod datum;
+pub(crate) mod debugger;
+pub(crate) mod diagnostics;
+pub(crate) mod embed;
+pub(crate) mod evaluator;
+pub(crate) mod gc;
+pub(crate) mod infrastructure;
+#[cfg(feature = "serde")]
+pub(crate) mod json;
+pub(crate) mod machine;
+pub(crate) mod machine_builder;
+#[cfg(test)]
+pub(crate) mod machine_cases;
+pub(crate) mod memory;
+pub(crate) mod parser;
+pub(crate) mod parserfordev;
+pub(crate) mod primitives;
+pub(crate) mod reader;
+pub(crate) mod representation;
+pub(crate) mod runner;
+pub(crate) mod snapshot;
+pub(crate) mod tpfordev;
+pub(crate) mod trace;
+
+pub use cli::cli::run_cli;
+pub use convert::convert::{FromScheme, IntoScheme};
+pub use embed::embed::{value_of, Interpreter};
+pub use infrastructure::stack::StackDiscipline;
+#[cfg(feature = "serde")]
+pub use json::json::{HeapSeed, HeapValue};
+pub use machine::basic_machine::{BasicMachine, CallbackExp, Callbackmachine, Status};
+pub use machine_builder::machine_builder::MachineBuilder;
+pub use memory::memory::Memory;
+pub use representation::type_system::Object;
+pub use tpfordev::type_system::{Exp, Pair};
+
+pub(crate) use tpfordev::type_system::scheme_list;
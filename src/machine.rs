@@ -1,35 +1,103 @@
 pub mod basic_machine {
-    use crate::assembler::assembler::{consume_box_closure, make_execution_procedure};
+    use crate::assembler::assembler::{
+        consume_box_closure, make_execution_procedure, ControllerInfo,
+    };
+    use crate::datum::datum::{exp_to_object, object_to_exp};
+    use crate::infrastructure::profile::Profile;
     use crate::infrastructure::register::Register;
     use crate::infrastructure::stack::Stack;
     use crate::memory::memory::Memory;
-    use crate::parserfordev::parser::{exp_to_str, str_to_exp};
+    use crate::parser::parser::Span;
+    use crate::parserfordev::parser::{display_exp, exp_to_str, str_to_exp};
     use crate::primitives::primitives::*;
     use crate::primitives::primitives::{
-        announce_output, define_variable, initialize_stack, machine_statistics,
+        announce_output, define_variable, final_statistics, initialize_stack, machine_statistics,
     };
+    use crate::reader::reader::Reader;
     use crate::representation::type_system::Object;
     use crate::scheme_list;
     use crate::tpfordev::type_system::{append, car, cdr, scheme_cons, Exp, Pair};
+    use crate::trace::trace::{
+        display_object, RegisterChange, RegisterTrace, StdoutSink, TraceEvent, TraceSink,
+    };
     use std::collections::HashMap;
     use std::mem;
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    // why execute returned, after a watchpoint execute can be called again to go on
+    // with the next instruction, after a breakpoint proceed has to be called
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Status {
+        Done,
+        // the register and the value that matched the predicate
+        Watchpoint(String, Object),
+        // label and offset of the breakpoint, the instruction there hasn't been run
+        Breakpoint(String, usize),
+        // returned by step and step_over
+        Stepped,
+    }
+
+    // execution pauses as soon as the register is set to a value for which the
+    // predicate holds
+    pub struct Watchpoint {
+        pub register: String,
+        pub predicate: Box<dyn Fn(&Object) -> bool>,
+    }
 
     pub struct BasicMachine {
         pub registers: HashMap<String, Register>,
         pub stack: Stack,
+        // the stacks of save-from-to and restore-to-from, made by the assembler
+        pub stacks: HashMap<String, Stack>,
+        // ops may be applied to (label x), which SICP exercise 5.9 rules out
+        pub label_operands: bool,
         pub semantic_ops: HashMap<String, CallbackExp>,
         pub machine_ops: HashMap<String, Callbackmachine>,
+        // primitive procedures of the host, see define_primitive
+        pub primitives: HashMap<String, CallbackExp>,
         // instructions that are generated by assembler
         pub instruction_sequence:
             Vec<Option<Box<dyn FnOnce(&mut BasicMachine, &mut Memory) -> Exp>>>,
         // native machine instructions
         pub raw_instructions: Vec<Exp>,
+        // where each raw instruction starts in the controller text
+        pub spans: Vec<Span>,
+        // what the controller text is called in messages, such as the file it was
+        // read from
+        pub source_name: String,
         // label-index pair for instructions
         pub labels: HashMap<String, usize>,
+        // what the assembled controller does, see ControllerInfo
+        pub info: ControllerInfo,
+        // hand each instruction that has been run to the trace sink
+        pub trace: bool,
+        pub trace_sink: Box<dyn TraceSink>,
+        // changes of traced registers that haven't been handed to the sink yet
+        pub register_changes: Vec<RegisterChange>,
+        pub watchpoints: Vec<Watchpoint>,
+        pub watchpoint_hit: Option<(String, Object)>,
+        // instruction index to the label and offset given to set_breakpoint
+        pub breakpoints: HashMap<usize, (String, usize)>,
+        // run the instruction at pc even if there is a breakpoint, set by proceed
+        pub proceeding: bool,
+        // instructions run, in total and per offset, and the calls of each op
+        pub profile: Profile,
+        // time the calls of ops, which costs a clock reading for each of them
+        pub profiling: bool,
+        // print stack statistics when the machine-statistics op is performed
+        pub statistics: bool,
+        // no prompt and no announcement of output in the read-eval-print-loop
+        pub quiet: bool,
+        // files that are being loaded, the innermost one is the last, relative paths
+        // given to load are resolved against it
+        pub loading: Vec<PathBuf>,
+        // where the read op takes its input from
+        pub reader: Reader,
     }
 
-    type CallbackExp = fn(&Exp) -> Exp;
-    type Callbackmachine = fn(&mut BasicMachine, &mut Memory) -> Exp;
+    pub type CallbackExp = fn(&Exp) -> Exp;
+    pub type Callbackmachine = fn(&mut BasicMachine, &mut Memory) -> Exp;
 
     impl BasicMachine {
         // Register exp is used to hold the expression to be evaluated
@@ -86,9 +154,13 @@ pub mod basic_machine {
         #[allow(dead_code)]
         pub fn initialize_env(&mut self, memory: &mut Memory) {
             let mut env = Exp::List(Pair::Nil);
-            let primitives = [
-                "car", "cdr", "cons", "null?", "+", "-", "*", "/", "<", ">", "=",
+            let mut primitives = vec![
+                "car", "cdr", "cons", "null?", "+", "-", "*", "/", "<", ">", "=", "display",
+                "newline", "load",
             ];
+            if cfg!(feature = "serde") {
+                primitives.extend(["json->scheme", "scheme->json"]);
+            }
             for item in primitives.iter() {
                 let mut p = str_to_exp("(primitive )".to_string());
                 let token = Exp::Symbol((**item).to_string());
@@ -112,12 +184,17 @@ pub mod basic_machine {
         pub fn initialize_op(&mut self) {
             let machine_ops = [
                 "machine-statistics",
+                "final-statistics",
                 "initialize-stack",
                 "prompt-for-input",
+                "announce-output",
                 "read",
+                "load-file",
+                "finish-load",
+                "instruction-count",
+                "reset-instruction-count",
             ];
             let semantic_ops = [
-                "announce-output",
                 "user-print",
                 "multiply",
                 "division",
@@ -164,15 +241,40 @@ pub mod basic_machine {
                 "application?",
                 "self-evaluating?",
                 "compound-procedure?",
+                "make-compiled-procedure",
+                "compiled-procedure?",
+                "compiled-procedure-entry",
+                "lexical-address-lookup",
+                "lexical-address-set!",
                 "extend-environment",
                 "lookup-variable-value",
                 "define-variable!",
                 "set-variable-value!",
+                "load-procedure?",
+                "signal-error",
+                "eof-object?",
+                "+",
+                "-",
+                "*",
+                "/",
+                "=",
+                "<",
+                ">",
             ];
 
-            let machine_ops_object = [machine_statistics, initialize_stack, prompt_for_input, read];
-            let semantic_ops_object = [
+            let machine_ops_object = [
+                machine_statistics,
+                final_statistics,
+                initialize_stack,
+                prompt_for_input,
                 announce_output,
+                read,
+                load_file,
+                finish_load,
+                instruction_count,
+                reset_instruction_count,
+            ];
+            let semantic_ops_object = [
                 user_print,
                 multiply,
                 division,
@@ -219,10 +321,25 @@ pub mod basic_machine {
                 is_application,
                 is_self_evaluating,
                 is_compound_procedure,
+                make_compiled_procedure,
+                is_compiled_procedure,
+                compiled_procedure_entry,
+                lexical_address_lookup,
+                lexical_address_set,
                 extend_environment,
                 lookup_variable_value,
                 define_variable,
                 set_variable_value,
+                is_load_procedure,
+                signal_error,
+                is_eof_object,
+                add,
+                substract,
+                multiply,
+                division,
+                is_eq,
+                is_smaller_than,
+                is_larger_than,
             ];
             let mut count = 0;
             while count != machine_ops.len() {
@@ -239,32 +356,275 @@ pub mod basic_machine {
             }
         }
 
+        // run instructions from the current pc until it falls off the end of the
+        // instruction sequence, a watchpoint is hit or a breakpoint is reached
         #[allow(dead_code)]
-        pub fn execute(&mut self, memory: &mut Memory) {
-            let reg = self.get_register(&"pc".to_string()).unwrap();
-            let index = reg.get_memory_index();
+        pub fn execute(&mut self, memory: &mut Memory) -> Status {
             let max_offset = self.instruction_sequence.len();
-            println!(
-                "instruction that is running =>  {}",
-                exp_to_str(self.raw_instructions[index].clone())
-            );
-            if index == max_offset {
-                println!("Done!");
-                return;
+            self.watchpoint_hit = None;
+            self.report_register_changes(memory);
+            loop {
+                let reg = self.get_register(&"pc".to_string()).unwrap();
+                let index = reg.get_memory_index();
+                if index == max_offset {
+                    self.proceeding = false;
+                    return Status::Done;
+                }
+                if !self.proceeding {
+                    if let Some((label, offset)) = self.breakpoints.get(&index) {
+                        return Status::Breakpoint(label.clone(), *offset);
+                    }
+                }
+                self.proceeding = false;
+                if let Some(status) = self.run_instruction(index, memory) {
+                    return status;
+                }
+            }
+        }
+
+        // run the single instruction at index, which must be the pc
+        fn run_instruction(&mut self, index: usize, memory: &mut Memory) -> Option<Status> {
+            let before = if self.trace {
+                Some(self.registers_except_pc())
             } else {
-                let cb = mem::replace(&mut self.instruction_sequence[index], None);
-                match cb {
-                    Some(x) => {
-                        let _r = consume_box_closure(x, self, memory);
+                None
+            };
+            self.profile.count_instruction(index);
+            let cb = mem::take(&mut self.instruction_sequence[index]);
+            match cb {
+                Some(x) => {
+                    let _r = consume_box_closure(x, self, memory);
+                }
+                None => {
+                    let inst = self.raw_instructions[index].clone();
+                    let x = make_execution_procedure(inst, self, memory);
+                    let _r = consume_box_closure(x, self, memory);
+                }
+            }
+            if let Some(before) = before {
+                self.record_trace(index, before);
+            }
+            self.report_register_changes(memory);
+            self.watchpoint_hit
+                .take()
+                .map(|(name, value)| Status::Watchpoint(name, value))
+        }
+
+        // SICP exercise 5.19, the breakpoint is set just before the offset-th
+        // instruction after the label, offset 1 being the first one
+        #[allow(dead_code)]
+        pub fn set_breakpoint(&mut self, label: &String, offset: usize) {
+            let index = self.breakpoint_index(label, offset);
+            self.breakpoints.insert(index, (label.clone(), offset));
+        }
+
+        #[allow(dead_code)]
+        pub fn cancel_breakpoint(&mut self, label: &String, offset: usize) {
+            let index = self.breakpoint_index(label, offset);
+            self.breakpoints.remove(&index);
+        }
+
+        #[allow(dead_code)]
+        pub fn cancel_all_breakpoints(&mut self) {
+            self.breakpoints.clear();
+        }
+
+        fn breakpoint_index(&self, label: &String, offset: usize) -> usize {
+            let start = match self.labels.get(label) {
+                Some(x) => *x,
+                None => panic!("Unknown label {}", label),
+            };
+            if offset == 0 || start + offset > self.raw_instructions.len() {
+                panic!("No instruction at {}+{}", label, offset);
+            }
+            start + offset - 1
+        }
+
+        // go on after a breakpoint, the instruction the machine stopped at is run
+        #[allow(dead_code)]
+        pub fn proceed(&mut self, memory: &mut Memory) -> Status {
+            self.proceeding = true;
+            self.execute(memory)
+        }
+
+        // run the instruction at pc and stop
+        #[allow(dead_code)]
+        pub fn step(&mut self, memory: &mut Memory) -> Status {
+            let index = self
+                .get_register(&"pc".to_string())
+                .unwrap()
+                .get_memory_index();
+            if index == self.instruction_sequence.len() {
+                return Status::Done;
+            }
+            self.watchpoint_hit = None;
+            self.report_register_changes(memory);
+            self.proceeding = false;
+            self.run_instruction(index, memory)
+                .unwrap_or(Status::Stepped)
+        }
+
+        // run up to and including the next (goto (reg continue)), that is, until
+        // the subroutine the machine is in returns. Breakpoints and watchpoints on
+        // the way still stop it.
+        #[allow(dead_code)]
+        pub fn step_over(&mut self, memory: &mut Memory) -> Status {
+            let ret = str_to_exp("(goto (reg continue))".to_string());
+            self.watchpoint_hit = None;
+            self.report_register_changes(memory);
+            let mut first = true;
+            loop {
+                let index = self
+                    .get_register(&"pc".to_string())
+                    .unwrap()
+                    .get_memory_index();
+                if index == self.instruction_sequence.len() {
+                    return Status::Done;
+                }
+                if !first {
+                    if let Some((label, offset)) = self.breakpoints.get(&index) {
+                        return Status::Breakpoint(label.clone(), *offset);
                     }
-                    None => {
-                        let inst = self.raw_instructions[index].clone();
-                        let x = make_execution_procedure(inst, self, memory);
-                        let _r = consume_box_closure(x, self, memory);
+                }
+                first = false;
+                let is_return = self.raw_instructions[index] == ret;
+                if let Some(status) = self.run_instruction(index, memory) {
+                    return status;
+                }
+                if is_return {
+                    return Status::Stepped;
+                }
+            }
+        }
+
+        // where an instruction comes from, for messages about it, such as
+        // "evaluator.rs controller line 47: (restore benv)"
+        #[allow(dead_code)]
+        pub fn describe_instruction(&self, index: usize) -> String {
+            let inst = display_exp(&self.raw_instructions[index]);
+            match self.spans.get(index) {
+                Some(span) => format!("{} line {}: {}", self.source_name, span.line, inst),
+                None => format!("{} {}: {}", self.source_name, self.location_of(index), inst),
+            }
+        }
+
+        // label+offset of an instruction, in the same form as breakpoints are given
+        #[allow(dead_code)]
+        pub fn location_of(&self, index: usize) -> String {
+            match self.label_of(index) {
+                Some(label) => {
+                    let start = self.labels[&label];
+                    format!("{}+{}", label, index - start + 1)
+                }
+                None => format!("{}", index),
+            }
+        }
+
+        // SICP exercise 5.16
+        #[allow(dead_code)]
+        pub fn trace_on(&mut self) {
+            self.trace = true;
+        }
+
+        #[allow(dead_code)]
+        pub fn trace_off(&mut self) {
+            self.trace = false;
+        }
+
+        #[allow(dead_code)]
+        pub fn set_trace_sink(&mut self, sink: Box<dyn TraceSink>) {
+            self.trace_sink = sink;
+        }
+
+        // the label an instruction belongs to is the nearest one before it, among
+        // labels at the same offset the first in alphabetical order is taken
+        #[allow(dead_code)]
+        pub fn label_of(&self, offset: usize) -> Option<String> {
+            let mut found: Option<(&String, usize)> = None;
+            for (name, i) in self.labels.iter() {
+                if *i > offset {
+                    continue;
+                }
+                found = match found {
+                    Some((x, j)) if j > *i || (j == *i && x < name) => Some((x, j)),
+                    _ => Some((name, *i)),
+                };
+            }
+            found.map(|(name, _i)| name.clone())
+        }
+
+        // an index held by pc or continue is the offset of an instruction, shown
+        // with the label there if any, any other index is a list in memory
+        pub fn display_register_value(
+            &self,
+            name: &str,
+            value: &Object,
+            memory: &Memory,
+        ) -> String {
+            match value {
+                Object::Index(i) if name == "pc" || name == "continue" => {
+                    let mut labels: Vec<&String> = self
+                        .labels
+                        .iter()
+                        .filter(|(_name, x)| *x == i)
+                        .map(|(name, _x)| name)
+                        .collect();
+                    labels.sort();
+                    match labels.first() {
+                        Some(label) => format!("@{} ({})", i, label),
+                        None => format!("@{}", i),
                     }
                 }
-                self.execute(memory);
+                Object::Index(i) => match memory.list_to_string(*i) {
+                    Some(x) => x,
+                    None => display_object(value),
+                },
+                x => display_object(x),
+            }
+        }
+
+        fn report_register_changes(&mut self, memory: &Memory) {
+            for change in mem::take(&mut self.register_changes) {
+                let trace = RegisterTrace {
+                    old: self.display_register_value(&change.register, &change.old, memory),
+                    new: self.display_register_value(&change.register, &change.new, memory),
+                    register: change.register,
+                };
+                self.trace_sink.record_register(&trace);
+            }
+        }
+
+        fn registers_except_pc(&self) -> Vec<(String, Object)> {
+            let mut registers: Vec<(String, Object)> = self
+                .registers
+                .iter()
+                .filter(|(name, _r)| *name != "pc")
+                .map(|(name, r)| (name.clone(), r.get().clone()))
+                .collect();
+            registers.sort_by(|x, y| x.0.cmp(&y.0));
+            registers
+        }
+
+        fn record_trace(&mut self, offset: usize, before: Vec<(String, Object)>) {
+            let mut changes = vec![];
+            for (name, old) in before {
+                let new = self.get_register_contents(&name).unwrap();
+                if new != old {
+                    changes.push(RegisterChange {
+                        register: name,
+                        old,
+                        new,
+                    });
+                }
             }
+            let event = TraceEvent {
+                pc: offset,
+                label: self.label_of(offset),
+                line: self.spans.get(offset).map(|x| x.line),
+                instruction: display_exp(&self.raw_instructions[offset]),
+                changes,
+            };
+            self.trace_sink.record(&event);
         }
 
         #[allow(dead_code)]
@@ -278,12 +638,103 @@ pub mod basic_machine {
         }
 
         pub fn call_semantic_op(&mut self, fn_name: String, argv: &Exp) -> Exp {
-            self.semantic_ops[&fn_name](argv)
+            if !self.profiling {
+                return self.apply_semantic_op(&fn_name, argv);
+            }
+            let start = Instant::now();
+            let result = self.apply_semantic_op(&fn_name, argv);
+            self.profile.count_op(&fn_name, start.elapsed());
+            result
+        }
+
+        // the primitives of the host are applied by meta-apply-primitive-procedure
+        // like the built-in ones, argv is (proc argl) then
+        fn apply_semantic_op(&self, fn_name: &str, argv: &Exp) -> Exp {
+            if fn_name == "meta-apply-primitive-procedure" && !self.primitives.is_empty() {
+                if let Ok(Exp::Symbol(name)) = car(argv).and_then(|x| cadr(&x)) {
+                    if let Some(func) = self.primitives.get(&name) {
+                        return func(&cadr(argv).unwrap());
+                    }
+                }
+            }
+            self.semantic_ops[fn_name](argv)
+        }
+
+        // bind name to a primitive procedure of the host in the global environment
+        // held by env, func is applied to the list of the arguments and reports
+        // errors by panics, as the built-in primitives do
+        #[allow(dead_code)]
+        pub fn define_primitive(&mut self, name: &str, func: CallbackExp, memory: &mut Memory) {
+            self.primitives.insert(name.to_string(), func);
+            let primitive = scheme_list!(
+                Exp::Symbol("primitive".to_string()),
+                Exp::Symbol(name.to_string())
+            );
+            self.define_global(name, primitive, memory);
+        }
+
+        // bind name to value in the global environment held by env, as define does
+        #[allow(dead_code)]
+        pub fn define_global(&mut self, name: &str, value: Exp, memory: &mut Memory) {
+            let env = self.get_register_contents(&"env".to_string()).unwrap();
+            let args = scheme_list!(
+                Exp::Symbol(name.to_string()),
+                value,
+                object_to_exp(&env, memory)
+            );
+            let env = define_variable(&args);
+            match exp_to_object(&env, self, memory) {
+                Ok(x) => self.set_register_contents(&"env".to_string(), x),
+                Err(e) => panic!("Error: {}", e),
+            }
         }
 
         #[allow(dead_code)]
         pub fn call_machine_op(&mut self, fn_name: String, memory: &mut Memory) -> Exp {
-            self.machine_ops[&fn_name](self, memory)
+            if !self.profiling {
+                return self.machine_ops[&fn_name](self, memory);
+            }
+            let start = Instant::now();
+            let result = self.machine_ops[&fn_name](self, memory);
+            self.profile.count_op(&fn_name, start.elapsed());
+            result
+        }
+
+        // how often the instructions after each label have been run, the label run
+        // most often first
+        #[allow(dead_code)]
+        pub fn label_counts(&self) -> Vec<(String, u64)> {
+            let mut counts: HashMap<String, u64> = HashMap::new();
+            for (index, count) in self.profile.instruction_counts().iter().enumerate() {
+                if *count == 0 {
+                    continue;
+                }
+                let label = self.label_of(index).unwrap_or_else(|| "-".to_string());
+                *counts.entry(label).or_insert(0) += count;
+            }
+            let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+            counts.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(&y.0)));
+            counts
+        }
+
+        // instructions per label and, when profiling, calls and time per op, both
+        // sorted by cost
+        #[allow(dead_code)]
+        pub fn profile_report(&self) -> String {
+            let mut report = format!("instructions = {}\n", self.profile.instruction_count());
+            report.push_str(&format!("{:<32}{:>12}\n", "label", "instructions"));
+            for (label, count) in self.label_counts() {
+                report.push_str(&format!("{:<32}{:>12}\n", label, count));
+            }
+            let ops = self.profile.ops();
+            if !ops.is_empty() {
+                report.push_str(&format!("{:<32}{:>12}{:>14}\n", "op", "calls", "time (us)"));
+                for (name, op) in ops {
+                    let time = op.time.as_micros();
+                    report.push_str(&format!("{:<32}{:>12}{:>14}\n", name, op.calls, time));
+                }
+            }
+            report
         }
 
         #[allow(dead_code)]
@@ -297,18 +748,48 @@ pub mod basic_machine {
         }
 
         pub fn new() -> Self {
-            let machine = BasicMachine {
+            let mut machine = BasicMachine {
                 registers: HashMap::new(),
                 stack: Stack::new(),
+                stacks: HashMap::new(),
+                label_operands: false,
                 semantic_ops: HashMap::new(),
                 machine_ops: HashMap::new(),
+                primitives: HashMap::new(),
                 instruction_sequence: Vec::new(),
                 raw_instructions: Vec::new(),
+                spans: Vec::new(),
+                source_name: "controller".to_string(),
                 labels: HashMap::new(),
+                info: ControllerInfo::default(),
+                trace: false,
+                trace_sink: Box::new(StdoutSink),
+                register_changes: Vec::new(),
+                watchpoints: Vec::new(),
+                watchpoint_hit: None,
+                breakpoints: HashMap::new(),
+                proceeding: false,
+                profile: Profile::new(),
+                profiling: false,
+                statistics: true,
+                quiet: false,
+                loading: Vec::new(),
+                reader: Reader::plain(),
             };
+            // pc and flag are used by the instructions themselves, any other register
+            // is allocated by the assembler when the controller refers to it
+            machine.allocate_register("pc");
+            machine.allocate_register("flag");
+            machine.set_register_contents(&"pc".to_string(), Object::Index(0));
             machine
         }
 
+        // SICP exercise 5.13
+        pub fn allocate_register(&mut self, name: &str) {
+            let register = Register::new(&name.to_uppercase());
+            self.registers.insert(name.to_string(), register);
+        }
+
         #[allow(dead_code)]
         pub fn print_insts(&mut self) {
             let mut iter = self.raw_instructions.iter().enumerate();
@@ -363,9 +844,26 @@ pub mod basic_machine {
         pub fn set_register_contents(&mut self, name: &String, item: Object) {
             let register = self.registers.get_mut(name);
 
+            let watched = self
+                .watchpoints
+                .iter()
+                .any(|x| x.register == *name && (x.predicate)(&item));
             match register {
                 Some(x) => {
-                    x.set(item);
+                    if watched {
+                        self.watchpoint_hit = Some((name.clone(), item.clone()));
+                    }
+                    if x.traced {
+                        let new = item.clone();
+                        let old = x.set(item);
+                        self.register_changes.push(RegisterChange {
+                            register: name.clone(),
+                            old,
+                            new,
+                        });
+                    } else {
+                        x.set(item);
+                    }
                 }
                 None => {
                     panic!("No such register in this Machine!");
@@ -373,6 +871,39 @@ pub mod basic_machine {
             }
         }
 
+        // SICP exercise 5.18
+        #[allow(dead_code)]
+        pub fn trace_register_on(&mut self, name: &String) {
+            match self.registers.get_mut(name) {
+                Some(x) => x.traced = true,
+                None => panic!("No such register in this Machine!"),
+            }
+        }
+
+        #[allow(dead_code)]
+        pub fn trace_register_off(&mut self, name: &String) {
+            match self.registers.get_mut(name) {
+                Some(x) => x.traced = false,
+                None => panic!("No such register in this Machine!"),
+            }
+        }
+
+        #[allow(dead_code)]
+        pub fn add_watchpoint(&mut self, name: &String, predicate: Box<dyn Fn(&Object) -> bool>) {
+            if !self.registers.contains_key(name) {
+                panic!("No such register in this Machine!");
+            }
+            self.watchpoints.push(Watchpoint {
+                register: name.clone(),
+                predicate,
+            });
+        }
+
+        #[allow(dead_code)]
+        pub fn remove_watchpoints(&mut self, name: &String) {
+            self.watchpoints.retain(|x| x.register != *name);
+        }
+
         // in this case, a list object is wriiten into memory and the beginning address
         // is returned and stored in some register, for specific,
         // set a list in memory from a str and return a index to some register
@@ -389,6 +920,22 @@ pub mod basic_machine {
             self.set_register_contents(name, Object::Index(item));
         }
 
+        // in this case, a Scheme expression is read from a str, a list is written into
+        // memory and its beginning index is stored in the register, while an atom
+        // such as 3 or x is stored in the register directly
+        #[allow(dead_code)]
+        pub fn set_register_contents_as_exp(
+            &mut self,
+            name: &String,
+            object: String,
+            memory: &mut Memory,
+        ) {
+            match exp_to_object(&str_to_exp(object), self, memory) {
+                Ok(x) => self.set_register_contents(name, x),
+                Err(e) => panic!("Error: {}", e),
+            }
+        }
+
         #[allow(dead_code)]
         pub fn assign_from_one_register_to_another(&mut self, to: &String, from: &String) {
             let from = self.get_register_contents(&from);
@@ -491,6 +1038,9 @@ mod test {
     };
 
     use super::basic_machine::BasicMachine;
+    use crate::assembler::assembler::assemble;
+    use crate::machine_cases::machine_case::MachineCase;
+    use crate::representation::type_system::Object;
 
     #[test]
     fn set_register_contents_as_in_memory_works() {
@@ -500,7 +1050,7 @@ mod test {
         let s = "(define x '(+ 1 2))".to_string();
         machine.set_register_contents_as_in_memory(&"root".to_string(), s, &mut memory);
         let ss = machine.get_register_contents_as_in_memory(&"root".to_string(), &memory);
-        assert_eq!(ss, String::from("( define x '( + 1 2))"));
+        assert_eq!(ss, String::from("( define x '(+ 1 2))"));
         let s = "(3 ())".to_string();
         machine.set_register_contents_as_in_memory(&"exp".to_string(), s, &mut memory);
         let ss = machine.get_register_contents_as_in_memory(&"exp".to_string(), &memory);
@@ -619,4 +1169,32 @@ mod test {
             true
         );
     }
+
+    #[test]
+    fn profile_works() {
+        let mut memory = Memory::new(20);
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        machine.initialize_op();
+        let text = MachineCase::test_case().controller_text.to_string();
+        assemble(text, &mut machine, &mut memory).unwrap();
+        machine.set_register_contents(&"exp".to_string(), Object::Integer(2));
+        machine.profiling = true;
+        machine.execute(&mut memory);
+        assert_eq!(machine.profile.instruction_count(), 16);
+        let counts = machine.label_counts();
+        assert_eq!(counts[0], ("fact-loop".to_string(), 9));
+        assert!(counts.contains(&("controller".to_string(), 1)));
+        let ops = machine.profile.ops();
+        let calls = |name: &str| ops.iter().find(|x| x.0 == name).unwrap().1.calls;
+        assert_eq!(calls("="), 2);
+        assert_eq!(calls("-"), 1);
+        assert_eq!(calls("*"), 1);
+        assert!(machine.profile_report().starts_with("instructions = 16\n"));
+        let r = machine.call_machine_op("instruction-count".to_string(), &mut memory);
+        assert_eq!(r, Exp::Integer(16));
+        machine.call_machine_op("reset-instruction-count".to_string(), &mut memory);
+        assert_eq!(machine.profile.instruction_count(), 0);
+        assert!(machine.label_counts().is_empty());
+    }
 }
@@ -0,0 +1,302 @@
+pub mod machine_builder {
+    use crate::controller_file::controller_file::{
+        assemble_controller, read_controller, read_controller_file, ControllerSource,
+    };
+    use crate::infrastructure::stack::{Stack, StackDiscipline};
+    use crate::machine::basic_machine::{BasicMachine, CallbackExp, Callbackmachine};
+    use crate::memory::memory::Memory;
+    use crate::representation::type_system::Object;
+    use crate::runner::runner::panic_message;
+    use std::collections::BTreeSet;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::Path;
+
+    // SICP's (make-machine register-names ops controller), e.g.
+    //   let (mut machine, mut memory) = MachineBuilder::new()
+    //       .registers(&["n", "val", "continue"])
+    //       .standard_ops()
+    //       .controller(text)
+    //       .build()?;
+    // When registers are given the controller may use no others, otherwise they
+    // are allocated as the controller refers to them, see SICP exercise 5.13.
+
+    // the registers lists in memory are built with and the garbage collector
+    // uses, every machine has them
+    const HEAP_REGISTERS: [&str; 7] = [
+        "free",
+        "root",
+        "scan",
+        "old",
+        "oldcr",
+        "new",
+        "relocate_continue",
+    ];
+
+    pub struct MachineBuilder {
+        registers: Vec<String>,
+        standard_ops: bool,
+        semantic_ops: Vec<(String, CallbackExp)>,
+        machine_ops: Vec<(String, Callbackmachine)>,
+        controller: Option<Result<ControllerSource, String>>,
+        heap_size: usize,
+        stack_limit: usize,
+        stack_discipline: StackDiscipline,
+        global_environment: bool,
+        label_operands: bool,
+    }
+
+    impl Default for MachineBuilder {
+        fn default() -> Self {
+            MachineBuilder::new()
+        }
+    }
+
+    impl MachineBuilder {
+        #[allow(dead_code)]
+        pub fn new() -> Self {
+            MachineBuilder {
+                registers: vec![],
+                standard_ops: false,
+                semantic_ops: vec![],
+                machine_ops: vec![],
+                controller: None,
+                heap_size: 1000,
+                stack_limit: 100,
+                stack_discipline: StackDiscipline::Shared,
+                global_environment: false,
+                label_operands: false,
+            }
+        }
+
+        #[allow(dead_code)]
+        pub fn register(mut self, name: &str) -> Self {
+            self.registers.push(name.to_string());
+            self
+        }
+
+        #[allow(dead_code)]
+        pub fn registers(mut self, names: &[&str]) -> Self {
+            self.registers.extend(names.iter().map(|x| x.to_string()));
+            self
+        }
+
+        // the ops of initialize_op, ops given by op and machine_op replace them
+        #[allow(dead_code)]
+        pub fn standard_ops(mut self) -> Self {
+            self.standard_ops = true;
+            self
+        }
+
+        #[allow(dead_code)]
+        pub fn op(mut self, name: &str, op: CallbackExp) -> Self {
+            self.semantic_ops.push((name.to_string(), op));
+            self
+        }
+
+        // an op that works on the machine itself, such as read
+        #[allow(dead_code)]
+        pub fn machine_op(mut self, name: &str, op: Callbackmachine) -> Self {
+            self.machine_ops.push((name.to_string(), op));
+            self
+        }
+
+        // the text of a controller, with the headers and comments of a .rm file
+        #[allow(dead_code)]
+        pub fn controller(mut self, text: &str) -> Self {
+            self.controller = Some(read_controller(text, "controller", None));
+            self
+        }
+
+        #[allow(dead_code)]
+        pub fn controller_file(mut self, path: &Path) -> Self {
+            self.controller = Some(read_controller_file(path));
+            self
+        }
+
+        // the number of pairs in the heap
+        #[allow(dead_code)]
+        pub fn heap(mut self, size: usize) -> Self {
+            self.heap_size = size;
+            self
+        }
+
+        #[allow(dead_code)]
+        pub fn stack(mut self, limit: usize, discipline: StackDiscipline) -> Self {
+            self.stack_limit = limit;
+            self.stack_discipline = discipline;
+            self
+        }
+
+        // env holds the primitives of initialize_env, as in the evaluator
+        #[allow(dead_code)]
+        pub fn global_environment(mut self) -> Self {
+            self.global_environment = true;
+            self
+        }
+
+        #[allow(dead_code)]
+        pub fn label_operands(mut self) -> Self {
+            self.label_operands = true;
+            self
+        }
+
+        // a machine with its controller assembled and pc at the first instruction,
+        // and the memory it works on
+        #[allow(dead_code)]
+        pub fn build(self) -> Result<(BasicMachine, Memory), String> {
+            let mut source = match self.controller {
+                Some(x) => x?,
+                None => return Err("no controller".to_string()),
+            };
+            if self.heap_size == 0 {
+                return Err("the heap needs at least one pair".to_string());
+            }
+            let mut names = BTreeSet::new();
+            for name in self.semantic_ops.iter().map(|x| &x.0) {
+                if !names.insert(name) {
+                    return Err(format!("operation {} is given twice", name));
+                }
+            }
+            for name in self.machine_ops.iter().map(|x| &x.0) {
+                if !names.insert(name) {
+                    return Err(format!("operation {} is given twice", name));
+                }
+            }
+            let mut machine = BasicMachine::new();
+            machine.stack = Stack::with_discipline(self.stack_limit, self.stack_discipline);
+            machine.label_operands = self.label_operands;
+            if self.standard_ops {
+                machine.initialize_op();
+            }
+            for (name, op) in self.semantic_ops {
+                machine.add_semantic_op(name, op);
+            }
+            for (name, op) in self.machine_ops {
+                machine.add_machine_op(name, op);
+            }
+            for name in HEAP_REGISTERS
+                .iter()
+                .copied()
+                .chain(self.registers.iter().map(|x| x.as_str()))
+            {
+                if machine.get_register(&name.to_string()).is_none() {
+                    machine.allocate_register(name);
+                }
+            }
+            machine.set_register_contents(&"free".to_string(), Object::Index(0));
+            if self.global_environment {
+                machine.allocate_register("env");
+            }
+            // the registers given here are declared along with the ones of the
+            // registers header
+            if !self.registers.is_empty() {
+                let mut declared = source.registers.take().unwrap_or_default();
+                declared.extend(self.registers);
+                if self.global_environment {
+                    declared.push("env".to_string());
+                }
+                source.registers = Some(declared);
+            }
+            let mut memory = Memory::new(self.heap_size);
+            if self.global_environment {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    machine.initialize_env(&mut memory);
+                }));
+                if result.is_err() {
+                    return Err(format!(
+                        "the heap of {} pairs is too small for the global environment",
+                        self.heap_size
+                    ));
+                }
+            }
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                assemble_controller(&source, &mut machine, &mut memory)
+            }));
+            match result {
+                Ok(x) => x?,
+                Err(e) => return Err(format!("{}: {}", source.name, panic_message(e))),
+            }
+            machine.set_register_contents(&"pc".to_string(), Object::Index(0));
+            Ok((machine, memory))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::machine_builder::MachineBuilder;
+    use crate::machine::basic_machine::Status;
+    use crate::machine_cases::machine_case::MachineCase;
+    use crate::representation::type_system::Object;
+    use crate::tpfordev::type_system::Exp;
+
+    fn double(args: &Exp) -> Exp {
+        match args {
+            Exp::List(_x) => match crate::tpfordev::type_system::car(args) {
+                Ok(Exp::Integer(x)) => Exp::Integer(2 * x),
+                _ => panic!("Error: double expects an integer"),
+            },
+            _ => panic!("Error: double expects an integer"),
+        }
+    }
+
+    #[test]
+    fn build_works() {
+        let (mut machine, mut memory) = MachineBuilder::new()
+            .registers(&["n", "val", "continue"])
+            .standard_ops()
+            .controller(MachineCase::new().controller_text)
+            .build()
+            .unwrap();
+        machine.set_register_contents(&"n".to_string(), Object::Integer(5));
+        while machine.execute(&mut memory) != Status::Done {}
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Integer(120))
+        );
+        // an op of its own, registers allocated as they are used
+        let (mut machine, mut memory) = MachineBuilder::new()
+            .op("double", double)
+            .controller("(controller (assign x (op double) (const 21)))")
+            .build()
+            .unwrap();
+        while machine.execute(&mut memory) != Status::Done {}
+        assert_eq!(
+            machine.get_register_contents(&"x".to_string()),
+            Some(Object::Integer(42))
+        );
+    }
+
+    #[test]
+    fn build_checks_the_combination() {
+        let controller = MachineCase::new().controller_text;
+        let r = MachineBuilder::new().standard_ops().build();
+        assert_eq!(r.err(), Some("no controller".to_string()));
+        let r = MachineBuilder::new()
+            .registers(&["n", "val"])
+            .standard_ops()
+            .controller(controller)
+            .build();
+        assert_eq!(
+            r.err(),
+            Some("controller: register continue is not declared".to_string())
+        );
+        let r = MachineBuilder::new().controller(controller).build();
+        assert!(r.err().unwrap().contains("unknown operation ="));
+        let r = MachineBuilder::new()
+            .op("double", double)
+            .op("double", double)
+            .controller(controller)
+            .build();
+        assert_eq!(r.err(), Some("operation double is given twice".to_string()));
+        let r = MachineBuilder::new()
+            .heap(10)
+            .global_environment()
+            .controller("(controller (assign val (reg env)))")
+            .build();
+        assert_eq!(
+            r.err(),
+            Some("the heap of 10 pairs is too small for the global environment".to_string())
+        );
+    }
+}
@@ -1,5 +1,6 @@
 pub mod machine_case {
     pub struct MachineCase {
+        #[allow(dead_code)]
         pub name: &'static str,
         pub controller_text: &'static str,
     }
@@ -25,7 +26,7 @@ pub mod machine_case {
                     (assign val (op *) (reg n) (reg val))
                     (goto (reg continue))
                 base-case
-                    (assgin val (const 1))
+                    (assign val (const 1))
                     (goto (reg continue))
                 fact-done)",
             }
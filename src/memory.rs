@@ -3,6 +3,7 @@ pub mod memory {
         machine::basic_machine::BasicMachine,
         parser::parser::{build_syntax_tree_into_memeory, tokenizer},
         representation::type_system::Object,
+        trace::trace::display_object,
     };
     use std::{
         fmt::{self},
@@ -58,8 +59,8 @@ pub mod memory {
         }
 
         pub fn write(&mut self, item: String, machine: &mut BasicMachine) -> usize {
-            let mut tokens = tokenizer(item);
-            let index = build_syntax_tree_into_memeory(&mut tokens, self, machine);
+            let tokens = tokenizer(item);
+            let index = build_syntax_tree_into_memeory(&tokens, self, machine);
             index
         }
 
@@ -92,53 +93,104 @@ pub mod memory {
 
         // fetch a clone of item in ith positon of new_cdrs
         pub fn new_cdr(&self, i: usize) -> Object {
-            let item = &self.new_cars[i];
+            let item = &self.new_cdrs[i];
             (**item).clone()
         }
     }
 
-    impl fmt::Display for Memory {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            let mut car_item = self.the_cars.iter();
-            let mut cdr_item = self.the_cdrs.iter();
-            let mut new_car_item = self.new_cars.iter();
-            let mut new_cdr_item = self.new_cdrs.iter();
-
-            let mut index: usize = 0;
-            println!("Begin to display working memory");
-            let mut car = car_item.next();
-            let mut cdr = cdr_item.next();
-            while let Some(x) = car {
-                match **x {
+    impl Memory {
+        // the list beginning at index i written the usual way, such as (a (1 2) "s"),
+        // or None if the cells there don't hold a proper list, e.g. when i is
+        // not a heap index at all but the offset of an instruction
+        pub fn list_to_string(&self, i: usize) -> Option<String> {
+            let mut items = vec![];
+            let mut index = i;
+            loop {
+                if index >= self.the_cars.len() || items.len() > self.the_cars.len() {
+                    return None;
+                }
+                match self.car(index) {
+                    Object::Nil => {}
+                    Object::Pair(x) => items.push(self.list_to_string(x)?),
+                    Object::Index(_) | Object::Empty => return None,
+                    x => items.push(display_object(&x)),
+                }
+                match self.cdr(index) {
                     Object::Nil => break,
-                    _ => {
-                        println!("Column {}", index);
-                        index += 1;
-                        print!("{:?}\t", *x);
-                        print!("{:?}\n", *(cdr.unwrap()));
-                        car = car_item.next();
-                        cdr = cdr_item.next();
-                    }
+                    Object::Pair(x) => index = x,
+                    _ => return None,
                 }
             }
-            println!("Working Memory Block displayed!");
+            Some(format!("({})", items.join(" ")))
+        }
 
-            let mut new_car = new_car_item.next();
-            let mut new_cdr = new_cdr_item.next();
-            while let Some(x) = new_car {
-                match **x {
-                    Object::Nil => break,
-                    _ => {
-                        println!("Column {}", index);
-                        index += 1;
-                        print!("{:?}\t", *x);
-                        print!("{:?}\n", *(new_cdr.unwrap()));
-                        new_car = new_car_item.next();
-                        new_cdr = new_cdr_item.next();
-                    }
+        // the cells below free, the only ones in use, a line each with the
+        // index, the car and the cdr
+        pub fn live_cells(&self, free: usize) -> String {
+            let mut lines = vec![];
+            for (index, (car, cdr)) in self.the_cars.iter().zip(self.the_cdrs.iter()).enumerate() {
+                if index >= free {
+                    break;
                 }
+                lines.push(format!("{}\t{:?}\t{:?}", index, **car, **cdr));
+            }
+            lines.join("\n")
+        }
+    }
+
+    // cells are displayed up to the first one that has never been written
+    impl fmt::Display for Memory {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(f, "Begin to display working memory")?;
+            for (index, (car, cdr)) in self.the_cars.iter().zip(self.the_cdrs.iter()).enumerate() {
+                if **car == Object::Empty {
+                    break;
+                }
+                writeln!(f, "Column {}", index)?;
+                writeln!(f, "{:?}\t{:?}", **car, **cdr)?;
+            }
+            writeln!(f, "Working Memory Block displayed!")?;
+            for (index, (car, cdr)) in self.new_cars.iter().zip(self.new_cdrs.iter()).enumerate() {
+                if **car == Object::Empty {
+                    break;
+                }
+                writeln!(f, "Column {}", index)?;
+                writeln!(f, "{:?}\t{:?}", **car, **cdr)?;
             }
             write!(f, "New Memory Block displayed!")
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::memory::Memory;
+    use crate::machine::basic_machine::BasicMachine;
+
+    #[test]
+    fn list_to_string_works() {
+        let mut memory = Memory::new(30);
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        let i = memory.write("(a (1 2) \"s\" () 'x)".to_string(), &mut machine);
+        assert_eq!(
+            memory.list_to_string(i),
+            Some("(a (1 2) \"s\" () 'x)".to_string())
+        );
+        assert_eq!(memory.list_to_string(29), None);
+        assert_eq!(memory.list_to_string(100), None);
+    }
+
+    #[test]
+    fn live_cells_works() {
+        let mut memory = Memory::new(30);
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        memory.write("(a b)".to_string(), &mut machine);
+        assert_eq!(
+            memory.live_cells(2),
+            "0\tSymbol(\"a\")\tPair(1)\n1\tSymbol(\"b\")\tNil"
+        );
+        assert_eq!(memory.live_cells(0), "");
+    }
+}
@@ -1,462 +1,175 @@
 pub mod parser {
-    use std::io;
-    use std::io::prelude::*;
-    use std::usize;
+    use std::fmt;
 
     use crate::{
-        machine::basic_machine::BasicMachine, memory::memory::Memory,
+        datum::datum::{lex, materialize, read_tokens, HeapBackend, TokenKind},
+        machine::basic_machine::BasicMachine,
+        memory::memory::Memory,
         representation::type_system::Object,
     };
 
-    pub struct PairStack {
-        capacity: usize,
-        container: Vec<usize>,
+    // a position in a source text, both counted from 1
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Span {
+        pub line: usize,
+        pub column: usize,
     }
 
-    impl PairStack {
+    impl Span {
         pub fn new() -> Self {
-            PairStack {
-                capacity: 100,
-                container: Vec::new(),
-            }
+            Span { line: 1, column: 1 }
         }
 
-        pub fn push(&mut self, index: usize) {
-            if self.container.len() < self.capacity {
-                self.container.push(index);
+        // move past the character
+        pub fn advance(&mut self, c: char) {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
             } else {
-                panic!("Maximum depth violated!");
+                self.column += 1;
             }
         }
+    }
 
-        pub fn pop(&mut self) -> Option<usize> {
-            self.container.pop()
+    impl Default for Span {
+        fn default() -> Self {
+            Span::new()
         }
+    }
 
-        pub fn peek(&self) -> Option<usize> {
-            if self.container.len() > 0 {
-                Some(self.container[self.container.len() - 1])
-            } else {
-                None
-            }
+    impl fmt::Display for Span {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}:{}", self.line, self.column)
         }
     }
 
-    #[allow(dead_code)]
-    pub fn read_scheme_programs_from_stdin(p: &mut String) -> io::Result<()> {
-        let stdin = io::stdin();
+    // split the text of a Scheme source file into its top-level forms, in order.
+    // Each form is its text as it is written, comments in it included, and a
+    // quote prefix stays attached to the datum that follows it, such that
+    // "(define x 1) ; one\n'(a b) x" gives ["(define x 1)", "'(a b)", "x"]
+    pub fn read_scheme_forms(text: &str) -> Vec<String> {
+        read_scheme_forms_with_spans(text)
+            .into_iter()
+            .map(|(form, _span)| form)
+            .collect()
+    }
 
-        for line in stdin.lock().lines() {
-            match line {
-                Ok(line) => {
-                    if !line.trim().is_empty() {
-                        p.push(' ');
-                        p.push_str(&line);
-                        let tokens = tokenizer_alternative(p);
-                        if syntax_checker(&tokens) {
-                            break;
-                        }
-                    } else {
-                        continue;
-                    }
-                }
-                Err(_e) => break,
+    // the same as read_scheme_forms, each form comes with the place it starts at
+    pub fn read_scheme_forms_with_spans(text: &str) -> Vec<(String, Span)> {
+        let tokens = match lex(text) {
+            Ok(x) => x,
+            Err((at, e)) => panic!("Error: unexpected end of input, {} at {}", e, at),
+        };
+        let mut forms = vec![];
+        let mut start = None;
+        let mut depth = 0;
+        for token in tokens {
+            let (offset, span) = *start.get_or_insert((token.range.start, token.span));
+            match token.kind {
+                TokenKind::Open => depth += 1,
+                TokenKind::Close if depth == 0 => panic!(
+                    "Error: unbalanced parentheses, unexpected ')' at {}",
+                    token.span
+                ),
+                TokenKind::Close => depth -= 1,
+                TokenKind::Quote => continue,
+                _ => {}
+            }
+            if depth == 0 {
+                forms.push((text[offset..token.range.end].to_string(), span));
+                start = None;
             }
         }
-        Ok(())
-    }
-
-    pub fn tokenizer(s: String) -> Vec<String> {
-        let mut tokens: Vec<String> = vec![];
-        let ss = s.replace("(", " ( ");
-        let ss = ss.replace(")", " ) ");
-        let v: Vec<&str> = ss.trim().split_whitespace().collect();
-        for item in v {
-            tokens.push(item.to_string());
+        if let Some((_offset, span)) = start {
+            panic!(
+                "Error: unexpected end of input, missing ')' for the form at {}",
+                span
+            );
         }
-        tokens
+        forms
     }
 
-    pub fn tokenizer_alternative(s: &mut String) -> Vec<String> {
-        let mut tokens: Vec<String> = vec![];
-        let ss = s.replace("(", " ( ");
-        let ss = ss.replace(")", " ) ");
-        let v: Vec<&str> = ss.trim().split_whitespace().collect();
-        for item in v {
-            tokens.push(item.to_string());
+    // where the elements directly inside the outermost list of the text start,
+    // for a controller these are its labels and instructions in order
+    pub fn inner_element_spans(text: &str) -> Vec<Span> {
+        let tokens = lex(text).unwrap_or_default();
+        let mut spans = vec![];
+        let mut depth = 0;
+        let mut quoted = false;
+        for token in tokens {
+            if depth == 1 && !quoted && token.kind != TokenKind::Close {
+                spans.push(token.span);
+            }
+            quoted = token.kind == TokenKind::Quote;
+            match token.kind {
+                TokenKind::Open => depth += 1,
+                TokenKind::Close => depth -= 1,
+                _ => {}
+            }
         }
-        tokens
+        spans
     }
 
-    #[allow(dead_code)]
-    pub fn reverse(s: &mut Vec<String>) -> Vec<String> {
-        let mut x = vec![];
-        while let Some(token) = s.pop() {
-            x.push(token);
-        }
-        x
+    // true if the text ends inside a list, a string, a #| comment or right after
+    // a quote, that is, more lines are needed to complete the last form. An
+    // unexpected ')' does not ask for more lines, read_scheme_forms reports it
+    // instead.
+    pub fn is_incomplete_input(text: &str) -> bool {
+        let tokens = match lex(text) {
+            Ok(x) => x,
+            Err(_e) => return true,
+        };
+        let depth: i32 = tokens
+            .iter()
+            .map(|x| match x.kind {
+                TokenKind::Open => 1,
+                TokenKind::Close => -1,
+                _ => 0,
+            })
+            .sum();
+        depth > 0 || tokens.last().map(|x| x.kind) == Some(TokenKind::Quote)
     }
 
-    #[allow(dead_code)]
-    pub fn syntax_checker(t: &Vec<String>) -> bool {
-        let mut iterator = t.iter();
-        let mut left_parenthesis = 0;
-        let mut right_parenthesis = 0;
-        let mut token = iterator.next();
-        loop {
-            match token {
-                x if x == Some(&("(".to_string())) => {
-                    left_parenthesis = left_parenthesis + 1;
-                }
-                x if x == Some(&(")".to_string())) => {
-                    right_parenthesis = right_parenthesis + 1;
-                }
-                Some(_x) => {}
-                None => {
-                    break;
-                }
-            }
-            token = iterator.next();
-        }
-        if left_parenthesis == right_parenthesis {
-            true
-        } else {
-            false
+    // the tokens of text as they are written, see datum::lex
+    pub fn tokenizer(s: String) -> Vec<String> {
+        match lex(&s) {
+            Ok(tokens) => tokens
+                .iter()
+                .map(|x| s[x.range.clone()].to_string())
+                .collect(),
+            Err((at, e)) => panic!("Error: {} at {}", e, at),
         }
     }
 
     // writing a list into memory and return a index to the root of this list object
     #[allow(dead_code)]
     pub fn build_syntax_tree_into_memeory(
-        tokens: &mut Vec<String>,
+        tokens: &[String],
         memory: &mut Memory,
         machine: &mut BasicMachine,
     ) -> usize {
-        if tokens.len() == 0 {
-            panic!("Error: empty tokens in BUILD_SYNTAX_TREE_INTO_MEMORY");
+        let datum = match read_tokens(tokens) {
+            Ok(x) => x,
+            Err(e) => panic!("Error: {}", e),
+        };
+        match materialize(&datum, &mut HeapBackend { machine, memory }) {
+            Ok(Object::Index(root)) => root,
+            Ok(_) => panic!("Error: only a list can be written into memory!"),
+            Err(e) => panic!("Error: {}", e),
         }
-        if !syntax_checker(&tokens) {
-            panic!("Error: syntax wrong in building syntax tree!");
-        }
-        let mut tokens = reverse(tokens);
-        let free = machine.get_register(&"free".to_string()).unwrap();
-        let mut stack = PairStack::new();
-        let root = free.get_memory_index();
-
-        build_syntax_tree_into_memory_helper(&mut tokens, &mut stack, memory, machine);
-        // root is the beginining index of the list written into memory
-        root
     }
 
     #[allow(dead_code)]
-    fn build_syntax_tree_into_memory_helper(
-        tokens: &mut Vec<String>,
-        stack: &mut PairStack,
-        memory: &mut Memory,
-        machine: &mut BasicMachine,
-    ) {
-        // if flag is set to true, the item should be written into car, otherwise written to cdr
-        let mut flag = true;
-
-        while let Some(t) = tokens.pop() {
-            let token = t;
-            let free = machine.get_register(&"free".to_string()).unwrap();
-            // free_index indicates the first index of memory space that is not used
-            let free_index = free.get_memory_index();
-            // pair_index indicates the current memory index that is being written
-            let pair_index = stack.peek();
-
-            match token {
-                // begin of a list
-                x if x == "(".to_string() => {
-                    match pair_index {
-                        Some(i) => {
-                            let item = Object::Pair(free_index);
-                            if flag {
-                                memory.update("car", item, i);
-                                stack.push(free_index);
-                                machine.register_increment_by_one(&"free".to_string());
-                                let next_token = tokens.last();
-                                let null = ")".to_string();
-                                match next_token {
-                                    t if t == Some(&null) => {
-                                        let index = stack.peek().unwrap();
-                                        memory.update("car", Object::Nil, index);
-                                        flag = false;
-                                    }
-                                    _ => flag = true,
-                                }
-                            } else {
-                                memory.update("cdr", item, i);
-                                stack.pop();
-                                // push the new pair index into stack
-                                stack.push(free_index);
-                                let pair_index = free_index;
-                                machine.register_increment_by_one(&"free".to_string());
-                                let free_index = machine
-                                    .get_register(&"free".to_string())
-                                    .unwrap()
-                                    .get_memory_index();
-                                let item = Object::Pair(free_index);
-
-                                memory.update("car", item, pair_index);
-                                let next_token = tokens.last();
-                                let null = ")".to_string();
-                                match next_token {
-                                    t if t == Some(&null) => {
-                                        // in this case, this ")" must match to the preceding "("
-                                        // since nothing else exists between this pair of "()"
-                                        stack.push(free_index);
-                                        memory.update("car", Object::Nil, free_index);
-                                        machine.advance_free();
-                                        flag = false;
-                                    }
-                                    _ => {
-                                        flag = true;
-                                        stack.push(
-                                            machine
-                                                .get_register(&"free".to_string())
-                                                .unwrap()
-                                                .get_memory_index(),
-                                        );
-                                        machine.register_increment_by_one(&"free".to_string());
-                                    }
-                                }
-                            }
-                        }
-                        None => {
-                            stack.push(free_index);
-                            let next_token = tokens.last();
-                            let null = ")".to_string();
-                            match next_token {
-                                t if t == Some(&null) => {
-                                    let index = stack.peek().unwrap();
-                                    memory.update("car", Object::Nil, index);
-                                    flag = false;
-                                }
-                                _ => flag = true,
-                            }
-                            // note that the free indicator is always ahead of pair_index
-                            machine.register_increment_by_one(&"free".to_string());
-                        }
-                    }
-                }
-                // tail of a List
-                x if x == ")".to_string() => {
-                    // find the right index for the Nil by pop PairStack, which is the
-                    // current pair being written into memory
-                    let null = Object::Nil;
-                    match pair_index {
-                        Some(i) => {
-                            if !flag {
-                                memory.update("cdr", null, i);
-                                // pop current pair_index since both car and cdr part
-                                // has been filled
-                                stack.pop();
-                                let next_token = tokens.last();
-                                match next_token {
-                                    Some(t) => {
-                                        if t == ")" {
-                                            continue;
-                                        } else {
-                                            // after the current pair_index has been poped
-                                            // another issue arise, which is,
-                                            // since current list has a cdr part(next_token exists!)
-                                            //,there's a blank
-                                            // position in the front of memory that need to be filled
-                                            // and this position can be obtained by peek stack
-                                            // (since position yet to be filled must be in
-                                            //  stack at moment)
-                                            let pair_index = stack.peek();
-                                            match pair_index {
-                                                Some(i) => {
-                                                    let pair_item = Object::Pair(free_index);
-                                                    memory.update("cdr", pair_item, i);
-                                                    stack.pop();
-                                                }
-                                                None => {}
-                                            }
-                                            stack.push(free_index);
-                                            machine.register_increment_by_one(&"free".to_string());
-                                            flag = true;
-                                        }
-                                    }
-                                    None => {
-                                        break;
-                                    }
-                                }
-                            } else {
-                                panic!("Something wrong happened in writing into memory!");
-                            }
-                        }
-                        None => {
-                            panic!("Error in PairStack: Not Match!");
-                        }
-                    }
-                }
-
-                x if (x == "true"
-                    || x == "false"
-                    || is_i32(x.clone())
-                    || x.chars().nth(0) == Some('"')
-                    || x.chars().nth(0) == Some('\'')
-                    || is_f32(x.clone())
-                    || is_symbol(&x)) =>
-                {
-                    let mut item = Object::Nil;
-                    match x {
-                        x if x == "true" => item = Object::Bool(true),
-                        x if x == "false" => item = Object::Bool(false),
-                        x if is_i32(x.clone()) => {
-                            item = Object::Integer(x.parse::<i32>().unwrap());
-                        }
-                        x if is_f32(x.clone()) => {
-                            item = Object::Number(x.parse::<f32>().unwrap());
-                        }
-                        x if is_symbol(&x) => item = Object::Symbol(x),
-                        x if x.chars().nth(0) == Some('"') => {
-                            let s = read_scheme_string(x, tokens);
-                            item = Object::LispString(s);
-                        }
-                        x if x.chars().nth(0) == Some('\'') => {
-                            let s = read_scheme_quote(x, tokens);
-                            item = Object::Quote(s);
-                        }
-                        _ => {}
-                    }
-                    match pair_index {
-                        Some(i) => {
-                            if flag {
-                                memory.update("car", item, i);
-                                flag = false;
-                            } else {
-                                let pair_item = Object::Pair(free_index);
-                                memory.update("cdr", pair_item, i);
-                                stack.pop();
-                                stack.push(free_index);
-                                let pair_index = free_index;
-                                machine.register_increment_by_one(&"free".to_string());
-                                memory.update("car", item, pair_index);
-                                flag = false;
-                            }
-                        }
-                        None => {
-                            panic!("Something wrong happened in writing into memory!");
-                        }
-                    }
-                }
-                _ => {
-                    panic!("unknow token!");
-                }
-            }
-        }
-    }
-
-    fn is_symbol(x: &String) -> bool {
-        x.chars().nth(0).unwrap().is_alphabetic()
-            || x == "="
-            || x == "+"
-            || x == "-"
-            || x == "*"
-            || x == "/"
-            || x == ">"
-            || x == "<"
-    }
-
-    fn is_f32(x: String) -> bool {
-        let s = x.parse::<f32>();
-        match s {
-            Ok(_x) => true,
-            _ => false,
-        }
-    }
-
-    fn is_i32(x: String) -> bool {
-        let s = x.parse::<i32>();
-        match s {
-            Ok(_x) => true,
-            _ => false,
-        }
-    }
-
-    pub fn read_scheme_string(t: String, tokens: &mut Vec<String>) -> String {
-        let mut tt = (&t[1..]).to_string();
-        loop {
-            let s = tokens.pop();
-            match s {
-                Some(x) => {
-                    if is_end_with_double_quote(&x) {
-                        tt.push(' ');
-                        let tx = &x[..(x.len() - 1)];
-                        tt.push_str(tx);
-                        return tt;
-                    } else {
-                        tt.push(' ');
-                        tt.push_str(&x);
-                    }
-                }
-                None => {
-                    panic!("missing part for a Scheme String!");
-                }
-            }
-        }
-    }
-
     pub fn is_end_with_double_quote(s: &str) -> bool {
         s.chars().last().unwrap() == '\"'
     }
-
-    pub fn read_scheme_quote(t: String, tokens: &mut Vec<String>) -> String {
-        if t.len() == 1 {
-            let first_token = tokens.pop().unwrap();
-            let mut left = 0;
-            let mut right = 0;
-            if first_token != "(" {
-                panic!("syntax wrong!");
-            } else {
-                let mut s = "".to_string();
-                s.push('(');
-                left = left + 1;
-                loop {
-                    let token = tokens.pop();
-                    match token {
-                        Some(x) => {
-                            if x == "(" {
-                                left = left + 1;
-                                s.push(' ');
-                                s.push('(');
-                                if left == right {
-                                    return s;
-                                }
-                            } else if x == ")" {
-                                right = right + 1;
-                                s.push(')');
-                                if left == right {
-                                    return s;
-                                }
-                            } else {
-                                s.push(' ');
-                                s.push_str(&x);
-                            }
-                        }
-                        None => {
-                            panic!("syntax wrong!");
-                        }
-                    }
-                }
-            }
-        } else {
-            let s = (&t[1..]).to_string();
-            s
-        }
-    }
 }
 
 #[cfg(test)]
 mod test {
     use super::parser::{
-        build_syntax_tree_into_memeory, is_end_with_double_quote, read_scheme_quote,
-        read_scheme_string, reverse, tokenizer,
+        build_syntax_tree_into_memeory, inner_element_spans, is_end_with_double_quote,
+        is_incomplete_input, read_scheme_forms, read_scheme_forms_with_spans, tokenizer,
     };
 
     use crate::representation::type_system::Object;
@@ -492,8 +205,8 @@ mod test {
                                3  
                                   ))"
         .to_string();
-        let mut tokens = tokenizer(s);
-        build_syntax_tree_into_memeory(&mut tokens, &mut memory, &mut machine);
+        let tokens = tokenizer(s);
+        build_syntax_tree_into_memeory(&tokens, &mut memory, &mut machine);
         let car_0 = memory.car(0);
         let cdr_0 = memory.cdr(0);
 
@@ -540,32 +253,54 @@ mod test {
     }
 
     #[test]
-    fn read_scheme_string_works() {
-        let t = vec!["\"winter", "is", "coming\""];
-        let mut tokens: Vec<String> = t.into_iter().map(|x| x.to_string()).collect();
-        tokens = reverse(&mut tokens);
-        let x = tokens.pop().unwrap();
-        let s = read_scheme_string(x, &mut tokens);
-        assert_eq!(s, "winter is coming".to_string());
+    fn is_end_with_double_quote_works() {
+        let s = "coming\"";
+        assert_eq!(is_end_with_double_quote(s), true);
+    }
+
+    #[test]
+    fn read_scheme_forms_works() {
+        let text = "; square numbers
+(define (square x) ; x is a number
+   (* x x)) ; end of square
+'(1 2) x \"a ; b\"
+(square 3)";
+        let forms = read_scheme_forms(text);
+        let checkout = vec![
+            "(define (square x) ; x is a number\n   (* x x))",
+            "'(1 2)",
+            "x",
+            "\"a ; b\"",
+            "(square 3)",
+        ];
+        assert_eq!(forms, checkout);
+        assert_eq!(read_scheme_forms(" ; nothing here\n"), Vec::<String>::new());
+        let spans: Vec<String> = read_scheme_forms_with_spans(text)
+            .into_iter()
+            .map(|(_form, span)| span.to_string())
+            .collect();
+        assert_eq!(spans, vec!["2:1", "4:1", "4:8", "4:10", "5:1"]);
     }
 
     #[test]
-    fn read_scheme_quote_works() {
-        let quote = "'(1 ( 2 3 ))".to_string();
-        let mut tokens = reverse(&mut tokenizer(quote));
-        let x = tokens.pop().unwrap();
-        let s = read_scheme_quote(x, &mut tokens);
-        assert_eq!(s, "( 1 ( 2 3))".to_string());
-        let another_quote = "'symbol ( 1 2)".to_string();
-        let mut ttokens = reverse(&mut tokenizer(another_quote));
-        let xx = ttokens.pop().unwrap();
-        let ss = read_scheme_quote(xx, &mut ttokens);
-        assert_eq!(ss, "symbol".to_string());
+    fn inner_element_spans_works() {
+        let text = "(controller\n  (assign n (const \")\"))\n  done (goto (reg x)))";
+        let spans: Vec<String> = inner_element_spans(text)
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert_eq!(spans, vec!["1:2", "2:3", "3:3", "3:8"]);
     }
 
     #[test]
-    fn is_end_with_double_quote_works() {
-        let s = "coming\"";
-        assert_eq!(is_end_with_double_quote(s), true);
+    fn is_incomplete_input_works() {
+        assert_eq!(is_incomplete_input("(define (square x)"), true);
+        assert_eq!(is_incomplete_input("(define (square x) (* x x))"), false);
+        assert_eq!(is_incomplete_input("(display \"a (b"), true);
+        assert_eq!(is_incomplete_input("(display \"a (b\")"), false);
+        assert_eq!(is_incomplete_input("(car ; (cdr"), true);
+        assert_eq!(is_incomplete_input("'"), true);
+        assert_eq!(is_incomplete_input("x)"), false);
+        assert_eq!(is_incomplete_input(""), false);
     }
 }
@@ -1,108 +1,6 @@
 pub mod parser {
-    use crate::parser::parser::{
-        read_scheme_quote, read_scheme_string, reverse, syntax_checker, tokenizer,
-    };
-    use crate::scheme_list;
-    use crate::tpfordev::type_system::{append, car, scheme_cons, scheme_for_each, Exp, Pair};
-
-    #[allow(dead_code)]
-    pub fn build_syntax_tree(tokens: &mut Vec<String>) -> Exp {
-        let mut tokens = reverse(tokens);
-        let tree = build_syntax_tree_helper(&mut tokens);
-        if tree != Exp::List(Pair::Nil) {
-            car(&tree).unwrap()
-        } else {
-            Exp::List(Pair::Nil)
-        }
-    }
-
-    #[allow(dead_code)]
-    fn build_syntax_tree_helper(tokens: &mut Vec<String>) -> Exp {
-        let mut tree_buffer = Exp::List(Pair::Nil);
-        while let Some(t) = tokens.pop() {
-            let token = t;
-            match token {
-                // head of a Exp::List
-                x if x == "(".to_string() => {
-                    let subtree = build_syntax_tree_helper(tokens);
-                    tree_buffer = append(tree_buffer, scheme_list!(subtree));
-                }
-                // tail of a Exp::List
-                x if x == ")".to_string() => {
-                    break;
-                }
-                x if x == "Nil".to_string() => {
-                    tree_buffer = append(tree_buffer, Exp::List(Pair::Nil));
-                }
-                // bool value
-                x if x == "true".to_string() => {
-                    tree_buffer = append(tree_buffer, scheme_list!(Exp::Bool(true)));
-                }
-                x if x == "false".to_string() => {
-                    tree_buffer = append(tree_buffer, scheme_list!(Exp::Bool(false)));
-                }
-                // symbol value
-                x if is_symbol(&x) => {
-                    tree_buffer = append(tree_buffer, scheme_list!(Exp::Symbol(x)));
-                }
-                // scheme string, for example, "winter is coming!"
-                x if x.chars().nth(0) == Some('"') => {
-                    let s = read_scheme_string(x, tokens);
-                    tree_buffer = append(tree_buffer, scheme_list!(Exp::SchemeString(s)));
-                }
-                // scheme quote, for example, 'winter
-                x if x.chars().nth(0) == Some('\'') => {
-                    let s = read_scheme_quote(x, tokens);
-                    tree_buffer = append(tree_buffer, scheme_list!(Exp::Quote(s)));
-                }
-                // i32
-                x if is_i32(x.clone()) => {
-                    tree_buffer = append(
-                        tree_buffer,
-                        scheme_list!(Exp::Integer(x.parse::<i32>().unwrap())),
-                    );
-                }
-                // f32
-                x if is_f32(x.clone()) => {
-                    tree_buffer = append(
-                        tree_buffer,
-                        scheme_list!(Exp::FloatNumber(x.parse::<f32>().unwrap())),
-                    );
-                }
-                _ => {
-                    panic!("unknow token!");
-                }
-            }
-        }
-        tree_buffer
-    }
-
-    fn is_symbol(x: &String) -> bool {
-        x.chars().nth(0).unwrap().is_alphabetic()
-            || x == "="
-            || x == "+"
-            || x == "-"
-            || x == "*"
-            || x == "/"
-            || x == ">"
-            || x == "<"
-    }
-
-    fn is_f32(x: String) -> bool {
-        let s = x.parse::<f32>();
-        match s {
-            Ok(_x) => true,
-            _ => false,
-        }
-    }
-
-    fn is_i32(x: String) -> bool {
-        let s = x.parse::<i32>();
-        match s {
-            Ok(_x) => true,
-            _ => false,
-        }
-    }
+    use crate::datum::datum::{materialize, read_datum, ExpBackend};
+    use crate::tpfordev::type_system::{scheme_for_each, Exp, Pair};
 
     #[allow(dead_code)]
     pub fn print(exp: Exp) {
@@ -110,11 +8,10 @@ pub mod parser {
             Exp::FloatNumber(x) => print!("{}", x),
             Exp::Integer(x) => print!("{}", x),
             Exp::Symbol(x) => print!("{}", x),
-            Exp::Quote(x) => {
-                print!("{}", &x[1..x.len()]);
-            }
+            Exp::Quote(x) => print!("{}", x),
             Exp::SchemeString(x) => print!("{}", x),
             Exp::Index(x) => print!("{}", x),
+            Exp::Eof => print!("#<eof>"),
             Exp::Bool(x) => print!("{}", x),
             Exp::List(Pair::Nil) => {
                 print!("()");
@@ -167,6 +64,7 @@ pub mod parser {
                 s.push(' ');
                 s.push_str(&x.to_string());
             }
+            Exp::Eof => s.push_str(" #<eof>"),
             Exp::Quote(x) => {
                 s.push(' ');
                 s.push('\'');
@@ -207,15 +105,43 @@ pub mod parser {
         }
     }
 
-    // map string to exp
+    // the usual written form of an exp with single spaces between the items of a
+    // list, such as (assign n (op -) (reg n) (const 1)), for messages and traces.
+    // exp_to_str is kept as it is since its output is parsed again.
+    #[allow(dead_code)]
+    pub fn display_exp(exp: &Exp) -> String {
+        match exp {
+            Exp::FloatNumber(x) => x.to_string(),
+            Exp::Integer(x) => x.to_string(),
+            Exp::Symbol(x) => x.to_string(),
+            Exp::Index(x) => x.to_string(),
+            Exp::Eof => "#<eof>".to_string(),
+            Exp::Quote(x) => format!("'{}", x.trim()),
+            Exp::SchemeString(x) => format!("\"{}\"", x),
+            Exp::Bool(x) => x.to_string(),
+            Exp::List(x) => {
+                let mut items = vec![];
+                let mut temp = x;
+                while let Pair::Cons(lhs, rhs) = temp {
+                    items.push(display_exp(lhs));
+                    temp = rhs;
+                }
+                format!("({})", items.join(" "))
+            }
+        }
+    }
+
+    // map string to exp, see datum::read_datum
     #[allow(dead_code)]
     pub fn str_to_exp(s: String) -> Exp {
-        let mut tokens = tokenizer(s);
-        if !syntax_checker(&tokens) {
-            panic!("syntax wrong!");
+        // text with nothing in it stands for the empty list
+        if s.trim().is_empty() {
+            return Exp::List(Pair::Nil);
+        }
+        match read_datum(&s).and_then(|x| materialize(&x, &mut ExpBackend)) {
+            Ok(x) => x,
+            Err(e) => panic!("syntax wrong! {}", e),
         }
-        let exp = build_syntax_tree(&mut tokens);
-        exp
     }
 
     #[allow(dead_code)]
@@ -233,7 +159,7 @@ pub mod parser {
 
 #[cfg(test)]
 mod test {
-    use super::parser::{exp_to_str, str_to_exp};
+    use super::parser::{display_exp, exp_to_str, str_to_exp};
     use crate::{
         scheme_list,
         tpfordev::type_system::{append, scheme_cons, Exp, Pair},
@@ -279,7 +205,7 @@ mod test {
                 Exp::SchemeString("winter is coming".to_string())
             )
         );
-        assert_eq!(exp5, Exp::Quote("( 1 ( 2 3))".to_string()));
+        assert_eq!(exp5, Exp::Quote("(1 (2 3))".to_string()));
         assert_eq!(exp6, Exp::List(Pair::Nil));
         assert_eq!(
             exp7,
@@ -324,8 +250,18 @@ mod test {
         assert_eq!(ss2, "3.14".to_string());
         assert_eq!(ss3, "(( 1 2)( 3( 4 5)))".to_string());
         assert_eq!(ss4, "( define x  \"winter is coming\")".to_string());
-        assert_eq!(ss5, "'( 1 ( 2 3))".to_string());
+        assert_eq!(ss5, "'(1 (2 3))".to_string());
         assert_eq!(ss6, "()".to_string());
         assert_eq!(s7.to_string(), exp_to_str(exp7));
     }
+
+    #[test]
+    fn display_exp_works() {
+        let s = "(( 1  2 )
+        (assign n (op -)   (reg n) (const 1)) () \"a b\" 'x 3.5 false)";
+        assert_eq!(
+            display_exp(&str_to_exp(s.to_string())),
+            "((1 2) (assign n (op -) (reg n) (const 1)) () \"a b\" 'x 3.5 false)"
+        );
+    }
 }
@@ -1,16 +1,20 @@
 pub mod primitives {
+    #[cfg(feature = "serde")]
+    use crate::json::json::{json_to_scheme, scheme_to_json};
     use crate::{
-        infrastructure::stack::Stack,
+        commands::commands::{is_command, run_command},
         machine::basic_machine::BasicMachine,
         memory::memory::Memory,
-        parser::parser::read_scheme_programs_from_stdin,
-        parserfordev::parser::{exp_to_str, str_to_exp},
+        parser::parser::read_scheme_forms,
+        parserfordev::parser::{display_exp, exp_to_str, str_to_exp},
         representation::type_system::Object,
         scheme_list,
         tpfordev::type_system::{
             append, car, cdr, list_length, scheme_cons, set_car, set_cdr, Exp, Pair,
         },
     };
+    use std::fs;
+    use std::path::Path;
 
     /* primitives that are used as basic Scheme list operations
     note that these procedurs are not used as machine and semantic primitives
@@ -75,9 +79,42 @@ pub mod primitives {
 
     /* The procedurs below are primitives of machine ops
     which is, has machine and memory as args */
+    // statistics are printed only when they are asked for, see BasicMachine::statistics
     #[allow(dead_code)]
     pub fn machine_statistics(machine: &mut BasicMachine, _memory: &mut Memory) -> Exp {
-        machine.stack.statistics();
+        if machine.statistics {
+            machine.stack.statistics();
+            println!(
+                "total-instructions = {}",
+                machine.profile.instruction_count()
+            );
+        }
+        Exp::Quote("ok".to_string())
+    }
+
+    // the statistics at the end of the input of the read-eval-print-loop, which
+    // are printed unless the machine is quiet. With statistics they have already
+    // been printed after the last form.
+    #[allow(dead_code)]
+    pub fn final_statistics(machine: &mut BasicMachine, memory: &mut Memory) -> Exp {
+        if !machine.quiet && !machine.statistics {
+            machine.statistics = true;
+            machine_statistics(machine, memory);
+            machine.statistics = false;
+        }
+        Exp::Quote("ok".to_string())
+    }
+
+    // SICP exercise 5.15
+    #[allow(dead_code)]
+    pub fn instruction_count(machine: &mut BasicMachine, _memory: &mut Memory) -> Exp {
+        Exp::Integer(machine.profile.instruction_count() as i32)
+    }
+
+    // the counts of instructions and the times of ops start over
+    #[allow(dead_code)]
+    pub fn reset_instruction_count(machine: &mut BasicMachine, _memory: &mut Memory) -> Exp {
+        machine.profile.reset();
         Exp::Quote("ok".to_string())
     }
 
@@ -85,51 +122,99 @@ pub mod primitives {
     // error may happen in last round.
     #[allow(dead_code)]
     pub fn initialize_stack(machine: &mut BasicMachine, _memory: &mut Memory) -> Exp {
-        machine.stack = Stack::new();
+        machine.stack.initialize();
+        for stack in machine.stacks.values_mut() {
+            stack.initialize();
+        }
         Exp::Quote("ok".to_string())
     }
 
     #[allow(dead_code)]
-    pub fn prompt_for_input(_machine: &mut BasicMachine, _memory: &mut Memory) -> Exp {
-        println!("=> ");
+    pub fn prompt_for_input(machine: &mut BasicMachine, _memory: &mut Memory) -> Exp {
+        // a line editor shows the prompt itself
+        if !machine.quiet && !machine.reader.is_interactive() {
+            println!("=> ");
+        }
+        Exp::Quote("ok".to_string())
+    }
+
+    #[allow(dead_code)]
+    pub fn announce_output(machine: &mut BasicMachine, _memory: &mut Memory) -> Exp {
+        if !machine.quiet {
+            println!("=> ");
+        }
         Exp::Quote("ok".to_string())
     }
 
     #[allow(dead_code)]
     pub fn read(machine: &mut BasicMachine, memory: &mut Memory) -> Exp {
-        let mut s = "".to_string();
-        let r = read_scheme_programs_from_stdin(&mut s);
-        match r {
-            Ok(()) => {
-                // println!("{}", s.clone());
-                let exp = str_to_exp(s.clone());
-                // println!("{:?}", exp.clone());
-                match exp {
-                    Exp::List(_x) => {
-                        let index = memory.write(s, machine);
-                        machine.set_register_contents(&"exp".to_string(), Object::Index(index));
-                    }
-                    _ => {
-                        machine.set_register_contents(&"exp".to_string(), exp.exp_to_object());
-                    }
+        loop {
+            match machine.reader.read_form() {
+                Some(s) if is_command(&s) => {
+                    run_command(&s, machine, memory);
+                    prompt_for_input(machine, memory);
+                }
+                Some(s) => {
+                    machine.set_register_contents_as_exp(&"exp".to_string(), s, memory);
+                    break;
+                }
+                None => {
+                    // end of input, the controller checks for it with eof-object?
+                    machine.set_register_contents(&"exp".to_string(), eof_object());
+                    break;
                 }
-            }
-            Err(x) => {
-                panic!("Error when reading input {}", x);
             }
         }
         Exp::Quote("ok".to_string())
     }
 
-    /* The procedures below are semantic ops for machine
-    such as self_evaluating in eval dispatch */
-    // semantic primitives for IO
+    // the object that read leaves in register exp at the end of input, a value
+    // of its own such that no input can be mistaken for it
+    pub fn eof_object() -> Object {
+        Object::Eof
+    }
+
+    // (load "lib.scm") reads all top-level forms of a file and hands them to the
+    // controller as a sequence to be evaluated. A relative path is resolved against
+    // the directory of the file that is being loaded right now, if any.
+    // note that the file name is taken from register argl directly
     #[allow(dead_code)]
-    pub fn announce_output(_exp: &Exp) -> Exp {
-        println!("=> ");
+    pub fn load_file(machine: &mut BasicMachine, memory: &mut Memory) -> Exp {
+        let argl = machine.get_register_contents_as_in_memory(&"argl".to_string(), memory);
+        let name = match car(&str_to_exp(argl)) {
+            Ok(Exp::SchemeString(x)) => x,
+            _ => panic!("Error: LOAD expects a file name as a string"),
+        };
+        let path = match machine.loading.last() {
+            Some(current) if Path::new(&name).is_relative() => match current.parent() {
+                Some(dir) => dir.join(&name),
+                None => Path::new(&name).to_path_buf(),
+            },
+            _ => Path::new(&name).to_path_buf(),
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(e) => panic!("Error: can't load {}: {}", path.display(), e),
+        };
+        let mut forms = Exp::List(Pair::Nil);
+        for form in read_scheme_forms(&text) {
+            forms = append(forms, scheme_list!(str_to_exp(form)));
+        }
+        machine.loading.push(path);
+        // load returns 'ok when all forms are evaluated, which also makes an empty
+        // file a legal sequence
+        append(forms, scheme_list!(Exp::Quote("ok".to_string())))
+    }
+
+    #[allow(dead_code)]
+    pub fn finish_load(machine: &mut BasicMachine, _memory: &mut Memory) -> Exp {
+        machine.loading.pop();
         Exp::Quote("ok".to_string())
     }
 
+    /* The procedures below are semantic ops for machine
+    such as self_evaluating in eval dispatch */
+    // semantic primitives for IO
     #[allow(dead_code)]
     pub fn user_print(exp: &Exp) -> Exp {
         let arg = car(exp).unwrap();
@@ -364,6 +449,15 @@ pub mod primitives {
             x if x == Exp::Symbol("<".to_string()) => is_smaller_than(&argl),
             x if x == Exp::Symbol(">".to_string()) => is_larger_than(&argl),
             x if x == Exp::Symbol("=".to_string()) => is_eq(&argl),
+            x if x == Exp::Symbol("display".to_string()) => display(&argl),
+            x if x == Exp::Symbol("newline".to_string()) => {
+                println!();
+                Exp::Quote("ok".to_string())
+            }
+            #[cfg(feature = "serde")]
+            x if x == Exp::Symbol("json->scheme".to_string()) => json_to_scheme(&argl),
+            #[cfg(feature = "serde")]
+            x if x == Exp::Symbol("scheme->json".to_string()) => scheme_to_json(&argl),
             _ => {
                 panic!(
                     "Error: primitives not implemented yet: {}",
@@ -373,6 +467,43 @@ pub mod primitives {
         }
     }
 
+    // strings and quotes are printed without their delimiters
+    #[allow(dead_code)]
+    pub fn display(args: &Exp) -> Exp {
+        let arg = car(args).unwrap();
+        match arg {
+            Exp::SchemeString(x) => print!("{}", x),
+            Exp::Quote(x) => print!("{}", x),
+            _ => print!("{}", display_exp(&arg)),
+        }
+        Exp::Quote("ok".to_string())
+    }
+
+    #[allow(dead_code)]
+    pub fn is_load_procedure(args: &Exp) -> Exp {
+        let proc = car(args).unwrap();
+        let load = scheme_list!(
+            Exp::Symbol("primitive".to_string()),
+            Exp::Symbol("load".to_string())
+        );
+        if proc == load {
+            Exp::Bool(true)
+        } else {
+            Exp::Bool(false)
+        }
+    }
+
+    // errors that are detected by the evaluator controller itself, such as an
+    // unknown expression type, abort the evaluation just like errors detected in
+    // primitives do
+    #[allow(dead_code)]
+    pub fn signal_error(args: &Exp) -> Exp {
+        match car(args).unwrap() {
+            Exp::Quote(x) => panic!("Error: {}", x),
+            x => panic!("Error: {}", exp_to_str(x)),
+        }
+    }
+
     //primitive that is used to debug evaluator controller as to print the content
     // of specific register in the process of evaluation
     #[allow(dead_code)]
@@ -628,6 +759,12 @@ pub mod primitives {
         }
     }
 
+    #[allow(dead_code)]
+    pub fn is_eof_object(args: &Exp) -> Exp {
+        let exp = car(args).unwrap();
+        Exp::Bool(exp == eof_object().object_to_exp())
+    }
+
     // It should be noted that a Exp::Bool is returned instead of a real Rust bool
     // Because this procedure is used as a primitive op for our machine, hence, a Scheme bool is
     // returned here!
@@ -713,6 +850,42 @@ pub mod primitives {
         is_tagged_list(&args)
     }
 
+    // compiled procedures, SICP 5.5.2. Like the procedures of the evaluator they
+    // are applied in the environment of the caller, so only the entry is kept:
+    // (compiled-procedure 42), where 42 is the offset of the entry label
+    #[allow(dead_code)]
+    pub fn make_compiled_procedure(args: &Exp) -> Exp {
+        let entry = match car(args).unwrap() {
+            Exp::Index(x) => Exp::Integer(x as i32),
+            x => panic!(
+                "Error: not an entry of a compiled procedure: {}",
+                exp_to_str(x)
+            ),
+        };
+        scheme_list!(Exp::Symbol("compiled-procedure".to_string()), entry)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_compiled_procedure(args: &Exp) -> Exp {
+        let p = car(args).unwrap();
+        let tag = Exp::Symbol("compiled-procedure".to_string());
+        let args = scheme_list!(p, tag);
+        is_tagged_list(&args)
+    }
+
+    // the entry is given back as a label such that (goto (reg val)) can jump to it
+    #[allow(dead_code)]
+    pub fn compiled_procedure_entry(args: &Exp) -> Exp {
+        let p = car(args).unwrap();
+        match cadr(&p).unwrap() {
+            Exp::Integer(x) => Exp::Index(x as usize),
+            x => panic!(
+                "Error: not an entry of a compiled procedure: {}",
+                exp_to_str(x)
+            ),
+        }
+    }
+
     // semantic primitives and helper procedures that has a effect on environment
     // or lookup var-val pair in environment
     #[allow(dead_code)]
@@ -867,6 +1040,71 @@ pub mod primitives {
         }
     }
 
+    // lexical addresses of compiled code, SICP exercise 5.39. An address is a
+    // list (frame offset), frame 0 being the first frame of env
+    #[allow(dead_code)]
+    pub fn lexical_address_lookup(args: &Exp) -> Exp {
+        let (frame, offset) = lexical_address(&car(args).unwrap());
+        let mut env = cadr(args).unwrap();
+        for _i in 0..frame {
+            env = cdr(&env).unwrap_or_else(|_e| no_lexical_address(frame, offset));
+        }
+        let mut vals = car(&env)
+            .and_then(|x| cdr(&x))
+            .unwrap_or_else(|_e| no_lexical_address(frame, offset));
+        for _i in 0..offset {
+            vals = cdr(&vals).unwrap_or_else(|_e| no_lexical_address(frame, offset));
+        }
+        // a scanned-out definition whose define hasn't been run, SICP exercise 5.43
+        match car(&vals).unwrap_or_else(|_e| no_lexical_address(frame, offset)) {
+            Exp::Symbol(x) if x == "*unassigned*" => panic!(
+                "Error: unassigned variable at lexical address ({} {})",
+                frame, offset
+            ),
+            x => x,
+        }
+    }
+
+    // like set-variable-value! the changed environment is returned
+    #[allow(dead_code)]
+    pub fn lexical_address_set(args: &Exp) -> Exp {
+        let (frame, offset) = lexical_address(&car(args).unwrap());
+        let val = cadr(args).unwrap();
+        let env = caddr(args).unwrap();
+        replace_nth(env, frame, &mut |x| {
+            let vals = replace_nth(frame_values(&x), offset, &mut |_y| val.clone())
+                .unwrap_or_else(|| no_lexical_address(frame, offset));
+            set_cdr(x, vals).unwrap()
+        })
+        .unwrap_or_else(|| no_lexical_address(frame, offset))
+    }
+
+    fn lexical_address(address: &Exp) -> (usize, usize) {
+        match (car(address), cadr(address)) {
+            (Ok(Exp::Integer(x)), Ok(Exp::Integer(y))) if x >= 0 && y >= 0 => {
+                (x as usize, y as usize)
+            }
+            _ => panic!("Error: bad lexical address {}", exp_to_str(address.clone())),
+        }
+    }
+
+    fn no_lexical_address(frame: usize, offset: usize) -> ! {
+        panic!("Error: nothing at lexical address ({} {})", frame, offset)
+    }
+
+    // the list with its nth item replaced by f of it, None if it is too short
+    fn replace_nth(items: Exp, n: usize, f: &mut dyn FnMut(Exp) -> Exp) -> Option<Exp> {
+        if !items.is_pair() {
+            None
+        } else if n == 0 {
+            let item = car(&items).unwrap();
+            set_car(items, f(item)).ok()
+        } else {
+            let rest = replace_nth(cdr(&items).unwrap(), n - 1, f)?;
+            set_cdr(items, rest).ok()
+        }
+    }
+
     #[allow(dead_code)]
     pub fn scan_and_set(
         vars: Exp,
@@ -897,16 +1135,15 @@ pub mod primitives {
 #[cfg(test)]
 mod test {
     use crate::{
-        append,
+        parserfordev::parser::str_to_exp,
         primitives::primitives::{
             caadr, caar, cadddr, caddr, cadr, cdadr, cdar, cdddr, cddr, define_variable,
             is_assignment, is_definition, is_primitive_procedure, is_self_evaluating,
-            is_tagged_list, lambda_body, lambda_parameters, lookup_variable_value,
-            meta_apply_primitive_procedure, multiply,
+            is_tagged_list, lambda_body, lambda_parameters, lexical_address_lookup,
+            lexical_address_set, lookup_variable_value, meta_apply_primitive_procedure, multiply,
         },
-        scheme_cons, scheme_list, str_to_exp,
-        tpfordev::type_system::Exp,
-        Pair,
+        scheme_list,
+        tpfordev::type_system::{append, scheme_cons, Exp, Pair},
     };
 
     use super::primitives::add_binding_to_frame;
@@ -1060,6 +1297,26 @@ mod test {
         assert_eq!(lookup_variable_value(&args), Exp::Integer(5));
     }
 
+    #[test]
+    fn lexical_addresses_work() {
+        let env = str_to_exp("(((a b c) 1 2 3) ((x y z) 4 5 6))".to_string());
+        let args = scheme_list!(str_to_exp("(1 2)".to_string()), env.clone());
+        assert_eq!(lexical_address_lookup(&args), Exp::Integer(6));
+        let args = scheme_list!(str_to_exp("(0 1)".to_string()), Exp::Integer(7), env);
+        let env = lexical_address_set(&args);
+        assert_eq!(
+            env,
+            str_to_exp("(((a b c) 1 7 3) ((x y z) 4 5 6))".to_string())
+        );
+        let args = scheme_list!(str_to_exp("(0 3)".to_string()), env);
+        let r = std::panic::catch_unwind(|| lexical_address_lookup(&args));
+        assert!(r.is_err());
+        let env = str_to_exp("(((a) *unassigned*))".to_string());
+        let args = scheme_list!(str_to_exp("(0 0)".to_string()), env);
+        let r = std::panic::catch_unwind(|| lexical_address_lookup(&args));
+        assert!(r.is_err());
+    }
+
     #[test]
     fn is_self_evaluating_works() {
         let mut exp = str_to_exp("(())".to_string());
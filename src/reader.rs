@@ -0,0 +1,276 @@
+pub mod reader {
+    use crate::parser::parser::{is_incomplete_input, read_scheme_forms};
+    use rustyline::error::ReadlineError;
+    use rustyline::DefaultEditor;
+    use std::collections::VecDeque;
+    use std::env;
+    use std::io::{self, BufRead, IsTerminal};
+    use std::path::PathBuf;
+
+    pub enum Line {
+        Text(String),
+        // Ctrl-C, the form typed so far is dropped
+        Interrupted,
+        // Ctrl-D or the end of a pipe
+        Eof,
+    }
+
+    // where the lines of the read-eval-print-loop come from
+    pub trait LineSource {
+        fn read_line(&mut self, prompt: &str) -> Line;
+        fn add_history(&mut self, _entry: &str) {}
+    }
+
+    // plain line reading, used when stdin is not a terminal. No prompt is shown
+    // here, the prompt-for-input op prints it as before.
+    pub struct StdinSource;
+
+    impl LineSource for StdinSource {
+        fn read_line(&mut self, _prompt: &str) -> Line {
+            let mut line = "".to_string();
+            match io::stdin().lock().read_line(&mut line) {
+                Ok(0) => Line::Eof,
+                Ok(_n) => Line::Text(line),
+                Err(e) => panic!("Error when reading input {}", e),
+            }
+        }
+    }
+
+    // lines taken from a text, for driving the read-eval-print-loop from a
+    // program
+    #[allow(dead_code)]
+    pub struct TextSource {
+        lines: VecDeque<String>,
+    }
+
+    impl TextSource {
+        #[allow(dead_code)]
+        pub fn new(text: &str) -> Self {
+            TextSource {
+                lines: text.lines().map(|x| x.to_string()).collect(),
+            }
+        }
+    }
+
+    impl LineSource for TextSource {
+        fn read_line(&mut self, _prompt: &str) -> Line {
+            match self.lines.pop_front() {
+                Some(x) => Line::Text(x),
+                None => Line::Eof,
+            }
+        }
+    }
+
+    // line editing with history, every complete form is appended to the history
+    // file right away such that nothing is lost when the machine panics
+    pub struct EditorSource {
+        editor: DefaultEditor,
+        history: Option<PathBuf>,
+    }
+
+    impl EditorSource {
+        pub fn new(history: Option<PathBuf>) -> Option<Self> {
+            let mut editor = DefaultEditor::new().ok()?;
+            if let Some(path) = &history {
+                let _r = editor.load_history(path);
+            }
+            Some(EditorSource { editor, history })
+        }
+    }
+
+    impl LineSource for EditorSource {
+        fn read_line(&mut self, prompt: &str) -> Line {
+            match self.editor.readline(prompt) {
+                Ok(line) => Line::Text(line),
+                Err(ReadlineError::Interrupted) => Line::Interrupted,
+                Err(ReadlineError::Eof) => Line::Eof,
+                Err(e) => panic!("Error when reading input {}", e),
+            }
+        }
+
+        fn add_history(&mut self, entry: &str) {
+            let _r = self.editor.add_history_entry(entry);
+            if let Some(path) = &self.history {
+                let _r = self.editor.save_history(path);
+            }
+        }
+    }
+
+    // the reader of the read op, it asks for lines until the input holds complete
+    // forms and hands them out one at a time
+    pub struct Reader {
+        source: Box<dyn LineSource>,
+        interactive: bool,
+        pending: VecDeque<String>,
+        // the input ended in the middle of a form
+        unexpected_end: bool,
+        pub prompt: String,
+        pub continuation_prompt: String,
+    }
+
+    impl Reader {
+        pub fn new(source: Box<dyn LineSource>, interactive: bool) -> Self {
+            Reader {
+                source,
+                interactive,
+                pending: VecDeque::new(),
+                unexpected_end: false,
+                prompt: "=> ".to_string(),
+                continuation_prompt: ".. ".to_string(),
+            }
+        }
+
+        pub fn plain() -> Self {
+            Reader::new(Box::new(StdinSource), false)
+        }
+
+        // line editing if stdin is a terminal, plain line reading otherwise
+        pub fn interactive(history: Option<PathBuf>) -> Self {
+            if io::stdin().is_terminal() {
+                if let Some(source) = EditorSource::new(history) {
+                    return Reader::new(Box::new(source), true);
+                }
+            }
+            Reader::plain()
+        }
+
+        pub fn is_interactive(&self) -> bool {
+            self.interactive
+        }
+
+        // a single line as it is, for the debugger
+        pub fn read_line(&mut self, prompt: &str) -> Line {
+            let line = self.source.read_line(prompt);
+            if let Line::Text(x) = &line {
+                self.source.add_history(x.trim());
+            }
+            line
+        }
+
+        // whether the input ended in the middle of a form, such as (+ 1
+        pub fn unexpected_end(&self) -> bool {
+            self.unexpected_end
+        }
+
+        // None means the end of input. A half-typed form is dropped on Ctrl-C, at
+        // the end of input it is dropped and unexpected_end tells about it.
+        pub fn read_form(&mut self) -> Option<String> {
+            while self.pending.is_empty() {
+                let mut text = "".to_string();
+                loop {
+                    let prompt = if text.is_empty() {
+                        &self.prompt
+                    } else {
+                        &self.continuation_prompt
+                    };
+                    match self.source.read_line(prompt) {
+                        Line::Text(line) => {
+                            if text.is_empty() && line.trim().is_empty() {
+                                continue;
+                            }
+                            // a REPL command such as ,trace on takes the whole line
+                            if text.is_empty() && line.trim_start().starts_with(',') {
+                                text.push_str(line.trim());
+                                break;
+                            }
+                            text.push_str(&line);
+                            text.push('\n');
+                            if !is_incomplete_input(&text) {
+                                break;
+                            }
+                        }
+                        Line::Interrupted => text.clear(),
+                        Line::Eof => {
+                            self.unexpected_end = !text.trim().is_empty();
+                            return None;
+                        }
+                    }
+                }
+                self.source.add_history(text.trim());
+                if text.starts_with(',') {
+                    self.pending.push_back(text);
+                } else {
+                    self.pending.extend(read_scheme_forms(&text));
+                }
+            }
+            self.pending.pop_front()
+        }
+    }
+
+    // $HOME/.rusmachine_history
+    pub fn default_history_file() -> Option<PathBuf> {
+        env::var_os("HOME").map(|x| PathBuf::from(x).join(".rusmachine_history"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::reader::{Line, LineSource, Reader};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    struct Script {
+        lines: VecDeque<Line>,
+        prompts: Rc<RefCell<Vec<String>>>,
+        history: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl LineSource for Script {
+        fn read_line(&mut self, prompt: &str) -> Line {
+            self.prompts.borrow_mut().push(prompt.to_string());
+            self.lines.pop_front().unwrap_or(Line::Eof)
+        }
+
+        fn add_history(&mut self, entry: &str) {
+            self.history.borrow_mut().push(entry.to_string());
+        }
+    }
+
+    fn text(s: &str) -> Line {
+        Line::Text(s.to_string())
+    }
+
+    #[test]
+    fn read_form_works() {
+        let prompts = Rc::new(RefCell::new(vec![]));
+        let history = Rc::new(RefCell::new(vec![]));
+        let script = Script {
+            lines: VecDeque::from(vec![
+                text(""),
+                text("(define (square x)"),
+                text("  (* x x))"),
+                text("(car"),
+                Line::Interrupted,
+                text("1 2 ; two forms"),
+                text("  ,trace on"),
+                text("(cdr"),
+            ]),
+            prompts: prompts.clone(),
+            history: history.clone(),
+        };
+        let mut reader = Reader::new(Box::new(script), true);
+        assert_eq!(
+            reader.read_form(),
+            Some("(define (square x)\n  (* x x))".to_string())
+        );
+        assert_eq!(reader.read_form(), Some("1".to_string()));
+        assert_eq!(reader.read_form(), Some("2".to_string()));
+        assert_eq!(reader.read_form(), Some(",trace on".to_string()));
+        assert!(!reader.unexpected_end());
+        assert_eq!(reader.read_form(), None);
+        assert!(reader.unexpected_end());
+        assert_eq!(
+            *prompts.borrow(),
+            vec!["=> ", "=> ", ".. ", "=> ", ".. ", "=> ", "=> ", "=> ", ".. "]
+        );
+        assert_eq!(
+            *history.borrow(),
+            vec![
+                "(define (square x)\n  (* x x))",
+                "1 2 ; two forms",
+                ",trace on"
+            ]
+        );
+    }
+}
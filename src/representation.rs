@@ -1,7 +1,7 @@
 pub mod type_system {
     use std::usize;
 
-    use crate::tpfordev::type_system::Exp;
+    use crate::tpfordev::type_system::{Exp, Pair};
 
     #[derive(Debug, Clone, PartialEq)]
     pub enum Object {
@@ -15,6 +15,7 @@ pub mod type_system {
         Pair(usize),        // live in memory only
         Nil,                // live in memory only
         Empty,              // live in memory only
+        Eof,                // the end of input, register only
     }
 
     impl Object {
@@ -26,9 +27,11 @@ pub mod type_system {
                 Object::Quote(x) => Exp::Quote((*x).clone()),
                 Object::LispString(x) => Exp::SchemeString((*x).clone()),
                 Object::Bool(x) => Exp::Bool(*x),
-                _ => {
-                    panic!("Error, the type can't be converted to Exp!");
-                }
+                // the list at an index is read by datum::object_to_exp, which
+                // needs the heap
+                Object::Index(x) | Object::Pair(x) => Exp::Index(*x),
+                Object::Nil | Object::Empty => Exp::List(Pair::Nil),
+                Object::Eof => Exp::Eof,
             }
         }
     }
@@ -0,0 +1,507 @@
+pub mod runner {
+    use crate::assembler::assembler::{assemble, lookup_label};
+    use crate::bytecode::bytecode::{encode, install, is_bytecode, write_bytecode, Bytecode};
+    use crate::compiler::compiler::Compiler;
+    use crate::controller_file::controller_file::{
+        assemble_controller, read_controller, read_controller_file,
+    };
+    use crate::debugger::debugger::run_debugger;
+    use crate::diagnostics::diagnostics::diagnostics_to_string;
+    use crate::evaluator::evaluator::Evaluator;
+    use crate::machine::basic_machine::{BasicMachine, Status};
+    use crate::memory::memory::Memory;
+    use crate::parser::parser::{read_scheme_forms, read_scheme_forms_with_spans};
+    use crate::parserfordev::parser::{exp_to_str, str_to_exp};
+    use crate::representation::type_system::Object;
+    use std::any::Any;
+    use std::fs;
+    use std::io::{self, Write};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::Path;
+
+    // errors in the machine are raised as panics, either by the primitives or by the
+    // signal-error op of the controller. The runners below catch them such that an
+    // error aborts the current evaluation instead of the whole process.
+
+    #[allow(dead_code)]
+    pub fn make_evaluator(memory: &mut Memory) -> BasicMachine {
+        let evaluator = Evaluator::new();
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        machine.initialize_op();
+        machine.initialize_env(memory);
+        let result = read_controller(evaluator.controller_text, evaluator.name, None)
+            .and_then(|x| assemble_controller(&x, &mut machine, memory));
+        if let Err(e) = result {
+            panic!("{}", e);
+        }
+        machine
+    }
+
+    // evaluate a single expression in the environment held by register env, the
+    // value is left in register val and returned as well. Nothing is printed except
+    // for what the expression prints itself.
+    #[allow(dead_code)]
+    pub fn eval_str(
+        exp: &str,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<Object, String> {
+        let done = lookup_label(machine, &"eval-done".to_string()).unwrap();
+        let dispatch = lookup_label(machine, &"eval-dispatch".to_string()).unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            machine.stack.initialize();
+            machine.set_register_contents_as_exp(&"exp".to_string(), exp.to_string(), memory);
+            machine.set_register_contents(&"continue".to_string(), Object::Index(done));
+            machine.set_register_contents(&"pc".to_string(), Object::Index(dispatch));
+            // watchpoints don't stop a single evaluation
+            while machine.execute(memory) != Status::Done {}
+            machine.get_register_contents(&"val".to_string()).unwrap()
+        }));
+        let _r = io::stdout().flush();
+        result.map_err(panic_message)
+    }
+
+    // compile the forms of text and add the code to the evaluator, SICP exercise
+    // 5.48, the procedures defined by it can be called by interpreted code and
+    // the other way round. The value of the last form is returned.
+    #[allow(dead_code)]
+    pub fn compile_and_go(
+        text: &str,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<Object, String> {
+        let done = lookup_label(machine, &"eval-done".to_string()).unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let forms: Vec<_> = read_scheme_forms(text)
+                .into_iter()
+                .map(str_to_exp)
+                .collect();
+            Compiler::new(machine.labels.len()).compile_program(&forms)
+        }));
+        let (entry, code) = result.map_err(panic_message)?;
+        // make-compiled-procedure takes the entry of the procedure as a label
+        let label_operands = machine.label_operands;
+        machine.label_operands = true;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            assemble(format!("({})", code.to_text()), machine, memory)
+        }));
+        machine.label_operands = label_operands;
+        match result {
+            Ok(x) => x.map_err(|e| diagnostics_to_string("compiled code", &e))?,
+            Err(e) => return Err(panic_message(e)),
+        }
+        let entry = lookup_label(machine, &entry).unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            machine.stack.initialize();
+            machine.set_register_contents(&"continue".to_string(), Object::Index(done));
+            machine.set_register_contents(&"pc".to_string(), Object::Index(entry));
+            while machine.execute(memory) != Status::Done {}
+            machine.get_register_contents(&"val".to_string()).unwrap()
+        }));
+        let _r = io::stdout().flush();
+        result.map_err(panic_message)
+    }
+
+    // evaluate every top-level form of a file in order, stopping at the first
+    // error, which is reported with the place of the form such as fact.scm:3:1
+    #[allow(dead_code)]
+    pub fn run_file(
+        path: &Path,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<(), String> {
+        let text = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) => return Err(format!("can't read {}: {}", path.display(), e)),
+        };
+        let forms = panic::catch_unwind(|| read_scheme_forms_with_spans(&text))
+            .map_err(|e| format!("{}: {}", path.display(), panic_message(e)))?;
+        let depth = machine.loading.len();
+        machine.loading.push(path.to_path_buf());
+        for (form, span) in forms {
+            if let Err(e) = eval_str(&form, machine, memory) {
+                machine.loading.truncate(depth);
+                return Err(format!("{}:{}: {}", path.display(), span, e));
+            }
+        }
+        machine.loading.truncate(depth);
+        Ok(())
+    }
+
+    // assemble a controller file into a machine that has the basic registers and
+    // ops plus the registers the controller refers to, set the initial contents of
+    // the given registers and run it from the first instruction. A value is either
+    // an atom such as 5 or a list such as (1 2).
+    #[allow(dead_code)]
+    pub fn exec_controller(
+        path: &Path,
+        registers: &[(String, String)],
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<(), String> {
+        load_controller(path, registers, machine, memory)?;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            while machine.execute(memory) != Status::Done {}
+        }));
+        let _r = io::stdout().flush();
+        result.map_err(|e| instruction_error(machine, panic_message(e)))
+    }
+
+    // the same as exec_controller but the machine is handed over to the debugger
+    // before the first instruction
+    #[allow(dead_code)]
+    pub fn debug_controller(
+        path: &Path,
+        registers: &[(String, String)],
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<bool, String> {
+        load_controller(path, registers, machine, memory)?;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            run_debugger(machine, memory, Status::Stepped)
+        }));
+        let _r = io::stdout().flush();
+        result.map_err(|e| instruction_error(machine, panic_message(e)))
+    }
+
+    // assemble the controller, or install it if it is bytecode, and set the
+    // registers, pc points to the first instruction afterwards
+    fn load_controller(
+        path: &Path,
+        registers: &[(String, String)],
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<(), String> {
+        let bytes = fs::read(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        let result = if is_bytecode(&bytes) {
+            let bytecode =
+                Bytecode::from_bytes(&bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                install(&bytecode, machine, memory)
+                    .map_err(|e| format!("{}: {}", path.display(), e))
+            }))
+        } else {
+            let source = read_controller_file(path)?;
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                assemble_controller(&source, machine, memory)
+            }))
+        };
+        match result {
+            Ok(x) => x?,
+            Err(e) => return Err(format!("{}: {}", path.display(), panic_message(e))),
+        }
+        // the registers are known once the controller has been assembled
+        for (name, _value) in registers {
+            if machine.get_register(name).is_none() {
+                return Err(format!("unknown register {}", name));
+            }
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            for (name, value) in registers {
+                machine.set_register_contents_as_exp(name, value.clone(), memory);
+            }
+            machine.set_register_contents(&"pc".to_string(), Object::Index(0));
+        }));
+        result.map_err(|e| format!("{}: {}", path.display(), panic_message(e)))
+    }
+
+    // assemble a controller file and write its bytecode to output
+    #[allow(dead_code)]
+    pub fn assemble_file(
+        path: &Path,
+        output: &Path,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<(), String> {
+        let source = read_controller_file(path)?;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            assemble_controller(&source, machine, memory)?;
+            Ok::<_, String>(encode(machine))
+        }));
+        let bytecode = match result {
+            Ok(x) => x?,
+            Err(e) => return Err(format!("{}: {}", path.display(), panic_message(e))),
+        };
+        write_bytecode(output, &bytecode)
+    }
+
+    // an error raised while running a controller, together with the instruction
+    // that raised it, pc hasn't been moved past that instruction yet
+    fn instruction_error(machine: &BasicMachine, message: String) -> String {
+        match machine.get_register_contents(&"pc".to_string()) {
+            Some(Object::Index(i)) if i < machine.raw_instructions.len() => {
+                format!("{}: {}", machine.describe_instruction(i), message)
+            }
+            _ => message,
+        }
+    }
+
+    // the contents of the named registers, a register holding an index is taken
+    // to point to a list in memory
+    #[allow(dead_code)]
+    pub fn register_dump(
+        names: &[String],
+        machine: &BasicMachine,
+        memory: &Memory,
+    ) -> Vec<(String, String)> {
+        let mut dump = vec![];
+        for name in names {
+            let value = match machine.get_register_contents(name) {
+                Some(Object::Index(_x)) => machine.get_register_contents_as_in_memory(name, memory),
+                Some(x) => exp_to_str(x.object_to_exp()),
+                None => continue,
+            };
+            dump.push((name.clone(), value));
+        }
+        dump
+    }
+
+    // run the read-eval-print-loop of the evaluator controller until the end of
+    // input. An error is reported and the loop starts over with the next form. At
+    // a breakpoint or a watchpoint the debugger takes over, quitting it drops the
+    // current evaluation. Input that ends in the middle of a form is an error.
+    #[allow(dead_code)]
+    pub fn run_repl(machine: &mut BasicMachine, memory: &mut Memory) -> Result<(), String> {
+        let repl = lookup_label(machine, &"read-eval-print-loop".to_string()).unwrap();
+        machine.set_register_contents(&"pc".to_string(), Object::Index(repl));
+        loop {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let status = machine.execute(memory);
+                status == Status::Done || run_debugger(machine, memory, status)
+            }));
+            let _r = io::stdout().flush();
+            match result {
+                Ok(true) if machine.reader.unexpected_end() => {
+                    return Err("Error: unexpected end of input".to_string())
+                }
+                Ok(true) => return Ok(()),
+                Ok(false) => {
+                    machine.set_register_contents(&"pc".to_string(), Object::Index(repl));
+                }
+                Err(e) => {
+                    eprintln!("{}", panic_message(e));
+                    machine.set_register_contents(&"pc".to_string(), Object::Index(repl));
+                }
+            }
+        }
+    }
+
+    pub fn panic_message(payload: Box<dyn Any + Send>) -> String {
+        if let Some(x) = payload.downcast_ref::<&str>() {
+            (*x).to_string()
+        } else if let Some(x) = payload.downcast_ref::<String>() {
+            x.clone()
+        } else {
+            "Error: unknown error".to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::runner::{
+        assemble_file, compile_and_go, eval_str, exec_controller, make_evaluator, run_file,
+        run_repl,
+    };
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::parserfordev::parser::str_to_exp;
+    use crate::reader::reader::{Reader, TextSource};
+    use crate::representation::type_system::Object;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn eval_str_works() {
+        let mut memory = Memory::new(5000);
+        let mut machine = make_evaluator(&mut memory);
+        let r = eval_str("(define (square x) (* x x))", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Quote("ok".to_string())));
+        let r = eval_str("(square 12)", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Integer(144)));
+        let r = eval_str("(cons 1 (cons 2 ()))", &mut machine, &mut memory);
+        assert!(matches!(r, Ok(Object::Index(_))));
+        let val = machine.get_register_contents_as_in_memory(&"val".to_string(), &memory);
+        assert_eq!(str_to_exp(val), str_to_exp("(1 2)".to_string()));
+        let r = eval_str("(cube 2)", &mut machine, &mut memory);
+        assert_eq!(r, Err("Error: unbound variable cube".to_string()));
+    }
+
+    #[test]
+    fn compiled_and_interpreted_code_call_each_other() {
+        let mut memory = Memory::new(20000);
+        let mut machine = make_evaluator(&mut memory);
+        let text = "(define (factorial n)
+                      (if (= n 1) 1 (* (factorial (- n 1)) n)))
+                    (factorial 5)";
+        let r = compile_and_go(text, &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Integer(120)));
+        let r = eval_str("(factorial 6)", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Integer(720)));
+        // a compiled procedure that calls an interpreted one, defined afterwards
+        let r = compile_and_go(
+            "(define (twice f x) (f (f x))) (define y 1) y",
+            &mut machine,
+            &mut memory,
+        );
+        assert_eq!(r, Ok(Object::Integer(1)));
+        let r = eval_str("(define (square x) (* x x))", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Quote("ok".to_string())));
+        let r = eval_str("(twice square (+ y 2))", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Integer(81)));
+        let r = compile_and_go("(twice factorial 3)", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Integer(720)));
+        let r = compile_and_go(
+            "(define (count n) (set! n (+ n 1 y)) n) (count 40)",
+            &mut machine,
+            &mut memory,
+        );
+        assert_eq!(r, Ok(Object::Integer(42)));
+        let r = compile_and_go("(car 1)", &mut machine, &mut memory);
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn internal_definitions_are_compiled() {
+        let mut memory = Memory::new(20000);
+        let mut machine = make_evaluator(&mut memory);
+        let text = "(define (f x)
+                      (define (g y) (* y y))
+                      (define z (+ x 1))
+                      (set! z (g z))
+                      (+ z x))
+                    (f 2)";
+        let r = compile_and_go(text, &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Integer(11)));
+        // the definitions stay in the frame of the call
+        let r = compile_and_go("z", &mut machine, &mut memory);
+        assert_eq!(r, Err("Error: unbound variable z".to_string()));
+        let r = compile_and_go(
+            "(define (h) (define a b) (define b 1) a) (h)",
+            &mut machine,
+            &mut memory,
+        );
+        assert_eq!(
+            r,
+            Err("Error: unassigned variable at lexical address (0 1)".to_string())
+        );
+        // a variable of the enclosing procedure is found by name
+        let r = compile_and_go(
+            "(define (k x) ((lambda (y) (+ x y)) 3)) (k 4)",
+            &mut machine,
+            &mut memory,
+        );
+        assert_eq!(r, Ok(Object::Integer(7)));
+    }
+
+    #[test]
+    fn run_file_works() {
+        let dir = env::temp_dir().join("rusmachine_run_file_works");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("main.scm"),
+            "; main file\n(load \"lib/square.scm\")\n(define y (square 5))\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lib").join("square.scm"),
+            "(define (square x) (* x x)) ; helper\n(load \"cube.scm\")",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lib").join("cube.scm"),
+            "(define (cube x) (* x (square x)))",
+        )
+        .unwrap();
+        fs::write(dir.join("bad.scm"), "(define z 1)\n(car z)\n").unwrap();
+        let mut memory = Memory::new(20000);
+        let mut machine = make_evaluator(&mut memory);
+        let r = run_file(&dir.join("main.scm"), &mut machine, &mut memory);
+        assert_eq!(r, Ok(()));
+        assert!(machine.loading.is_empty());
+        let r = eval_str("(+ y (cube 2))", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Integer(33)));
+        let r = run_file(&dir.join("bad.scm"), &mut machine, &mut memory);
+        assert!(r.unwrap_err().contains("bad.scm:2:1: "));
+        let r = run_file(&dir.join("missing.scm"), &mut machine, &mut memory);
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn exec_controller_reports_lines() {
+        let dir = env::temp_dir().join("rusmachine_exec_controller_reports_lines");
+        fs::create_dir_all(&dir).unwrap();
+        let text = "(controller\n  (assign val (reg n))\n  (goto (label done))\n  done)";
+        fs::write(dir.join("good.rm"), text).unwrap();
+        fs::write(dir.join("bad.rm"), "(controller\n  (assign val (op foo)))").unwrap();
+        fs::write(dir.join("empty.rm"), "(controller\n\n  (restore val))").unwrap();
+        let new_machine = || {
+            let mut machine = BasicMachine::new();
+            machine.initilize_registers();
+            machine.initialize_op();
+            machine
+        };
+        let mut memory = Memory::new(20);
+        let mut machine = new_machine();
+        let registers = vec![("n".to_string(), "5".to_string())];
+        let r = exec_controller(&dir.join("good.rm"), &registers, &mut machine, &mut memory);
+        assert_eq!(r, Ok(()));
+        assert_eq!(machine.spans[1].line, 3);
+        let r = exec_controller(&dir.join("bad.rm"), &[], &mut new_machine(), &mut memory);
+        let e = r.unwrap_err();
+        assert!(
+            e.ends_with("bad.rm:2:3 (controller+1): unknown operation foo: (assign val (op foo))")
+        );
+        let r = exec_controller(&dir.join("empty.rm"), &[], &mut new_machine(), &mut memory);
+        assert!(r.unwrap_err().contains("empty.rm line 3: (restore val): "));
+    }
+
+    #[test]
+    fn exec_controller_runs_bytecode() {
+        let dir = env::temp_dir().join("rusmachine_exec_controller_runs_bytecode");
+        fs::create_dir_all(&dir).unwrap();
+        let text = "(controller\n  (assign val (op *) (reg n) (const 2))\n  (assign n (op -) (reg n) (const true)))";
+        fs::write(dir.join("double.rm"), text).unwrap();
+        let new_machine = || {
+            let mut machine = BasicMachine::new();
+            machine.initilize_registers();
+            machine.initialize_op();
+            machine
+        };
+        let mut memory = Memory::new(20);
+        let (rm, rmb) = (dir.join("double.rm"), dir.join("double.rmb"));
+        assert_eq!(
+            assemble_file(&rm, &rmb, &mut new_machine(), &mut memory),
+            Ok(())
+        );
+        let mut machine = new_machine();
+        let registers = vec![("n".to_string(), "21".to_string())];
+        let r = exec_controller(&rmb, &registers, &mut machine, &mut memory);
+        // the spans come along with the bytecode
+        assert!(r
+            .unwrap_err()
+            .contains("double.rm line 3: (assign n (op -) (reg n) (const true)): "));
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Integer(42))
+        );
+    }
+
+    #[test]
+    fn run_repl_stops_at_end_of_input() {
+        let mut memory = Memory::new(5000);
+        let mut machine = make_evaluator(&mut memory);
+        machine.quiet = true;
+        machine.statistics = false;
+        let input = "(define (square x)\n  (* x x))\n(car 1)\n(define y (square 7))";
+        machine.reader = Reader::new(Box::new(TextSource::new(input)), false);
+        assert_eq!(run_repl(&mut machine, &mut memory), Ok(()));
+        let r = eval_str("y", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Integer(49)));
+        // a form left unfinished is an error, not the end of input
+        machine.reader = Reader::new(Box::new(TextSource::new("#!eof\n(+ 1")), false);
+        let r = run_repl(&mut machine, &mut memory);
+        assert_eq!(r, Err("Error: unexpected end of input".to_string()));
+    }
+}
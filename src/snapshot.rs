@@ -0,0 +1,395 @@
+pub mod snapshot {
+    use crate::bytecode::bytecode::{encode, install, Bytecode, Input, Output};
+    use crate::infrastructure::register::Register;
+    use crate::infrastructure::stack::{RegisterStatistics, Stack, StackDiscipline, StackState};
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::representation::type_system::Object;
+    use std::collections::BTreeMap;
+    use std::convert::TryInto;
+    use std::fs;
+    use std::path::Path;
+
+    // the whole state of a machine and its memory, to go on with it later. The
+    // instructions are kept as bytecode, the ops by their names only, they are
+    // bound again to the ops of the machine the snapshot is restored into. The
+    // global environment lives in the heap like any other list, env points to it.
+    // Host primitives are kept by their names as well, the machine a snapshot is
+    // restored into needs them in its primitives, e.g.
+    //   machine.primitives.insert("double".to_string(), double);
+    // Of the settings only label_operands and quiet are kept, tracing,
+    // breakpoints and the profile aren't.
+    //
+    // The file starts with MAGIC and VERSION, followed by the bytecode, the names
+    // of the ops, the names of the host primitives, the flags, the registers, the
+    // stack, the named stacks and the heap, in the numbers and strings of the
+    // bytecode format.
+
+    pub const MAGIC: &[u8; 4] = b"RMSN";
+    pub const VERSION: u8 = 2;
+
+    #[allow(dead_code)]
+    pub fn snapshot(machine: &BasicMachine, memory: &Memory) -> Vec<u8> {
+        let mut output = Output::new();
+        output.bytes.extend_from_slice(MAGIC);
+        output.u8(VERSION);
+        let bytecode = encode(machine).to_bytes();
+        output.u32(bytecode.len() as u32);
+        output.bytes.extend_from_slice(&bytecode);
+        let mut ops: Vec<&String> = machine.semantic_ops.keys().collect();
+        ops.extend(machine.machine_ops.keys());
+        ops.sort();
+        output.u32(ops.len() as u32);
+        for name in ops {
+            output.string(name);
+        }
+        let mut primitives: Vec<&String> = machine.primitives.keys().collect();
+        primitives.sort();
+        output.u32(primitives.len() as u32);
+        for name in primitives {
+            output.string(name);
+        }
+        output.u8(machine.label_operands as u8);
+        output.u8(machine.quiet as u8);
+        let registers: BTreeMap<&String, &Register> = machine.registers.iter().collect();
+        output.u32(registers.len() as u32);
+        for (name, register) in registers {
+            output.string(name);
+            output.string(&register.name);
+            output.u8(register.traced as u8);
+            write_object(&mut output, &register.contents);
+        }
+        write_stack(&mut output, &machine.stack.state());
+        let stacks: BTreeMap<&String, &Stack> = machine.stacks.iter().collect();
+        output.u32(stacks.len() as u32);
+        for (name, stack) in stacks {
+            output.string(name);
+            write_stack(&mut output, &stack.state());
+        }
+        // the cells after the last one in use are empty
+        let used = (0..memory.the_cars.len())
+            .rev()
+            .find(|i| {
+                *memory.the_cars[*i] != Object::Empty || *memory.the_cdrs[*i] != Object::Empty
+            })
+            .map_or(0, |i| i + 1);
+        output.u32(memory.the_cars.len() as u32);
+        output.u32(used as u32);
+        for i in 0..used {
+            write_object(&mut output, &memory.the_cars[i]);
+            write_object(&mut output, &memory.the_cdrs[i]);
+        }
+        output.bytes
+    }
+
+    // the machine has its ops but no instructions yet, such as a machine after
+    // initialize_op. Nothing is changed when the snapshot can't be restored.
+    #[allow(dead_code)]
+    pub fn restore(
+        bytes: &[u8],
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<(), String> {
+        if !bytes.starts_with(MAGIC) {
+            return Err("not a snapshot".to_string());
+        }
+        if !machine.raw_instructions.is_empty() {
+            return Err("the machine has instructions already".to_string());
+        }
+        let mut input = Input::new(bytes, MAGIC.len());
+        let version = input.u8()?;
+        if version != VERSION {
+            return Err(format!("snapshot version {} isn't supported", version));
+        }
+        let n = input.u32()? as usize;
+        let bytecode = Bytecode::from_bytes(input.take(n)?)?;
+        for _i in 0..input.u32()? {
+            let name = input.string()?;
+            if !machine.is_semantic_op(&name) && !machine.is_machine_op(&name) {
+                return Err(format!("unknown operation {}", name));
+            }
+        }
+        for _i in 0..input.u32()? {
+            let name = input.string()?;
+            if !machine.primitives.contains_key(&name) {
+                return Err(format!("unknown primitive {}", name));
+            }
+        }
+        let label_operands = input.u8()? != 0;
+        let quiet = input.u8()? != 0;
+        let mut registers = vec![];
+        for _i in 0..input.u32()? {
+            let key = input.string()?;
+            let name = input.string()?;
+            let traced = input.u8()? != 0;
+            let contents = read_object(&mut input)?;
+            registers.push((
+                key,
+                Register {
+                    name,
+                    contents,
+                    traced,
+                },
+            ));
+        }
+        let stack = read_stack(&mut input)?;
+        let mut stacks = vec![];
+        for _i in 0..input.u32()? {
+            let name = input.string()?;
+            stacks.push((name, read_stack(&mut input)?));
+        }
+        let size = input.u32()? as usize;
+        let used = input.u32()? as usize;
+        if used > size {
+            return Err("the heap is larger than its size".to_string());
+        }
+        let mut heap = Memory::new(size);
+        for i in 0..used {
+            *heap.the_cars[i] = read_object(&mut input)?;
+            *heap.the_cdrs[i] = read_object(&mut input)?;
+        }
+        if !input.at_end() {
+            return Err("trailing bytes after the snapshot".to_string());
+        }
+        install(&bytecode, machine, memory)?;
+        machine.label_operands = label_operands;
+        machine.quiet = quiet;
+        machine.registers.extend(registers);
+        machine.stack = Stack::from_state(stack);
+        for (name, stack) in stacks {
+            machine.stacks.insert(name, Stack::from_state(stack));
+        }
+        *memory = heap;
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn save_snapshot(
+        path: &Path,
+        machine: &BasicMachine,
+        memory: &Memory,
+    ) -> Result<(), String> {
+        fs::write(path, snapshot(machine, memory))
+            .map_err(|e| format!("can't write {}: {}", path.display(), e))
+    }
+
+    #[allow(dead_code)]
+    pub fn load_snapshot(
+        path: &Path,
+        machine: &mut BasicMachine,
+        memory: &mut Memory,
+    ) -> Result<(), String> {
+        let bytes = fs::read(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        restore(&bytes, machine, memory).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn write_object(output: &mut Output, object: &Object) {
+        match object {
+            Object::Number(x) => {
+                output.u8(0);
+                output.bytes.extend_from_slice(&x.to_le_bytes());
+            }
+            Object::Integer(x) => {
+                output.u8(1);
+                output.bytes.extend_from_slice(&x.to_le_bytes());
+            }
+            Object::Symbol(x) => {
+                output.u8(2);
+                output.string(x);
+            }
+            Object::Quote(x) => {
+                output.u8(3);
+                output.string(x);
+            }
+            Object::LispString(x) => {
+                output.u8(4);
+                output.string(x);
+            }
+            Object::Index(x) => {
+                output.u8(5);
+                output.u32(*x as u32);
+            }
+            Object::Bool(x) => {
+                output.u8(6);
+                output.u8(*x as u8);
+            }
+            Object::Pair(x) => {
+                output.u8(7);
+                output.u32(*x as u32);
+            }
+            Object::Nil => output.u8(8),
+            Object::Empty => output.u8(9),
+            Object::Eof => output.u8(10),
+        }
+    }
+
+    fn read_object(input: &mut Input) -> Result<Object, String> {
+        let object = match input.u8()? {
+            0 => Object::Number(f32::from_le_bytes(input.take(4)?.try_into().unwrap())),
+            1 => Object::Integer(i32::from_le_bytes(input.take(4)?.try_into().unwrap())),
+            2 => Object::Symbol(input.string()?),
+            3 => Object::Quote(input.string()?),
+            4 => Object::LispString(input.string()?),
+            5 => Object::Index(input.u32()? as usize),
+            6 => Object::Bool(input.u8()? != 0),
+            7 => Object::Pair(input.u32()? as usize),
+            8 => Object::Nil,
+            9 => Object::Empty,
+            10 => Object::Eof,
+            x => return Err(format!("unknown object code {}", x)),
+        };
+        Ok(object)
+    }
+
+    fn write_stack(output: &mut Output, state: &StackState) {
+        output.u32(state.capacity as u32);
+        output.u8(match state.discipline {
+            StackDiscipline::Shared => 0,
+            StackDiscipline::Tagged => 1,
+            StackDiscipline::PerRegister => 2,
+        });
+        for x in [state.push_count, state.current_depth, state.max_depth] {
+            output.u32(x as u32);
+        }
+        output.u32(state.items.len() as u32);
+        for (source, item) in state.items.iter() {
+            output.string(source);
+            write_object(output, item);
+        }
+        output.u32(state.per_register.len() as u32);
+        for (name, items) in state.per_register.iter() {
+            output.string(name);
+            output.u32(items.len() as u32);
+            for item in items {
+                write_object(output, item);
+            }
+        }
+        output.u32(state.registers.len() as u32);
+        for (name, x) in state.registers.iter() {
+            output.string(name);
+            for y in [x.pushes, x.depth, x.max_depth] {
+                output.u32(y as u32);
+            }
+        }
+    }
+
+    fn read_stack(input: &mut Input) -> Result<StackState, String> {
+        let capacity = input.u32()? as usize;
+        let discipline = match input.u8()? {
+            0 => StackDiscipline::Shared,
+            1 => StackDiscipline::Tagged,
+            2 => StackDiscipline::PerRegister,
+            x => return Err(format!("unknown stack discipline {}", x)),
+        };
+        let mut state = StackState {
+            capacity,
+            discipline,
+            push_count: input.u32()? as i32,
+            current_depth: input.u32()? as i32,
+            max_depth: input.u32()? as i32,
+            items: vec![],
+            per_register: BTreeMap::new(),
+            registers: BTreeMap::new(),
+        };
+        for _i in 0..input.u32()? {
+            let source = input.string()?;
+            state.items.push((source, read_object(input)?));
+        }
+        for _i in 0..input.u32()? {
+            let name = input.string()?;
+            let mut items = vec![];
+            for _j in 0..input.u32()? {
+                items.push(read_object(input)?);
+            }
+            state.per_register.insert(name, items);
+        }
+        for _i in 0..input.u32()? {
+            let name = input.string()?;
+            let statistics = RegisterStatistics {
+                pushes: input.u32()? as i32,
+                depth: input.u32()? as i32,
+                max_depth: input.u32()? as i32,
+            };
+            state.registers.insert(name, statistics);
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::snapshot::{restore, snapshot};
+    use crate::machine::basic_machine::BasicMachine;
+    use crate::memory::memory::Memory;
+    use crate::representation::type_system::Object;
+    use crate::runner::runner::{eval_str, make_evaluator};
+    use crate::tpfordev::type_system::{car, Exp};
+
+    fn new_machine() -> BasicMachine {
+        let mut machine = BasicMachine::new();
+        machine.initialize_op();
+        machine
+    }
+
+    #[test]
+    fn restored_machine_goes_on() {
+        let mut memory = Memory::new(5000);
+        let mut machine = make_evaluator(&mut memory);
+        let r = eval_str("(define (square x) (* x x))", &mut machine, &mut memory);
+        assert_eq!(r, Ok(Object::Quote("ok".to_string())));
+        eval_str("(define y (square 4))", &mut machine, &mut memory).unwrap();
+        let bytes = snapshot(&machine, &memory);
+        let mut restored = new_machine();
+        let mut restored_memory = Memory::new(10);
+        assert_eq!(restore(&bytes, &mut restored, &mut restored_memory), Ok(()));
+        assert_eq!(restored_memory.the_cars.len(), 5000);
+        assert_eq!(snapshot(&restored, &restored_memory), bytes);
+        let r = eval_str("(square y)", &mut restored, &mut restored_memory);
+        assert_eq!(r, Ok(Object::Integer(256)));
+        // the original machine doesn't see what is defined in the restored one
+        eval_str("(define z 1)", &mut restored, &mut restored_memory).unwrap();
+        assert!(eval_str("z", &mut machine, &mut memory).is_err());
+    }
+
+    #[test]
+    fn restore_checks_the_snapshot() {
+        let mut memory = Memory::new(5000);
+        let machine = make_evaluator(&mut memory);
+        let bytes = snapshot(&machine, &memory);
+        let mut other = Memory::new(10);
+        let r = restore(&bytes, &mut BasicMachine::new(), &mut other);
+        assert_eq!(r, Err("unknown operation *".to_string()));
+        let r = restore(&bytes[..bytes.len() - 1], &mut new_machine(), &mut other);
+        assert_eq!(r, Err("the file ends too early".to_string()));
+        let mut evaluator = make_evaluator(&mut Memory::new(5000));
+        let r = restore(&bytes, &mut evaluator, &mut other);
+        assert_eq!(r, Err("the machine has instructions already".to_string()));
+        assert_eq!(other.the_cars.len(), 10);
+    }
+
+    fn double(args: &Exp) -> Exp {
+        match car(args) {
+            Ok(Exp::Integer(x)) => Exp::Integer(2 * x),
+            _ => panic!("Error: double expects an integer"),
+        }
+    }
+
+    #[test]
+    fn host_primitives_and_flags_are_kept() {
+        let mut memory = Memory::new(5000);
+        let mut machine = make_evaluator(&mut memory);
+        machine.define_primitive("double", double, &mut memory);
+        machine.quiet = true;
+        let bytes = snapshot(&machine, &memory);
+        let mut other = Memory::new(10);
+        let r = restore(&bytes, &mut new_machine(), &mut other);
+        assert_eq!(r, Err("unknown primitive double".to_string()));
+        let mut restored = new_machine();
+        restored.primitives.insert("double".to_string(), double);
+        assert_eq!(restore(&bytes, &mut restored, &mut other), Ok(()));
+        assert!(restored.quiet);
+        assert!(!restored.label_operands);
+        let r = eval_str("(double 21)", &mut restored, &mut other);
+        assert_eq!(r, Ok(Object::Integer(42)));
+    }
+}
@@ -1,5 +1,5 @@
 pub mod type_system {
-    use crate::{parserfordev::parser::exp_to_str, representation::type_system::Object};
+    use crate::parserfordev::parser::exp_to_str;
 
     #[allow(dead_code)]
     #[derive(Debug, Clone)]
@@ -34,6 +34,8 @@ pub mod type_system {
         SchemeString(String),
         Bool(bool),
         Index(usize),
+        // the end of input, see primitives::eof_object
+        Eof,
     }
 
     impl Exp {
@@ -133,22 +135,8 @@ pub mod type_system {
                 _ => false,
             }
         }
-
-        pub fn exp_to_object(&self) -> Object {
-            match self {
-                Exp::FloatNumber(x) => Object::Number(*x),
-                Exp::Integer(x) => Object::Integer(*x),
-                Exp::Symbol(x) => Object::Symbol((*x).to_string()),
-                Exp::Quote(x) => Object::Quote((*x).to_string()),
-                Exp::Bool(x) => Object::Bool(*x),
-                Exp::Index(x) => Object::Index(*x),
-                Exp::SchemeString(x) => Object::LispString((*x).to_string()),
-                _ => panic!("Error: this type can't be converted to Object directly!"),
-            }
-        }
     }
 
-    #[macro_export]
     macro_rules! scheme_list {
     ( $( $x:expr ),* ) => {
         {
@@ -161,6 +149,7 @@ pub mod type_system {
         }
     }
 }
+    pub(crate) use scheme_list;
 
     #[allow(dead_code)]
     pub fn scheme_cons(lhs: Exp, rhs: Exp) -> Exp {
@@ -187,12 +176,19 @@ pub mod type_system {
         if !lhs.is_list() {
             panic!("Error: lhs must be a List for append {}", exp_to_str(lhs));
         }
-        let null = Exp::List(Pair::Nil);
-        if lhs == null {
-            rhs
-        } else {
-            scheme_cons(car(&lhs).unwrap(), append(cdr(&lhs).unwrap(), rhs))
+        // items of lhs are moved out instead of cloning the rest of lhs at each step,
+        // which keeps building a long list by repeated appends cheap
+        let mut items = vec![];
+        let mut temp = lhs;
+        while let Exp::List(Pair::Cons(x, y)) = temp {
+            items.push(*x);
+            temp = Exp::List(*y);
+        }
+        let mut result = rhs;
+        while let Some(x) = items.pop() {
+            result = scheme_cons(x, result);
         }
+        result
     }
 
     #[allow(dead_code)]
@@ -0,0 +1,374 @@
+pub mod trace {
+    use crate::parserfordev::parser::display_exp;
+    use crate::representation::type_system::Object;
+    use std::cell::RefCell;
+    use std::fs::File;
+    use std::io::{self, LineWriter, Write};
+    use std::path::Path;
+    use std::rc::Rc;
+
+    // a register whose content was changed by an instruction
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct RegisterChange {
+        pub register: String,
+        pub old: Object,
+        pub new: Object,
+    }
+
+    // one instruction that has been run, pc is its offset in the instruction
+    // sequence, label the nearest label before it and line the line of the
+    // controller text it is on, if known
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct TraceEvent {
+        pub pc: usize,
+        pub label: Option<String>,
+        pub line: Option<usize>,
+        pub instruction: String,
+        pub changes: Vec<RegisterChange>,
+    }
+
+    // the change of a traced register, values that live in memory are shown
+    // dereferenced
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct RegisterTrace {
+        pub register: String,
+        pub old: String,
+        pub new: String,
+    }
+
+    pub trait TraceSink {
+        fn record(&mut self, event: &TraceEvent);
+        fn record_register(&mut self, trace: &RegisterTrace);
+    }
+
+    // an index in a register points either to a list in memory or to an
+    // instruction, both are shown as @index
+    pub fn display_object(object: &Object) -> String {
+        match object {
+            Object::Index(x) => format!("@{}", x),
+            Object::Pair(x) => format!("@{}", x),
+            Object::Nil => "()".to_string(),
+            Object::Empty => "".to_string(),
+            x => display_exp(&x.object_to_exp()),
+        }
+    }
+
+    pub struct StdoutSink;
+
+    impl TraceSink for StdoutSink {
+        fn record(&mut self, event: &TraceEvent) {
+            let line = match event.line {
+                Some(x) => format!("  ; line {}", x),
+                None => "".to_string(),
+            };
+            match &event.label {
+                Some(label) => println!("{:>4} {}: {}{}", event.pc, label, event.instruction, line),
+                None => println!("{:>4} {}{}", event.pc, event.instruction, line),
+            }
+            for change in event.changes.iter() {
+                println!(
+                    "       {}: {} -> {}",
+                    change.register,
+                    display_object(&change.old),
+                    display_object(&change.new)
+                );
+            }
+        }
+
+        fn record_register(&mut self, trace: &RegisterTrace) {
+            println!(
+                "register {}: {} -> {}",
+                trace.register, trace.old, trace.new
+            );
+        }
+    }
+
+    // keeps the events in memory, the buffer is shared such that it can still be
+    // read after the sink has been handed over to a machine
+    #[allow(dead_code)]
+    #[derive(Clone)]
+    pub struct BufferSink {
+        events: Rc<RefCell<Vec<TraceEvent>>>,
+        registers: Rc<RefCell<Vec<RegisterTrace>>>,
+    }
+
+    impl Default for BufferSink {
+        fn default() -> Self {
+            BufferSink::new()
+        }
+    }
+
+    #[allow(dead_code)]
+    impl BufferSink {
+        pub fn new() -> Self {
+            BufferSink {
+                events: Rc::new(RefCell::new(vec![])),
+                registers: Rc::new(RefCell::new(vec![])),
+            }
+        }
+
+        pub fn events(&self) -> Vec<TraceEvent> {
+            self.events.borrow().clone()
+        }
+
+        pub fn registers(&self) -> Vec<RegisterTrace> {
+            self.registers.borrow().clone()
+        }
+
+        pub fn clear(&self) {
+            self.events.borrow_mut().clear();
+            self.registers.borrow_mut().clear();
+        }
+    }
+
+    impl TraceSink for BufferSink {
+        fn record(&mut self, event: &TraceEvent) {
+            self.events.borrow_mut().push(event.clone());
+        }
+
+        fn record_register(&mut self, trace: &RegisterTrace) {
+            self.registers.borrow_mut().push(trace.clone());
+        }
+    }
+
+    // one JSON object per line, each line is written out at once such that the
+    // trace is complete even if the process exits on an error, for example
+    // {"pc":3,"label":"fact-loop","line":5,"instruction":"(branch (label base-case))","changes":[]}
+    pub struct JsonLinesSink {
+        writer: LineWriter<File>,
+    }
+
+    impl JsonLinesSink {
+        pub fn create(path: &Path) -> io::Result<Self> {
+            let file = File::create(path)?;
+            Ok(JsonLinesSink {
+                writer: LineWriter::new(file),
+            })
+        }
+    }
+
+    impl TraceSink for JsonLinesSink {
+        fn record(&mut self, event: &TraceEvent) {
+            let line = event_to_json(event);
+            if let Err(e) = writeln!(self.writer, "{}", line) {
+                panic!("Error when writing trace {}", e);
+            }
+        }
+
+        // {"register":"val","old":"'unsigned","new":"(1 2)"}
+        fn record_register(&mut self, trace: &RegisterTrace) {
+            let line = format!(
+                "{{\"register\":{},\"old\":{},\"new\":{}}}",
+                json_string(&trace.register),
+                json_string(&trace.old),
+                json_string(&trace.new)
+            );
+            if let Err(e) = writeln!(self.writer, "{}", line) {
+                panic!("Error when writing trace {}", e);
+            }
+        }
+    }
+
+    pub fn event_to_json(event: &TraceEvent) -> String {
+        let label = match &event.label {
+            Some(x) => json_string(x),
+            None => "null".to_string(),
+        };
+        let line = match event.line {
+            Some(x) => x.to_string(),
+            None => "null".to_string(),
+        };
+        let changes: Vec<String> = event
+            .changes
+            .iter()
+            .map(|x| {
+                format!(
+                    "{{\"register\":{},\"old\":{},\"new\":{}}}",
+                    json_string(&x.register),
+                    json_string(&display_object(&x.old)),
+                    json_string(&display_object(&x.new))
+                )
+            })
+            .collect();
+        format!(
+            "{{\"pc\":{},\"label\":{},\"line\":{},\"instruction\":{},\"changes\":[{}]}}",
+            event.pc,
+            label,
+            line,
+            json_string(&event.instruction),
+            changes.join(",")
+        )
+    }
+
+    fn json_string(s: &str) -> String {
+        let mut r = "\"".to_string();
+        for c in s.chars() {
+            match c {
+                '"' => r.push_str("\\\""),
+                '\\' => r.push_str("\\\\"),
+                '\n' => r.push_str("\\n"),
+                '\t' => r.push_str("\\t"),
+                c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+                c => r.push(c),
+            }
+        }
+        r.push('"');
+        r
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::trace::{event_to_json, BufferSink, JsonLinesSink, RegisterChange, TraceEvent};
+    use crate::assembler::assembler::assemble;
+    use crate::machine::basic_machine::{BasicMachine, Status};
+    use crate::machine_cases::machine_case::MachineCase;
+    use crate::memory::memory::Memory;
+    use crate::representation::type_system::Object;
+    use std::env;
+    use std::fs;
+
+    fn factorial(memory: &mut Memory) -> BasicMachine {
+        let mut machine = BasicMachine::new();
+        machine.initilize_registers();
+        machine.initialize_op();
+        let text = MachineCase::test_case().controller_text.to_string();
+        assemble(text, &mut machine, memory).unwrap();
+        machine.set_register_contents(&"exp".to_string(), Object::Integer(2));
+        machine
+    }
+
+    #[test]
+    fn buffer_sink_works() {
+        let mut memory = Memory::new(20);
+        let mut machine = factorial(&mut memory);
+        let sink = BufferSink::new();
+        machine.set_trace_sink(Box::new(sink.clone()));
+        machine.trace_on();
+        machine.execute(&mut memory);
+        let events = sink.events();
+        assert_eq!(
+            events[0],
+            TraceEvent {
+                pc: 0,
+                label: Some("controller".to_string()),
+                line: Some(2),
+                instruction: "(assign continue (label fact-done))".to_string(),
+                changes: vec![RegisterChange {
+                    register: "continue".to_string(),
+                    old: Object::Quote("unsigned".to_string()),
+                    new: Object::Index(14),
+                }],
+            }
+        );
+        assert_eq!(events[1].label, Some("fact-loop".to_string()));
+        assert_eq!(events[1].changes[0].register, "flag");
+        assert_eq!(events[1].changes[0].new, Object::Bool(false));
+        let last = events.last().unwrap();
+        assert_eq!(last.label, Some("after-fact".to_string()));
+        assert_eq!(last.instruction, "(goto (reg continue))");
+        assert_eq!(events.len(), 16);
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Integer(2))
+        );
+        // nothing is recorded once tracing is off
+        sink.clear();
+        machine.trace_off();
+        machine.set_register_contents(&"pc".to_string(), Object::Index(0));
+        machine.execute(&mut memory);
+        assert!(sink.events().is_empty());
+    }
+
+    #[test]
+    fn json_lines_sink_works() {
+        let path = env::temp_dir().join("rusmachine_json_lines_sink_works.jsonl");
+        let mut memory = Memory::new(20);
+        let mut machine = factorial(&mut memory);
+        machine.set_trace_sink(Box::new(JsonLinesSink::create(&path).unwrap()));
+        machine.trace_on();
+        machine.execute(&mut memory);
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 16);
+        assert_eq!(
+            lines[0],
+            "{\"pc\":0,\"label\":\"controller\",\"line\":2,\"instruction\":\"(assign continue (label fact-done))\",\
+             \"changes\":[{\"register\":\"continue\",\"old\":\"'unsigned\",\"new\":\"@14\"}]}"
+        );
+        let event = TraceEvent {
+            pc: 1,
+            label: Some("a".to_string()),
+            line: None,
+            instruction: "(perform (op print) (const \"a\\b\"))".to_string(),
+            changes: vec![],
+        };
+        assert_eq!(
+            event_to_json(&event),
+            "{\"pc\":1,\"label\":\"a\",\"line\":null,\"instruction\":\"(perform (op print) (const \\\"a\\\\b\\\"))\",\"changes\":[]}"
+        );
+    }
+
+    #[test]
+    fn register_trace_works() {
+        let mut memory = Memory::new(20);
+        let mut machine = factorial(&mut memory);
+        machine.set_register_contents(&"exp".to_string(), Object::Integer(3));
+        let sink = BufferSink::new();
+        machine.set_trace_sink(Box::new(sink.clone()));
+        machine.trace_register_on(&"val".to_string());
+        machine.trace_register_on(&"continue".to_string());
+        machine.execute(&mut memory);
+        let val: Vec<(String, String)> = sink
+            .registers()
+            .into_iter()
+            .filter(|x| x.register == "val")
+            .map(|x| (x.old, x.new))
+            .collect();
+        let checkout = vec![
+            ("'unsigned".to_string(), "1".to_string()),
+            ("1".to_string(), "2".to_string()),
+            ("2".to_string(), "6".to_string()),
+        ];
+        assert_eq!(val, checkout);
+        let r = &sink.registers()[0];
+        assert_eq!(
+            (r.register.as_str(), r.new.as_str()),
+            ("continue", "@14 (fact-done)")
+        );
+        assert!(sink.events().is_empty());
+        // values in memory are dereferenced
+        sink.clear();
+        machine.trace_register_on(&"exp".to_string());
+        machine.set_register_contents_as_in_memory(
+            &"exp".to_string(),
+            "(1 (2))".to_string(),
+            &mut memory,
+        );
+        machine.execute(&mut memory);
+        assert_eq!(sink.registers()[0].new, "(1 (2))");
+    }
+
+    #[test]
+    fn watchpoint_works() {
+        let mut memory = Memory::new(20);
+        let mut machine = factorial(&mut memory);
+        machine.set_register_contents(&"exp".to_string(), Object::Integer(3));
+        machine.add_watchpoint(&"exp".to_string(), Box::new(|x| *x == Object::Integer(1)));
+        let status = machine.execute(&mut memory);
+        assert_eq!(
+            status,
+            Status::Watchpoint("exp".to_string(), Object::Integer(1))
+        );
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Quote("unsigned".to_string()))
+        );
+        // going on from where it paused
+        assert_eq!(machine.execute(&mut memory), Status::Done);
+        assert_eq!(
+            machine.get_register_contents(&"val".to_string()),
+            Some(Object::Integer(6))
+        );
+    }
+}
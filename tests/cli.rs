@@ -0,0 +1,58 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_machine"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn source_file(name: &str, text: &str) -> String {
+    let path = std::env::temp_dir().join(format!("machine-cli-{}.scm", name));
+    fs::write(&path, text).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn display_writes_lists() {
+    let path = source_file(
+        "display",
+        "(display '(1 (2 3)))\n(display (cons 1 (cons 2 ())))\n",
+    );
+    let output = run(&["--quiet", "run", &path]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "(1 (2 3))(1 2)");
+}
+
+#[test]
+fn piped_input_ends_the_session() {
+    let run_stdin = |args: &[&str]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_machine"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        stdin
+            .write_all(b"(define (square x) (* x x))\n(square 12)\n")
+            .unwrap();
+        // closing stdin is the end of input
+        drop(stdin);
+        child.wait_with_output().unwrap()
+    };
+    let output = run_stdin(&[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("144"));
+    // the statistics come once, at the end
+    assert_eq!(stdout.matches("Stack statistics").count(), 1);
+    let last = stdout.lines().last().unwrap();
+    assert!(last.starts_with("total-instructions = "));
+    // quiet prints only the values
+    let output = run_stdin(&["--quiet"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "'ok\n144\n");
+}